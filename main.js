@@ -1,5 +1,4 @@
-import Oscillator from './src/nes/webaudio/oscillator.js'
-import Noise from './src/nes/webaudio/noise.js'
+import Speaker from './src/nes/webaudio/speaker.js'
 import SRAM from './src/nes/ram/save_ram.js'
 
 let buf = null
@@ -16,6 +15,9 @@ const convertKeyCode = (key) => {
     case 37: return 0x40 // left anchor L
     case 39: return 0x80 // right anchor R
     case 82: return 0x0100 // R save ram
+    case 68: return 0x0200 // D switch disk side
+    case 87: return 0x0400 // W start / stop wav recording
+    case 85: return 0x0800 // U sprite limit on / off
   }
 }
 
@@ -45,26 +47,28 @@ const setupKeyHandler = () => {
 setupKeyHandler()
 
 // launch nes
-const startArrayBuf = (arrayBuf, rom) => {
-  const run = Module.cwrap('run', null, ['number', 'number'])
+const startArrayBuf = (arrayBuf, rom, patchBuf = null, biosBuf = null) => {
+  const run = Module.cwrap('run', null, ['number', 'number', 'number'])
+  const runWithPatch = Module.cwrap('run_with_patch', null, ['number', 'number', 'number', 'number', 'number'])
+  const runFds = Module.cwrap('run_fds', null, ['number', 'number', 'number', 'number', 'number'])
   const canvas = document.querySelector('canvas')
   const ctx = canvas.getContext('2d')
   if (Module.NES) {
-    Module.NES.oscs.forEach(o => o.close())
-    Module.NES.noise.close()
+    Module.NES.speaker.close()
   }
   Module.NES = {
     ctx,
     canvas,
     image: ctx.createImageData(256, 240),
-    oscs: [new Oscillator(), new Oscillator(), new Oscillator('triangle'), new Oscillator('triangle')],
-    noise: new Noise(),
+    speaker: new Speaker(44100),
     sram: new SRAM(rom),
+    disk: new SRAM(`${rom}.disk`),
   }
   canvas.width = 256
   canvas.height = 240
 
-  const nes = new Uint8Array(arrayBuf)
+  // modified disk sides are saved separately from the original image
+  const nes = biosBuf && Module.NES.disk.has() ? Module.NES.disk.load() : new Uint8Array(arrayBuf)
   // add key pad code in tail
   const size = nes.byteLength + 2
   const ptr = Module._malloc(size)
@@ -77,12 +81,28 @@ const startArrayBuf = (arrayBuf, rom) => {
   sram_buf.set(load_sram)
 
   console.log('run nes')
-  run(size, buf.byteOffset, sram_buf.byteOffset)
+  if (biosBuf) {
+    const bios = new Uint8Array(biosBuf)
+    const bios_ptr = Module._malloc(bios.byteLength)
+    new Uint8Array(Module.HEAPU8.buffer, bios_ptr, bios.byteLength).set(bios)
+    runFds(size, buf.byteOffset, sram_buf.byteOffset, bios.byteLength, bios_ptr)
+  } else if (patchBuf) {
+    // IPS / UPS / BPS patch applied on load
+    const patch = new Uint8Array(patchBuf)
+    const patch_ptr = Module._malloc(patch.byteLength)
+    new Uint8Array(Module.HEAPU8.buffer, patch_ptr, patch.byteLength).set(patch)
+    runWithPatch(size, buf.byteOffset, sram_buf.byteOffset, patch.byteLength, patch_ptr)
+  } else {
+    run(size, buf.byteOffset, sram_buf.byteOffset)
+  }
 }
 
 // called from html
-export const start = async (rom = './roms/games/ff3.nes') => {
+export const start = async (rom = './roms/games/ff3.nes', patch = null) => {
   const res = await fetch(rom);
   const arrayBuf = await res.arrayBuffer();
-  startArrayBuf(arrayBuf, rom);
+  const patchBuf = patch ? await (await fetch(patch)).arrayBuffer() : null;
+  // Famicom Disk System needs the BIOS dumped from the RAM adapter
+  const biosBuf = rom.endsWith('.fds') ? await (await fetch('./roms/disksys.rom')).arrayBuffer() : null;
+  startArrayBuf(arrayBuf, patch ? `${rom}+${patch}` : rom, patchBuf, biosBuf);
 }
\ No newline at end of file
@@ -0,0 +1,453 @@
+// Headless runner for native builds.
+// usage: nes_emulator <rom> [--frames N] [--bios disksys.rom] [--wav out.wav] [--wav-channels]
+//        [--mute pulse1,noise] [--solo triangle] [--gain dmc=0.5] [--volume 0.8]
+//        [--vgm out.vgm] [--vgm-loop FRAME] [--tracker] [--scope out.csv]
+//        [--overscan top,bottom,left,right] [--screenshot out.ppm]
+//        [--palette file.pal] [--ntsc-palette hue=0,saturation=1] [--save-palette out.pal]
+//        [--ntsc-filter composite|svideo|rgb] [--scaler xbr3x] [--region auto|ntsc|pal|dendy]
+use std::fs;
+use std::path::Path;
+
+use super::externs::native;
+use super::nes::{self, Apu, Channel, ChannelState, Context, Data, NtscPalette, NtscSetup, Overscan, Recording, Region, Scaler, CHANNELS};
+
+#[derive(Debug)]
+struct Options {
+  rom: String,
+  frames: usize,
+  bios: Option<String>,
+  wav: Option<String>,
+  wav_channels: bool,
+  muted: Vec<Channel>,
+  soloed: Vec<Channel>,
+  gains: Vec<(Channel, f32)>,
+  volume: f32,
+  vgm: Option<String>,
+  vgm_loop: Option<usize>,
+  tracker: bool,
+  scope: Option<String>,
+  overscan: Overscan,
+  screenshot: Option<String>,
+  palette: Option<String>,
+  ntsc_palette: Option<NtscPalette>,
+  save_palette: Option<String>,
+  ntsc_filter: Option<NtscSetup>,
+  scaler: Option<Scaler>,
+  region: Option<Region>, // None: from the rom
+}
+
+const SCOPE_LENGTH: usize = 2048;
+
+// "pulse1,noise"
+fn parse_channels(value: &str) -> Result<Vec<Channel>, String> {
+  value
+    .split(',')
+    .map(|name| Channel::from_name(name).ok_or(format!("unknown channel: {}", name)))
+    .collect()
+}
+
+// "dmc=0.5"
+fn parse_gain(value: &str) -> Result<(Channel, f32), String> {
+  let mut parts = value.splitn(2, '=');
+  let name = parts.next().unwrap_or("");
+  let channel = Channel::from_name(name).ok_or(format!("unknown channel: {}", name))?;
+  let gain = parts.next().and_then(|g| g.parse().ok()).ok_or(format!("invalid gain: {}", value))?;
+  Ok((channel, gain))
+}
+
+// "8,8,0,0" cuts the top and bottom 8 lines
+fn parse_overscan(value: &str) -> Result<Overscan, String> {
+  let sides = value
+    .split(',')
+    .map(|side| side.parse().map_err(|_| format!("invalid overscan: {}", value)))
+    .collect::<Result<Vec<usize>, String>>()?;
+  match sides.as_slice() {
+    [top, bottom, left, right] => Ok(Overscan { top: *top, bottom: *bottom, left: *left, right: *right }),
+    _ => Err(format!("overscan needs top,bottom,left,right: {}", value)),
+  }
+}
+
+// "auto" keeps the region of the rom
+fn parse_region(value: &str) -> Result<Option<Region>, String> {
+  match value {
+    "auto" => Ok(None),
+    "ntsc" => Ok(Some(Region::Ntsc)),
+    "pal" => Ok(Some(Region::Pal)),
+    "dendy" => Ok(Some(Region::Dendy)),
+    _ => Err(format!("unknown region: {}", value)),
+  }
+}
+
+// "hue=10,saturation=1.2", "default" for the plain one
+fn parse_ntsc_palette(value: &str) -> Result<NtscPalette, String> {
+  let mut palette = NtscPalette::default();
+  if value == "default" {
+    return Ok(palette);
+  }
+  for control in value.split(',') {
+    let mut parts = control.splitn(2, '=');
+    let name = parts.next().unwrap_or("");
+    let number = parts.next().and_then(|n| n.parse().ok()).ok_or(format!("invalid control: {}", control))?;
+    match name {
+      "hue" => palette.hue = number,
+      "saturation" => palette.saturation = number,
+      "contrast" => palette.contrast = number,
+      "brightness" => palette.brightness = number,
+      "gamma" => palette.gamma = number,
+      _ => return Err(format!("unknown control: {}", name)),
+    }
+  }
+  Ok(palette)
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+  let mut options = Options {
+    rom: String::new(),
+    frames: 60 * 60,
+    bios: None,
+    wav: None,
+    wav_channels: false,
+    muted: Vec::new(),
+    soloed: Vec::new(),
+    gains: Vec::new(),
+    volume: 1.0,
+    vgm: None,
+    vgm_loop: None,
+    tracker: false,
+    scope: None,
+    overscan: Overscan::default(),
+    screenshot: None,
+    palette: None,
+    ntsc_palette: None,
+    save_palette: None,
+    ntsc_filter: None,
+    scaler: None,
+    region: None,
+  };
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--frames" => {
+        let value = args.next().ok_or("--frames needs a number")?;
+        options.frames = value.parse().map_err(|_| format!("invalid frames: {}", value))?;
+      }
+      "--bios" => options.bios = Some(args.next().ok_or("--bios needs a path")?.clone()),
+      "--wav" => options.wav = Some(args.next().ok_or("--wav needs a path")?.clone()),
+      "--wav-channels" => options.wav_channels = true,
+      "--mute" => options.muted.extend(parse_channels(args.next().ok_or("--mute needs channels")?)?),
+      "--solo" => options.soloed.extend(parse_channels(args.next().ok_or("--solo needs channels")?)?),
+      "--gain" => options.gains.push(parse_gain(args.next().ok_or("--gain needs channel=gain")?)?),
+      "--volume" => {
+        let value = args.next().ok_or("--volume needs a number")?;
+        options.volume = value.parse().map_err(|_| format!("invalid volume: {}", value))?;
+      }
+      "--vgm" => options.vgm = Some(args.next().ok_or("--vgm needs a path")?.clone()),
+      "--vgm-loop" => {
+        let value = args.next().ok_or("--vgm-loop needs a frame")?;
+        options.vgm_loop = Some(value.parse().map_err(|_| format!("invalid frame: {}", value))?);
+      }
+      "--tracker" => options.tracker = true,
+      "--scope" => options.scope = Some(args.next().ok_or("--scope needs a path")?.clone()),
+      "--overscan" => options.overscan = parse_overscan(args.next().ok_or("--overscan needs top,bottom,left,right")?)?,
+      "--screenshot" => options.screenshot = Some(args.next().ok_or("--screenshot needs a path")?.clone()),
+      "--palette" => options.palette = Some(args.next().ok_or("--palette needs a path")?.clone()),
+      "--ntsc-palette" => {
+        options.ntsc_palette = Some(parse_ntsc_palette(args.next().ok_or("--ntsc-palette needs controls")?)?)
+      }
+      "--ntsc-filter" => {
+        let value = args.next().ok_or("--ntsc-filter needs composite, svideo or rgb")?;
+        options.ntsc_filter = Some(NtscSetup::from_name(value).ok_or(format!("unknown ntsc filter: {}", value))?);
+      }
+      "--scaler" => {
+        let value = args.next().ok_or("--scaler needs a name")?;
+        options.scaler = Some(Scaler::from_name(value).ok_or(format!("unknown scaler: {}", value))?);
+      }
+      "--region" => options.region = parse_region(args.next().ok_or("--region needs auto, ntsc, pal or dendy")?)?,
+      "--save-palette" => options.save_palette = Some(args.next().ok_or("--save-palette needs a path")?.clone()),
+      _ if arg.starts_with("--") => return Err(format!("unknown option: {}", arg)),
+      _ => options.rom = arg.clone(),
+    }
+  }
+  if options.rom.is_empty() {
+    return Err("no rom given".to_string());
+  }
+  if options.wav_channels && options.wav.is_none() {
+    return Err("--wav-channels needs --wav".to_string());
+  }
+  if options.vgm_loop.is_some() && options.vgm.is_none() {
+    return Err("--vgm-loop needs --vgm".to_string());
+  }
+  if options.palette.is_some() && options.ntsc_palette.is_some() {
+    return Err("--palette and --ntsc-palette can't be used together".to_string());
+  }
+  Ok(options)
+}
+
+// "p 0FD d2 v15 l254", "-" while silent
+fn format_channel(state: &ChannelState) -> String {
+  if state.volume == 0 {
+    return "-".to_string();
+  }
+  let duty = state.duty.map(|d| format!(" d{}", d)).unwrap_or_default();
+  format!("p {:03X}{} v{} l{}", state.period, duty, state.volume, state.length)
+}
+
+// one row a frame
+fn print_tracker_row(frame: usize, apu: &Apu) {
+  let columns: Vec<String> = CHANNELS[..5]
+    .iter()
+    .map(|channel| format!("{:<18}", format_channel(&apu.channel_state(*channel))))
+    .collect();
+  println!("{:6} | {}", frame, columns.join(" | "));
+}
+
+// the last samples of the scopes, a column per channel
+fn scope_csv(apu: &Apu) -> String {
+  let samples: Vec<Vec<f32>> = CHANNELS.iter().map(|c| apu.scope_samples(*c).unwrap_or_default()).collect();
+  let names: Vec<&str> = CHANNELS.iter().map(|c| c.name()).collect();
+  let mut csv = names.join(",") + "\n";
+  for i in 0..samples[0].len() {
+    let row: Vec<String> = samples.iter().map(|s| s[i].to_string()).collect();
+    csv += &(row.join(",") + "\n");
+  }
+  csv
+}
+
+// binary PPM of an RGBA frame
+fn encode_ppm(rgba: &[Data], width: usize, height: usize) -> Vec<u8> {
+  let mut ppm = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+  for pixel in rgba.chunks(4) {
+    ppm.extend_from_slice(&pixel[..3]);
+  }
+  ppm
+}
+
+// out.wav -> out.pulse1.wav
+fn channel_path(path: &str, channel: &str) -> String {
+  let path = Path::new(path);
+  let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("out");
+  path.with_file_name(format!("{}.{}.wav", stem, channel)).to_string_lossy().into_owned()
+}
+
+fn write_recording(path: &str, recording: &Recording) -> std::io::Result<()> {
+  fs::write(path, &recording.mix)?;
+  for (channel, wav) in recording.channels.iter() {
+    fs::write(channel_path(path, channel), wav)?;
+  }
+  Ok(())
+}
+
+pub fn main(args: &[String]) {
+  let options = match parse_args(args) {
+    Ok(options) => options,
+    Err(e) => {
+      println!("{}", e);
+      println!("usage: nes_emulator <rom> [--frames N] [--bios disksys.rom] [--wav out.wav] [--wav-channels]");
+      println!("       [--mute pulse1,noise] [--solo triangle] [--gain dmc=0.5] [--volume 0.8]");
+      println!("       [--vgm out.vgm] [--vgm-loop FRAME] [--tracker] [--scope out.csv]");
+      println!("       [--overscan top,bottom,left,right] [--screenshot out.ppm]");
+      println!("       [--palette file.pal] [--ntsc-palette hue=0,saturation=1] [--save-palette out.pal]");
+      println!("       [--ntsc-filter composite|svideo|rgb] [--scaler xbr3x] [--region auto|ntsc|pal|dendy]");
+      println!("scalers: nearest2x - 4x, scale2x, scale3x, hq2x, xbr2x - 4x");
+      println!("ntsc palette controls: hue (degrees), saturation, contrast, brightness, gamma");
+      println!("channels: pulse1, pulse2, triangle, noise, dmc, expansion");
+      return;
+    }
+  };
+  let buf = match fs::read(&options.rom) {
+    Ok(buf) => buf,
+    Err(e) => {
+      println!("Failed to read {}: {}", options.rom, e);
+      return;
+    }
+  };
+  let sram = vec![0; 0x2000];
+  let ctx = match options.bios.as_ref() {
+    Some(path) => match fs::read(path) {
+      Ok(bios) => Context::new_fds(&buf, &bios, &sram),
+      Err(e) => {
+        println!("Failed to read {}: {}", path, e);
+        return;
+      }
+    },
+    None => Context::new(&buf, &sram),
+  };
+  let mut ctx = match ctx {
+    Ok(ctx) => ctx,
+    Err(e) => {
+      println!("Failed to load rom: {}", e);
+      return;
+    }
+  };
+  ctx.set_region(options.region);
+  println!("region: {:?}", ctx.region());
+  nes::reset(&mut ctx);
+  ctx.set_overscan(options.overscan);
+  if let Some(path) = options.palette.as_ref() {
+    let loaded = fs::read(path).map_err(|e| e.to_string()).and_then(|pal| ctx.load_palette(&pal).map_err(|e| e.to_string()));
+    if let Err(e) = loaded {
+      println!("Failed to load {}: {}", path, e);
+      return;
+    }
+  }
+  if let Some(palette) = options.ntsc_palette {
+    ctx.set_ntsc_palette(palette);
+  }
+  if options.ntsc_filter.is_some() {
+    ctx.set_ntsc_filter(options.ntsc_filter, options.ntsc_palette.unwrap_or_default());
+  }
+  ctx.set_scaler(options.scaler);
+  if let Some(path) = options.save_palette.as_ref() {
+    match fs::write(path, ctx.palette_file()) {
+      Ok(()) => println!("Saved the palette to {}", path),
+      Err(e) => println!("Failed to write {}: {}", path, e),
+    }
+  }
+  let apu = ctx.apu_mut();
+  for channel in options.muted.iter() {
+    apu.set_channel_muted(*channel, true);
+  }
+  for channel in options.soloed.iter() {
+    apu.set_channel_soloed(*channel, true);
+  }
+  for (channel, gain) in options.gains.iter() {
+    apu.set_channel_gain(*channel, *gain);
+  }
+  apu.set_master_volume(options.volume);
+  if options.wav.is_some() {
+    ctx.start_recording(options.wav_channels);
+  }
+  if options.vgm.is_some() {
+    ctx.apu_mut().start_vgm_log();
+  }
+  if options.scope.is_some() {
+    ctx.apu_mut().set_scope_length(Some(SCOPE_LENGTH));
+  }
+  if options.tracker {
+    let names: Vec<String> = CHANNELS[..5].iter().map(|c| format!("{:<18}", c.name())).collect();
+    println!("{:>6} | {}", "frame", names.join(" | "));
+  }
+  for frame in 0..options.frames {
+    if options.vgm_loop == Some(frame) {
+      ctx.apu_mut().mark_vgm_loop();
+    }
+    nes::run(&mut ctx, 0, 0);
+    // nobody plays the audio here
+    native::take_samples();
+    if options.tracker {
+      print_tracker_row(frame, ctx.apu());
+    }
+  }
+  if let Some(path) = options.screenshot.as_ref() {
+    let (rgba, width, height) = ctx.frame();
+    match fs::write(path, encode_ppm(rgba, width, height)) {
+      Ok(()) => println!("Saved the last frame ({}x{}) to {}", width, height, path),
+      Err(e) => println!("Failed to write {}: {}", path, e),
+    }
+  }
+  if let Some(path) = options.scope.as_ref() {
+    match fs::write(path, scope_csv(ctx.apu())) {
+      Ok(()) => println!("Wrote the last {} samples of each channel to {}", SCOPE_LENGTH, path),
+      Err(e) => println!("Failed to write {}: {}", path, e),
+    }
+  }
+  if let (Some(path), Some(recording)) = (options.wav.as_ref(), ctx.stop_recording()) {
+    match write_recording(path, &recording) {
+      Ok(()) => println!("Recorded {} frames to {}", options.frames, path),
+      Err(e) => println!("Failed to write {}: {}", path, e),
+    }
+  }
+  if let (Some(path), Some(vgm)) = (options.vgm.as_ref(), ctx.apu_mut().stop_vgm_log()) {
+    match fs::write(path, vgm) {
+      Ok(()) => println!("Logged {} frames to {}", options.frames, path),
+      Err(e) => println!("Failed to write {}: {}", path, e),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|s| s.to_string()).collect()
+  }
+
+  #[test]
+  fn test_parse_args() {
+    let options = parse_args(&args(&["--frames", "10", "game.nes", "--wav", "out.wav", "--wav-channels"])).unwrap();
+    assert_eq!(options.rom, "game.nes");
+    assert_eq!(options.frames, 10);
+    assert_eq!(options.wav.as_deref(), Some("out.wav"));
+    assert!(options.wav_channels);
+    assert!(parse_args(&args(&["game.nes", "--wav-channels"])).is_err());
+    assert!(parse_args(&args(&["--frames", "x", "game.nes"])).is_err());
+    let options = parse_args(&args(&["game.nes", "--vgm", "out.vgm", "--vgm-loop", "120"])).unwrap();
+    assert_eq!(options.vgm.as_deref(), Some("out.vgm"));
+    assert_eq!(options.vgm_loop, Some(120));
+    assert!(parse_args(&args(&["game.nes", "--vgm-loop", "120"])).is_err());
+    assert_eq!(parse_args(&args(&["game.nes"])).unwrap().region, None);
+    assert_eq!(parse_args(&args(&["game.nes", "--region", "dendy"])).unwrap().region, Some(Region::Dendy));
+    assert_eq!(parse_region("auto"), Ok(None));
+    assert!(parse_args(&args(&["game.nes", "--region", "secam"])).is_err());
+  }
+
+  #[test]
+  fn test_parse_channel_args() {
+    let options = parse_args(&args(&["game.nes", "--mute", "pulse1,noise", "--solo", "dmc", "--gain", "triangle=0.5", "--volume", "0.8"])).unwrap();
+    assert_eq!(options.muted, vec![Channel::Pulse1, Channel::Noise]);
+    assert_eq!(options.soloed, vec![Channel::Dmc]);
+    assert_eq!(options.gains, vec![(Channel::Triangle, 0.5)]);
+    assert_eq!(options.volume, 0.8);
+    assert!(parse_args(&args(&["game.nes", "--mute", "square"])).is_err());
+    assert!(parse_args(&args(&["game.nes", "--gain", "dmc"])).is_err());
+  }
+
+  #[test]
+  fn test_parse_screen_args() {
+    let options = parse_args(&args(&["game.nes", "--overscan", "8,8,0,4", "--screenshot", "out.ppm"])).unwrap();
+    assert_eq!(options.overscan, Overscan { top: 8, bottom: 8, left: 0, right: 4 });
+    assert_eq!(options.screenshot.as_deref(), Some("out.ppm"));
+    assert!(parse_args(&args(&["game.nes", "--overscan", "8,8"])).is_err());
+    assert!(parse_args(&args(&["game.nes", "--overscan", "8,8,x,0"])).is_err());
+    let ppm = encode_ppm(&[1, 2, 3, 0xFF, 4, 5, 6, 0xFF], 2, 1);
+    assert_eq!(ppm, b"P6\n2 1\n255\n\x01\x02\x03\x04\x05\x06".to_vec());
+  }
+
+  #[test]
+  fn test_parse_palette_args() {
+    let options = parse_args(&args(&["game.nes", "--ntsc-palette", "hue=-15,gamma=1.8", "--save-palette", "out.pal"])).unwrap();
+    let palette = options.ntsc_palette.unwrap();
+    assert_eq!(palette.hue, -15.0);
+    assert_eq!(palette.gamma, 1.8);
+    assert_eq!(palette.saturation, 1.0);
+    assert_eq!(options.save_palette.as_deref(), Some("out.pal"));
+    assert_eq!(parse_ntsc_palette("default"), Ok(NtscPalette::default()));
+    assert!(parse_ntsc_palette("tint=1").is_err());
+    assert!(parse_ntsc_palette("hue").is_err());
+    assert!(parse_args(&args(&["game.nes", "--palette", "a.pal", "--ntsc-palette", "default"])).is_err());
+    let options = parse_args(&args(&["game.nes", "--ntsc-filter", "svideo"])).unwrap();
+    assert_eq!(options.ntsc_filter, Some(NtscSetup::svideo()));
+    assert!(parse_args(&args(&["game.nes", "--ntsc-filter", "vga"])).is_err());
+    let options = parse_args(&args(&["game.nes", "--scaler", "xbr4x"])).unwrap();
+    assert_eq!(options.scaler, Some(Scaler::Xbr(4)));
+    assert!(parse_args(&args(&["game.nes", "--scaler", "xbr8x"])).is_err());
+  }
+
+  #[test]
+  fn test_format_channel() {
+    let mut state = ChannelState::default();
+    assert_eq!(format_channel(&state), "-");
+    state.period = 0xFD;
+    state.duty = Some(2);
+    state.volume = 15;
+    state.length = 254;
+    assert_eq!(format_channel(&state), "p 0FD d2 v15 l254");
+  }
+
+  #[test]
+  fn test_channel_path() {
+    assert_eq!(channel_path("rec/out.wav", "pulse1"), "rec/out.pulse1.wav");
+    assert_eq!(channel_path("out", "dmc"), "out.dmc.wav");
+  }
+}
@@ -1,40 +1,32 @@
 mergeInto(LibraryManager.library, {
-  canvas_render: function (ptr, len) {
+  canvas_render: function (ptr, len, width, height) {
+    if (Module.NES.image.width !== width || Module.NES.image.height !== height) {
+      Module.NES.image = Module.NES.ctx.createImageData(width, height);
+      Module.NES.canvas.width = width;
+      Module.NES.canvas.height = height;
+    }
     Module.NES.buf = new Uint8Array(Module.HEAPU8.buffer, ptr, len);
     Module.NES.image.data.set(Module.NES.buf);
     Module.NES.ctx.putImageData(Module.NES.image, 0, 0);
   },
-  start_oscillator: function (index) {
-    Module.NES.oscs[index].start()
+  play_audio: function (ptr, len) {
+    Module.NES.speaker.push(Module.HEAP16.subarray(ptr >> 1, (ptr >> 1) + len))
   },
-  stop_oscillator: function (index) {
-    Module.NES.oscs[index].stop()
-  },
-  set_oscillator_frequency: function (index, freq) {
-    Module.NES.oscs[index].setFrequency(freq)
-  },
-  change_oscillator_frequency: function (index, freq) {
-    Module.NES.oscs[index].changeFrequency(freq)
-  },
-  set_oscillator_volume: function (index, volume) {
-    Module.NES.oscs[index].setVolume(volume)
-  },
-  set_oscillator_duty: function (index, duty) {
-    Module.NES.oscs[index].setDuty(duty)
-  },
-  set_noise_frequency: function(freq) {
-    Module.NES.noise.setFrequency(freq)
-  },
-  set_noise_volume: function (volume) {
-    Module.NES.noise.setVolume(volume)
-  },
-  start_noise: function () {
-    Module.NES.noise.start()
-  },
-  stop_noise: function () {
-    Module.NES.noise.stop()
+  get_sample_rate: function () {
+    return Module.NES.speaker.sampleRate()
   },
   save_sram: function(ptr, len) {
     Module.NES.sram.save(new Uint8Array(Module.HEAPU8.buffer, ptr, len))
+  },
+  save_disk: function(ptr, len) {
+    Module.NES.disk.save(new Uint8Array(Module.HEAPU8.buffer, ptr, len))
+  },
+  save_wav: function(ptr, len) {
+    const blob = new Blob([Module.HEAPU8.slice(ptr, ptr + len)], { type: 'audio/wav' })
+    const a = document.createElement('a')
+    a.href = URL.createObjectURL(blob)
+    a.download = 'nes.wav'
+    a.click()
+    URL.revokeObjectURL(a.href)
   }
 });
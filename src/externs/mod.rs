@@ -3,6 +3,9 @@ use std::ffi::CString;
 use std::ptr::null_mut;
 use std::os::raw::{c_int, c_void, c_uchar};
 
+#[cfg(not(target_os = "emscripten"))]
+pub mod native;
+
 #[allow(non_camel_case_types)]
 type em_callback_func = unsafe extern "C" fn();
 
@@ -29,13 +32,14 @@ pub fn cancel_main_loop() {
     }
 }
 
-pub fn set_main_loop_callback<F>(callback: F)
+// fps 0 follows the display refresh
+pub fn set_main_loop_callback<F>(callback: F, fps: c_int)
     where F: FnMut()
 {
     MAIN_LOOP_CALLBACK.with(|log| { *log.borrow_mut() = &callback as *const _ as *mut c_void; });
 
     unsafe {
-        emscripten_set_main_loop(wrapper::<F>, 0, 1);
+        emscripten_set_main_loop(wrapper::<F>, fps, 1);
     }
 }
 
@@ -0,0 +1,35 @@
+// The host functions lib.js provides in the browser, for native builds and tests.
+// Nothing is drawn or saved, the audio is kept until it is taken.
+use std::cell::RefCell;
+
+thread_local!(static SAMPLES: RefCell<Vec<i16>> = const { RefCell::new(Vec::new()) });
+
+// the samples played since the last call
+pub fn take_samples() -> Vec<i16> {
+  SAMPLES.with(|samples| samples.replace(Vec::new()))
+}
+
+#[no_mangle]
+pub extern "C" fn canvas_render(_ptr: *const u8, _len: usize, _width: usize, _height: usize) {}
+
+#[no_mangle]
+pub extern "C" fn save_sram(_ptr: *const u8, _len: usize) {}
+
+#[no_mangle]
+pub extern "C" fn save_disk(_ptr: *const u8, _len: usize) {}
+
+#[no_mangle]
+pub extern "C" fn save_wav(_ptr: *const u8, _len: usize) {}
+
+/// # Safety
+/// ptr must point to len samples.
+#[no_mangle]
+pub unsafe extern "C" fn play_audio(ptr: *const i16, len: usize) {
+  let buf = std::slice::from_raw_parts(ptr, len);
+  SAMPLES.with(|samples| samples.borrow_mut().extend_from_slice(buf));
+}
+
+#[no_mangle]
+pub extern "C" fn get_sample_rate() -> f64 {
+  44100.0
+}
@@ -3,24 +3,80 @@ extern crate lazy_static;
 
 mod nes;
 mod externs;
+#[cfg(not(target_os = "emscripten"))]
+mod cli;
 
-use nes::Context;
+use nes::{Context, Region, RomError};
 use std::string::String;
 
 fn main() {
+  #[cfg(not(target_os = "emscripten"))]
+  {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+      cli::main(&args);
+    }
+  }
 }
 
 #[no_mangle]
 pub fn run(len: usize, ptr: *mut u8, sram: *mut u8) {
   let buf: &mut [u8] = unsafe{ std::slice::from_raw_parts_mut(ptr, len) };
   let s: &mut [u8] = unsafe { std::slice::from_raw_parts_mut(sram, 0x2000)};
-  let mut ctx = Context::new(buf, s);
+  // the last 2 bytes of buf are reserved for key pad input
+  let ctx = Context::new(&buf[..len - 2], s);
+  start(ctx, buf);
+}
+
+/// # Safety
+/// ptr must point to len bytes, sram to 0x2000 bytes and patch_ptr to patch_len bytes.
+#[no_mangle]
+pub unsafe fn run_with_patch(len: usize, ptr: *mut u8, sram: *mut u8, patch_len: usize, patch_ptr: *const u8) {
+  let buf: &mut [u8] = std::slice::from_raw_parts_mut(ptr, len);
+  let s: &mut [u8] = std::slice::from_raw_parts_mut(sram, 0x2000);
+  let patch: &[u8] = std::slice::from_raw_parts(patch_ptr, patch_len);
+  let ctx = Context::new_with_patch(&buf[..len - 2], patch, s);
+  start(ctx, buf);
+}
+
+// Famicom Disk System: ptr is the *.fds image, bios is disksys.rom
+/// # Safety
+/// ptr must point to len bytes, sram to 0x2000 bytes and bios_ptr to bios_len bytes.
+#[no_mangle]
+pub unsafe fn run_fds(len: usize, ptr: *mut u8, sram: *mut u8, bios_len: usize, bios_ptr: *const u8) {
+  let buf: &mut [u8] = std::slice::from_raw_parts_mut(ptr, len);
+  let s: &mut [u8] = std::slice::from_raw_parts_mut(sram, 0x2000);
+  let bios: &[u8] = std::slice::from_raw_parts(bios_ptr, bios_len);
+  let ctx = Context::new_fds(&buf[..len - 2], bios, s);
+  start(ctx, buf);
+}
+
+fn start(ctx: Result<Context, RomError>, buf: &mut [u8]) {
+  let len = buf.len();
+  let mut ctx = match ctx {
+    Ok(ctx) => ctx,
+    Err(e) => {
+      println!("Failed to load rom: {}", e);
+      return;
+    }
+  };
+  if let Some(m) = ctx.database_match() {
+    println!("{}", m);
+  }
   nes::reset(&mut ctx);
+  if let (Some(nsf), Some(track)) = (ctx.nsf_info(), ctx.nsf_track()) {
+    println!("{} - {} ({}), track {}/{}", nsf.title, nsf.artist, nsf.copyright, track + 1, nsf.total_songs);
+    if nsf.unsupported_chips() != 0 {
+      println!("unsupported expansion audio (${:02X}), those channels are silent", nsf.unsupported_chips());
+    }
+  }
+  // PAL and Dendy run at 50 frames a second
+  let fps = if ctx.region() == Region::Ntsc { 0 } else { 50 };
   externs::cancel_main_loop();
   let main_loop = || {
     let key_state = buf[len -1];
     let debug_input = buf[len -2];
     nes::run(&mut ctx, key_state, debug_input);
   };
-  externs::set_main_loop_callback(main_loop);
+  externs::set_main_loop_callback(main_loop, fps);
 }
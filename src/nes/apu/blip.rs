@@ -0,0 +1,141 @@
+// Band-limited step synthesis, the idea of blip_buf by Shay Green.
+// Level changes are added as windowed sinc impulses at the output rate and
+// integrated when samples are read, so a change between two output samples
+// does not alias.
+use std::f64::consts::PI;
+
+const PHASES: usize = 32;
+const TAPS: usize = 16;
+// a bit under the nyquist frequency of the output rate
+const CUTOFF: f64 = 0.9;
+
+#[derive(Debug)]
+pub struct BlipBuffer {
+  clock_rate: f64,
+  sample_rate: f64,
+  kernel: Vec<[f32; TAPS]>,
+  buffer: Vec<f32>,
+  offset: f64, // output samples from the head of buffer to clock 0 of this frame
+  integrator: f32,
+}
+
+impl BlipBuffer {
+  pub fn new(clock_rate: f64, sample_rate: f64) -> Self {
+    BlipBuffer {
+      clock_rate,
+      sample_rate,
+      kernel: build_kernel(),
+      buffer: vec![0.0; TAPS],
+      offset: 0.0,
+      integrator: 0.0,
+    }
+  }
+
+  // the rate may change between frames for dynamic rate control
+  pub fn set_sample_rate(&mut self, sample_rate: f64) {
+    self.sample_rate = sample_rate;
+  }
+
+  // the cpu clock of the region
+  pub fn set_clock_rate(&mut self, clock_rate: f64) {
+    self.clock_rate = clock_rate;
+  }
+
+  pub fn sample_rate(&self) -> f64 {
+    self.sample_rate
+  }
+
+  // the level changed by delta at clock (from the start of this frame)
+  pub fn add_delta(&mut self, clock: u32, delta: f32) {
+    let pos = self.offset + clock as f64 * self.sample_rate / self.clock_rate;
+    let index = pos as usize;
+    let phase = ((pos - index as f64) * PHASES as f64) as usize;
+    if self.buffer.len() < index + TAPS {
+      self.buffer.resize(index + TAPS, 0.0);
+    }
+    for (sample, k) in self.buffer[index..index + TAPS].iter_mut().zip(self.kernel[phase].iter()) {
+      *sample += delta * k;
+    }
+  }
+
+  // finish the frame of clocks, the samples before it can be read
+  pub fn end_frame(&mut self, clocks: u32) {
+    self.offset += clocks as f64 * self.sample_rate / self.clock_rate;
+    let len = self.offset as usize + TAPS;
+    if self.buffer.len() < len {
+      self.buffer.resize(len, 0.0);
+    }
+  }
+
+  pub fn samples_avail(&self) -> usize {
+    self.offset as usize
+  }
+
+  // read all the finished samples, the levels are the same scale as the deltas
+  pub fn read_samples(&mut self, out: &mut Vec<f32>) {
+    let count = self.samples_avail();
+    for delta in self.buffer.iter().take(count) {
+      self.integrator += *delta;
+      out.push(self.integrator);
+    }
+    self.buffer.drain(..count);
+    if self.buffer.len() < TAPS {
+      self.buffer.resize(TAPS, 0.0);
+    }
+    self.offset -= count as f64;
+  }
+}
+
+// windowed sinc impulses, each phase sums to 1.0
+fn build_kernel() -> Vec<[f32; TAPS]> {
+  (0..PHASES)
+    .map(|phase| {
+      let frac = phase as f64 / PHASES as f64;
+      let mut taps = [0.0; TAPS];
+      for (k, tap) in taps.iter_mut().enumerate() {
+        let t = k as f64 - (TAPS / 2 - 1) as f64 - frac;
+        let x = t * CUTOFF;
+        let sinc = if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+        // blackman window over the kernel width
+        let w = (t + TAPS as f64 / 2.0) / TAPS as f64;
+        let window = 0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos();
+        *tap = (sinc * window) as f32;
+      }
+      let sum: f32 = taps.iter().sum();
+      for tap in taps.iter_mut() {
+        *tap /= sum;
+      }
+      taps
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_step_settles() {
+    let mut blip = BlipBuffer::new(1789773.0, 44100.0);
+    blip.add_delta(1000, 0.5);
+    blip.end_frame(29780);
+    let mut out = vec![];
+    blip.read_samples(&mut out);
+    assert_eq!(out.len(), 733);
+    assert_eq!(out[0], 0.0);
+    assert!((out[out.len() - 1] - 0.5).abs() < 0.0001);
+  }
+
+  #[test]
+  fn test_sample_rate() {
+    let mut blip = BlipBuffer::new(1789773.0, 48000.0);
+    blip.end_frame(29780);
+    let mut out = vec![];
+    blip.read_samples(&mut out);
+    assert_eq!(out.len(), 798);
+    blip.set_sample_rate(96000.0);
+    blip.end_frame(29780);
+    blip.read_samples(&mut out);
+    assert_eq!(out.len(), 798 + 1598);
+  }
+}
@@ -0,0 +1,140 @@
+// the sound sources going into the mixer, expansion is the sum of the cartridge audio
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Channel {
+  Pulse1,
+  Pulse2,
+  Triangle,
+  Noise,
+  Dmc,
+  Expansion,
+}
+
+pub const CHANNELS: [Channel; 6] = [
+  Channel::Pulse1,
+  Channel::Pulse2,
+  Channel::Triangle,
+  Channel::Noise,
+  Channel::Dmc,
+  Channel::Expansion,
+];
+
+impl Channel {
+  pub fn index(self) -> usize {
+    self as usize
+  }
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Channel::Pulse1 => "pulse1",
+      Channel::Pulse2 => "pulse2",
+      Channel::Triangle => "triangle",
+      Channel::Noise => "noise",
+      Channel::Dmc => "dmc",
+      Channel::Expansion => "expansion",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Self> {
+    CHANNELS.iter().copied().find(|c| c.name() == name)
+  }
+}
+
+// $4000 / $400C, the volume when constant, otherwise the decay level
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EnvelopeState {
+  pub is_constant: bool,
+  pub is_loop: bool,
+  pub period: u8, // also the constant volume
+  pub decay: u8,
+}
+
+// a snapshot of a channel for visualizers
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ChannelState {
+  pub is_enabled: bool, // $4015
+  pub period: u16,      // timer period, the dmc rate in cpu cycles
+  pub duty: Option<u8>, // pulse only, 0 - 3
+  // pulse / noise: 0 - 15 from the envelope, 0 when silenced
+  // triangle: 15 while the sequencer runs, dmc: 0 - 127 output level
+  pub volume: u8,
+  pub length: u16, // length counter, the bytes left for dmc
+  pub envelope: Option<EnvelopeState>,
+}
+
+// user controls of each channel, resolved into the gains the mixer multiplies
+#[derive(Debug)]
+pub struct ChannelGains {
+  gains: [f32; 6],
+  muted: [bool; 6],
+  soloed: [bool; 6],
+  effective: [f32; 6],
+}
+
+impl ChannelGains {
+  pub fn new() -> Self {
+    ChannelGains {
+      gains: [1.0; 6],
+      muted: [false; 6],
+      soloed: [false; 6],
+      effective: [1.0; 6],
+    }
+  }
+
+  pub fn set_gain(&mut self, channel: Channel, gain: f32) {
+    self.gains[channel.index()] = gain.max(0.0);
+    self.update();
+  }
+
+  pub fn set_muted(&mut self, channel: Channel, is_muted: bool) {
+    self.muted[channel.index()] = is_muted;
+    self.update();
+  }
+
+  // while any channel is soloed, only the soloed ones are heard
+  pub fn set_soloed(&mut self, channel: Channel, is_soloed: bool) {
+    self.soloed[channel.index()] = is_soloed;
+    self.update();
+  }
+
+  pub fn gain(&self, channel: Channel) -> f32 {
+    self.effective[channel.index()]
+  }
+
+  pub fn is_unity(&self) -> bool {
+    self.effective.iter().all(|&g| g == 1.0)
+  }
+
+  fn update(&mut self) {
+    let has_solo = self.soloed.iter().any(|&s| s);
+    for i in 0..self.gains.len() {
+      let is_heard = !self.muted[i] && (!has_solo || self.soloed[i]);
+      self.effective[i] = if is_heard { self.gains[i] } else { 0.0 };
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_mute_and_solo() {
+    let mut gains = ChannelGains::new();
+    assert!(gains.is_unity());
+    gains.set_gain(Channel::Noise, 0.5);
+    gains.set_muted(Channel::Pulse1, true);
+    assert_eq!(gains.gain(Channel::Pulse1), 0.0);
+    assert_eq!(gains.gain(Channel::Noise), 0.5);
+    gains.set_soloed(Channel::Noise, true);
+    gains.set_soloed(Channel::Pulse1, true);
+    // mute wins over solo
+    assert_eq!(gains.gain(Channel::Pulse1), 0.0);
+    assert_eq!(gains.gain(Channel::Pulse2), 0.0);
+    assert_eq!(gains.gain(Channel::Noise), 0.5);
+    gains.set_soloed(Channel::Noise, false);
+    gains.set_soloed(Channel::Pulse1, false);
+    assert_eq!(gains.gain(Channel::Triangle), 1.0);
+    assert_eq!(Channel::from_name("dmc"), Some(Channel::Dmc));
+    assert_eq!(Channel::from_name("fds"), None);
+  }
+}
@@ -1,6 +1,13 @@
 pub const CPU_CLOCK: usize = 1789772;
+pub const PAL_CPU_CLOCK: usize = 1662607;
+pub const DENDY_CPU_CLOCK: usize = 1773448;
 
-pub const DIVIDE_COUNT_FOR_240HZ: u16 = 7457;
+// cpu cycles of the frame counter steps
+// ref. http://wiki.nesdev.com/w/index.php/APU_Frame_Counter
+pub const NTSC_FRAME_STEPS: [u32; 5] = [7457, 14913, 22371, 29829, 37281];
+pub const PAL_FRAME_STEPS: [u32; 5] = [8313, 16627, 24939, 33253, 41565];
+
+pub const SAMPLE_RATE: f64 = 44100.0;
 
 // ref. http://pgate1.at-ninja.jp/NES_on_FPGA/nes_apu.htm
 pub const COUNTER_TABLE: &'static [u8] = &[0x0A, 0xFE, 0x14, 0x02, 0x28, 0x04, 0x50, 0x06, 0xA0,
@@ -8,12 +15,61 @@ pub const COUNTER_TABLE: &'static [u8] = &[0x0A, 0xFE, 0x14, 0x02, 0x28, 0x04, 0
                                            0x18, 0x12, 0x30, 0x14, 0x60, 0x16, 0xC0, 0x18, 0x48,
                                            0x1A, 0x10, 0x1C, 0x20, 0x1E];
 
-pub const GROBAL_GAIN: f32 = 10.0;
+// ref. http://wiki.nesdev.com/w/index.php/APU_Pulse
+pub const SQUARE_DUTY_TABLE: [[u8; 8]; 4] = [[0, 1, 0, 0, 0, 0, 0, 0],
+                                             [0, 1, 1, 0, 0, 0, 0, 0],
+                                             [0, 1, 1, 1, 1, 0, 0, 0],
+                                             [1, 0, 0, 1, 1, 1, 1, 1]];
+
+// ref. http://wiki.nesdev.com/w/index.php/APU_Triangle
+pub const TRIANGLE_SEQUENCE: [u8; 32] = [15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+                                         0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
 
 // ref. http://wiki.nesdev.com/w/index.php/APU_Noise
 pub const NOISE_TIMER_PERIOD_TABLE: &'static [u16] = &[0x004, 0x008, 0x010, 0x020, 0x040, 0x060,
                                                       0x080, 0x0A0, 0x0CA, 0x0FE, 0x17C, 0x1FC,
                                                       0x2FA, 0x3F8, 0x7F2, 0xFE4];
 
+pub const NOISE_PAL_TIMER_PERIOD_TABLE: &[u16] = &[0x004, 0x008, 0x00E, 0x01E, 0x03C, 0x058,
+                                                   0x076, 0x094, 0x0BC, 0x0EC, 0x162, 0x1D8,
+                                                   0x2C4, 0x3B0, 0x762, 0xEC2];
+
 pub const DMC_NTSC_TABLE: &'static [u16] = &[0x1AC, 0x17C, 0x154, 0x140, 0x11E, 0x0FE, 0x0E2, 0x0D6,
-                                             0x0BE, 0x0A0, 0x08E, 0x080, 0x06A, 0x054, 0x048, 0x036];
\ No newline at end of file
+                                             0x0BE, 0x0A0, 0x08E, 0x080, 0x06A, 0x054, 0x048, 0x036];
+
+pub const DMC_PAL_TABLE: &[u16] = &[0x18E, 0x162, 0x13C, 0x12A, 0x114, 0x0EC, 0x0D2, 0x0C6,
+                                    0x0B0, 0x094, 0x084, 0x076, 0x062, 0x04E, 0x042, 0x032];
+
+// the clocks of the apu on each console, Dendy keeps the NTSC tables on a slower cpu
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ApuTiming {
+  pub cpu_clock: usize,
+  pub frame_rate: u32,
+  pub frame_steps: [u32; 5],
+  pub noise_periods: &'static [u16],
+  pub dmc_periods: &'static [u16],
+}
+
+pub const NTSC_TIMING: ApuTiming = ApuTiming {
+  cpu_clock: CPU_CLOCK,
+  frame_rate: 60,
+  frame_steps: NTSC_FRAME_STEPS,
+  noise_periods: NOISE_TIMER_PERIOD_TABLE,
+  dmc_periods: DMC_NTSC_TABLE,
+};
+
+pub const PAL_TIMING: ApuTiming = ApuTiming {
+  cpu_clock: PAL_CPU_CLOCK,
+  frame_rate: 50,
+  frame_steps: PAL_FRAME_STEPS,
+  noise_periods: NOISE_PAL_TIMER_PERIOD_TABLE,
+  dmc_periods: DMC_PAL_TABLE,
+};
+
+pub const DENDY_TIMING: ApuTiming = ApuTiming {
+  cpu_clock: DENDY_CPU_CLOCK,
+  frame_rate: 50,
+  frame_steps: NTSC_FRAME_STEPS,
+  noise_periods: NOISE_TIMER_PERIOD_TABLE,
+  dmc_periods: DMC_NTSC_TABLE,
+};
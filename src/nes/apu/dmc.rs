@@ -1,66 +1,79 @@
 use super::constants::*;
+use super::channel::ChannelState;
 use super::super::types::{Data, Addr};
 use super::Mapper;
 use super::Ram;
 use super::Rom;
 
+// ref. http://wiki.nesdev.com/w/index.php/APU_DMC
 #[derive(Debug)]
 pub struct DMC {
-  index: usize,
+  // $4010
   is_irq_enabled: bool,
   is_loop: bool,
   tick_period: u16,
+  period_index: usize,
+  periods: &'static [u16], // of the region
+  // $4011
   volume: Data,
+  // $4012, $4013
   sample_address: Addr,
   sample_length: Addr,
 
+  // output unit
   tick_value: u16,
-  current_address: Addr,
-  current_length: Addr,
   bit_count: u8,
   shift_register: Data,
-  is_enabled: bool,
-  is_playing: bool,
-}
-
-// sham emulation
-extern "C" {
-  fn set_oscillator_frequency(index: usize, freq: usize);
-  fn change_oscillator_frequency(index: usize, freq: usize);
-  fn set_oscillator_volume(index: usize, volume: f32);
-  fn start_oscillator(index: usize);
-  fn stop_oscillator(index: usize);
+  is_silence: bool,
+  // memory reader
+  sample_buffer: Option<Data>,
+  current_address: Addr,
+  current_length: Addr,
+  is_interrupted: bool,
+  is_restarted: bool, // for the vgm log
 }
 
 impl DMC {
-  pub fn new(index: usize) -> Self {
+  pub fn new() -> Self {
     DMC {
-      index,
       is_irq_enabled: false,
       is_loop: false,
-      tick_period: 0x0,
+      tick_period: DMC_NTSC_TABLE[0],
+      period_index: 0,
+      periods: DMC_NTSC_TABLE,
       volume: 0x0,
-      sample_address: 0x0,
-      sample_length: 0x0,
+      sample_address: 0xC000,
+      sample_length: 0x1,
       tick_value: 0x0,
-      current_address: 0x0,
-      current_length: 0x0,
-      bit_count: 0x0,
+      bit_count: 0x8,
       shift_register: 0x0,
-      is_enabled: false,
-      is_playing: false,
+      is_silence: true,
+      sample_buffer: None,
+      current_address: 0xC000,
+      current_length: 0x0,
+      is_interrupted: false,
+      is_restarted: false,
     }
   }
 
+  // the rate of the last $4010 write follows the table
+  pub fn set_periods(&mut self, periods: &'static [u16]) {
+    self.periods = periods;
+    self.tick_period = periods[self.period_index];
+  }
+
   pub fn write(&mut self, addr: Addr, data: Data) {
     match addr {
       0x00 => { // 0x4010
         self.is_irq_enabled = data & 0x80 == 0x80;
+        if !self.is_irq_enabled {
+          self.is_interrupted = false;
+        }
         self.is_loop = data & 0x40 == 0x40;
-        self.tick_period = DMC_NTSC_TABLE[(data & 0x0F) as usize];
-        self.set_frequency();
+        self.period_index = (data & 0x0F) as usize;
+        self.tick_period = self.periods[self.period_index];
       }
-      0x01 => { // 0x4011
+      0x01 => { // 0x4011, direct load used for PCM playback
         self.volume = data & 0x7F;
       }
       0x02 => { // 0x4012
@@ -75,104 +88,167 @@ impl DMC {
     }
   }
 
+  // $4015 bit 4, also clears the interrupt
   pub fn enable(&mut self) {
-    self.is_enabled = true;
-    unsafe {
-      start_oscillator(self.index);
+    self.is_interrupted = false;
+    if self.current_length == 0 {
+      self.restart();
     }
-    self.is_playing = true;
   }
 
   pub fn disable(&mut self) {
-    self.is_enabled = false;
-    unsafe {
-      stop_oscillator(self.index);
-    }
-    self.is_playing = false;
+    self.is_interrupted = false;
+    self.current_length = 0;
   }
 
-  pub fn step_timer(&mut self, mapper: &mut dyn Mapper, sram: &Ram, prg_rom: &Rom, stall: &mut u8) {
-    if !self.is_enabled {
-      return
-    }
-    self.step_reader(mapper, sram, prg_rom, stall);
+  pub fn is_interrupted(&self) -> bool {
+    self.is_interrupted
+  }
+
+  // called every cpu cycle, returns true when the reader took the bus
+  pub fn step_timer(&mut self, mapper: &mut dyn Mapper, sram: &Ram, prg_rom: &Rom) -> bool {
+    let is_fetched = self.step_reader(mapper, sram, prg_rom);
     if self.tick_value == 0 {
-      self.tick_value = self.tick_period;
+      self.tick_value = self.tick_period - 1;
       self.step_shifter();
     } else {
       self.tick_value -= 1;
     }
+    is_fetched
   }
 
-  fn set_frequency(&mut self) {
-    let freq = CPU_CLOCK / self.tick_period as usize;
-    if !self.is_playing {
-      unsafe {
-        set_oscillator_frequency(self.index, freq);
-      }
-      self.is_playing = true;
-    } else {
-      self.update_frequency(freq);
+  // fill the sample buffer through the mapper, so bank switched samples work
+  fn step_reader(&mut self, mapper: &mut dyn Mapper, sram: &Ram, prg_rom: &Rom) -> bool {
+    if self.sample_buffer.is_some() || self.current_length == 0 {
+      return false;
     }
-  }
-
-  fn update_frequency(&self, freq: usize) {
-    unsafe {
-      change_oscillator_frequency(self.index, freq);
-    }
-  }
-
-  pub fn step_reader(&mut self, mapper: &mut dyn Mapper, sram: &Ram, prg_rom: &Rom, stall: &mut u8) {
-    if self.current_length > 0 && self.bit_count == 0 {
-      *stall += 4;
-      self.shift_register = mapper.read(self.current_address - 0x8000,prg_rom, sram); // Does here access to only prg rom?
-      self.bit_count = 8;
-      self.current_address += 1;
-      if self.current_address == 0 {
-        self.current_address = 0x8000;
-      }
-      self.current_length -= 1;
-      if self.current_length == 0 && self.is_loop {
+    self.sample_buffer = Some(mapper.read(self.current_address, prg_rom, sram));
+    self.current_address = if self.current_address == 0xFFFF {
+      0x8000
+    } else {
+      self.current_address + 1
+    };
+    self.current_length -= 1;
+    if self.current_length == 0 {
+      if self.is_loop {
         self.restart();
+      } else if self.is_irq_enabled {
+        self.is_interrupted = true;
       }
     }
+    true
   }
 
-  pub fn step_shifter(&mut self) {
-    if self.bit_count == 0 {
-      return
-    }
-    if self.shift_register & 0x1 == 0x1 {
-      if self.volume <= 125 {
-        self.volume += 2;
-      }
-    } else {
-      if self.volume >= 2 {
+  fn step_shifter(&mut self) {
+    if !self.is_silence {
+      if self.shift_register & 0x1 == 0x1 {
+        if self.volume <= 125 {
+          self.volume += 2;
+        }
+      } else if self.volume >= 2 {
         self.volume -= 2
       }
     }
     self.shift_register >>= 1;
     self.bit_count -= 1;
-    self.set_volume();
+    if self.bit_count == 0 {
+      // a new output cycle
+      self.bit_count = 8;
+      match self.sample_buffer.take() {
+        Some(sample) => {
+          self.is_silence = false;
+          self.shift_register = sample;
+        }
+        None => self.is_silence = true,
+      }
+    }
   }
 
   fn restart(&mut self) {
     self.current_address = self.sample_address;
     self.current_length = self.sample_length;
+    self.is_restarted = true;
   }
 
+  // (address, length) of the sample started since the last call
+  pub fn take_restarted_sample(&mut self) -> Option<(Addr, Addr)> {
+    if !self.is_restarted {
+      return None;
+    }
+    self.is_restarted = false;
+    Some((self.sample_address, self.sample_length))
+  }
 
-  fn set_volume(&self) {
-    unsafe {
-      set_oscillator_volume(self.index, self.get_volume());
+  // is_enabled while bytes are left to play
+  pub fn state(&self) -> ChannelState {
+    ChannelState {
+      is_enabled: self.current_length > 0,
+      period: self.tick_period,
+      duty: None,
+      volume: self.volume,
+      length: self.current_length,
+      envelope: None,
     }
   }
 
-  fn get_volume(&self) -> f32 {
-    self.volume as f32 / (GROBAL_GAIN * 10 as f32)
+  // 0 - 127
+  pub fn output(&self) -> Data {
+    self.volume
   }
 
   pub fn has_count_end(&self) -> bool {
     self.current_length == 0
   }
- }
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use super::super::super::mapper::Mapper0;
+
+  fn setup(sample: Vec<Data>) -> (DMC, Mapper0, Rom, Ram) {
+    let mut prg = vec![0; 0x4000];
+    prg[..sample.len()].copy_from_slice(&sample);
+    (DMC::new(), Mapper0::new(), Rom::new(prg), Ram::new(vec![]))
+  }
+
+  #[test]
+  fn test_play_sample() {
+    let (mut dmc, mut mapper, rom, sram) = setup(vec![0xFF, 0x00]);
+    dmc.write(0x00, 0x0F); // fastest rate, 54 cycles
+    dmc.write(0x01, 0x40);
+    dmc.write(0x02, 0x00); // $C000, mirrored first bank
+    dmc.write(0x03, 0x00); // 1 byte
+    dmc.enable();
+    let mut fetches = 0;
+    for _ in 0..54 * 16 {
+      if dmc.step_timer(&mut mapper, &sram, &rom) {
+        fetches += 1;
+      }
+    }
+    // the first output cycle is silent, then 8 bits of 1
+    assert_eq!(fetches, 1);
+    assert_eq!(dmc.output(), 0x40 + 16);
+    assert!(dmc.has_count_end());
+    assert!(!dmc.is_interrupted());
+  }
+
+  #[test]
+  fn test_loop_and_irq() {
+    let (mut dmc, mut mapper, rom, sram) = setup(vec![]);
+    dmc.write(0x00, 0xCF); // irq, loop
+    dmc.write(0x03, 0x00);
+    dmc.enable();
+    dmc.step_timer(&mut mapper, &sram, &rom);
+    assert!(!dmc.has_count_end());
+    assert!(!dmc.is_interrupted());
+    dmc.write(0x00, 0x8F); // irq, no loop
+    for _ in 0..54 * 8 {
+      dmc.step_timer(&mut mapper, &sram, &rom);
+    }
+    assert!(dmc.has_count_end());
+    assert!(dmc.is_interrupted());
+    dmc.write(0x00, 0x0F);
+    assert!(!dmc.is_interrupted());
+  }
+}
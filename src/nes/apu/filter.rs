@@ -0,0 +1,86 @@
+use std::f32::consts::PI;
+
+// ref. http://wiki.nesdev.com/w/index.php/APU_Mixer
+// first-order filters, cutoff in Hz
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterKind {
+  HighPass(f32),
+  LowPass(f32),
+}
+
+// the output stage of the NES
+pub const NES_FILTERS: [FilterKind; 3] = [
+  FilterKind::HighPass(90.0),
+  FilterKind::HighPass(440.0),
+  FilterKind::LowPass(14000.0),
+];
+
+#[derive(Debug)]
+pub struct Filter {
+  kind: FilterKind,
+  alpha: f32,
+  prev_input: f32,
+  prev_output: f32,
+}
+
+impl Filter {
+  pub fn new(kind: FilterKind, sample_rate: f32) -> Self {
+    let mut filter = Filter {
+      kind,
+      alpha: 0.0,
+      prev_input: 0.0,
+      prev_output: 0.0,
+    };
+    filter.set_sample_rate(sample_rate);
+    filter
+  }
+
+  pub fn set_sample_rate(&mut self, sample_rate: f32) {
+    let dt = 1.0 / sample_rate;
+    self.alpha = match self.kind {
+      FilterKind::HighPass(cutoff) => {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        rc / (rc + dt)
+      }
+      FilterKind::LowPass(cutoff) => {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        dt / (rc + dt)
+      }
+    };
+  }
+
+  pub fn process(&mut self, input: f32) -> f32 {
+    let output = match self.kind {
+      FilterKind::HighPass(_) => self.alpha * (self.prev_output + input - self.prev_input),
+      FilterKind::LowPass(_) => self.prev_output + self.alpha * (input - self.prev_output),
+    };
+    self.prev_input = input;
+    self.prev_output = output;
+    output
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_high_pass_removes_dc() {
+    let mut filter = Filter::new(FilterKind::HighPass(90.0), 44100.0);
+    let mut output = 0.0;
+    for _ in 0..44100 {
+      output = filter.process(0.5);
+    }
+    assert!(output.abs() < 0.001);
+  }
+
+  #[test]
+  fn test_low_pass_keeps_dc() {
+    let mut filter = Filter::new(FilterKind::LowPass(14000.0), 44100.0);
+    let mut output = 0.0;
+    for _ in 0..100 {
+      output = filter.process(0.5);
+    }
+    assert!((output - 0.5).abs() < 0.001);
+  }
+}
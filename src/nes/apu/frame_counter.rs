@@ -0,0 +1,211 @@
+use super::constants::*;
+use super::super::types::Data;
+
+// ref. http://wiki.nesdev.com/w/index.php/APU_Frame_Counter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameClock {
+  None,
+  Quarter, // envelopes & triangle linear counter
+  Half,    // quarter + length counters & sweeps
+}
+
+#[derive(Debug)]
+pub struct FrameCounter {
+  cycle: u32, // cpu cycles from the last reset of the sequencer
+  steps: [u32; 5], // of the region
+  is_five_step: bool,
+  is_irq_inhibited: bool,
+  is_interrupted: bool,
+  // $4017 write waiting to reset the sequencer, (data, delay)
+  pending_write: Option<(Data, u8)>,
+}
+
+impl FrameCounter {
+  pub fn new() -> Self {
+    FrameCounter {
+      cycle: 0,
+      steps: NTSC_FRAME_STEPS,
+      is_five_step: false,
+      is_irq_inhibited: false,
+      is_interrupted: false,
+      pending_write: None,
+    }
+  }
+
+  pub fn set_steps(&mut self, steps: [u32; 5]) {
+    self.steps = steps;
+  }
+
+  // $4017, the sequencer is reset 3 or 4 cpu cycles later
+  pub fn write(&mut self, data: Data, is_odd_cycle: bool) {
+    self.is_irq_inhibited = data & 0x40 == 0x40;
+    if self.is_irq_inhibited {
+      self.is_interrupted = false;
+    }
+    self.pending_write = Some((data, if is_odd_cycle { 4 } else { 3 }));
+  }
+
+  pub fn is_interrupted(&self) -> bool {
+    self.is_interrupted
+  }
+
+  // by $4015 read
+  pub fn clear_interrupt(&mut self) {
+    self.is_interrupted = false;
+  }
+
+  // called every cpu cycle
+  pub fn step(&mut self) -> FrameClock {
+    if let Some((data, delay)) = self.pending_write {
+      if delay > 1 {
+        self.pending_write = Some((data, delay - 1));
+      } else {
+        self.pending_write = None;
+        self.is_five_step = data & 0x80 == 0x80;
+        self.cycle = 0;
+        // 5-step mode clocks the units immediately
+        return if self.is_five_step { FrameClock::Half } else { FrameClock::None };
+      }
+    }
+    self.cycle += 1;
+    if self.is_five_step {
+      self.step_five()
+    } else {
+      self.step_four()
+    }
+  }
+
+  fn step_four(&mut self) -> FrameClock {
+    let [step1, step2, step3, step4, _] = self.steps;
+    match self.cycle {
+      c if c == step1 || c == step3 => FrameClock::Quarter,
+      c if c == step2 => FrameClock::Half,
+      c if c == step4 - 1 => {
+        self.set_interrupt();
+        FrameClock::None
+      }
+      c if c == step4 => {
+        self.set_interrupt();
+        FrameClock::Half
+      }
+      c if c == step4 + 1 => {
+        self.set_interrupt();
+        self.cycle = 0;
+        FrameClock::None
+      }
+      _ => FrameClock::None,
+    }
+  }
+
+  fn step_five(&mut self) -> FrameClock {
+    let [step1, step2, step3, _, step5] = self.steps;
+    match self.cycle {
+      c if c == step1 || c == step3 => FrameClock::Quarter,
+      c if c == step2 || c == step5 => FrameClock::Half,
+      c if c == step5 + 1 => {
+        self.cycle = 0;
+        FrameClock::None
+      }
+      _ => FrameClock::None,
+    }
+  }
+
+  fn set_interrupt(&mut self) {
+    if !self.is_irq_inhibited {
+      self.is_interrupted = true;
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn clocks(counter: &mut FrameCounter, cycles: u32) -> Vec<(u32, FrameClock)> {
+    (1..=cycles)
+      .map(|c| (c, counter.step()))
+      .filter(|(_, clock)| *clock != FrameClock::None)
+      .collect()
+  }
+
+  #[test]
+  fn test_four_step() {
+    let mut counter = FrameCounter::new();
+    assert_eq!(
+      clocks(&mut counter, 29830),
+      vec![(7457, FrameClock::Quarter), (14913, FrameClock::Half), (22371, FrameClock::Quarter), (29829, FrameClock::Half)]
+    );
+    assert!(counter.is_interrupted());
+    counter.clear_interrupt();
+    // the next frame starts right after
+    assert_eq!(clocks(&mut counter, 7457), vec![(7457, FrameClock::Quarter)]);
+    assert!(!counter.is_interrupted());
+  }
+
+  #[test]
+  fn test_five_step() {
+    let mut counter = FrameCounter::new();
+    counter.write(0x80, false);
+    assert_eq!(
+      clocks(&mut counter, 37285),
+      vec![(3, FrameClock::Half), (7460, FrameClock::Quarter), (14916, FrameClock::Half),
+           (22374, FrameClock::Quarter), (37284, FrameClock::Half)]
+    );
+    assert!(!counter.is_interrupted());
+  }
+
+  #[test]
+  fn test_pal_steps() {
+    let mut counter = FrameCounter::new();
+    counter.set_steps(PAL_FRAME_STEPS);
+    assert_eq!(
+      clocks(&mut counter, 33254),
+      vec![(8313, FrameClock::Quarter), (16627, FrameClock::Half), (24939, FrameClock::Quarter), (33253, FrameClock::Half)]
+    );
+    assert!(counter.is_interrupted());
+    assert_eq!(clocks(&mut counter, 8313), vec![(8313, FrameClock::Quarter)]);
+  }
+
+  #[test]
+  fn test_write_delay() {
+    let mut counter = FrameCounter::new();
+    counter.write(0x00, true);
+    assert_eq!(clocks(&mut counter, 7461), vec![(7461, FrameClock::Quarter)]);
+    // on an even cycle, the sequencer restarts from the middle of the frame
+    clocks(&mut counter, 5000);
+    counter.write(0x00, false);
+    assert_eq!(clocks(&mut counter, 7460), vec![(7460, FrameClock::Quarter)]);
+    // 5-step mode clocks a half frame when the write takes effect
+    counter.write(0x80, true);
+    assert_eq!(clocks(&mut counter, 4), vec![(4, FrameClock::Half)]);
+  }
+
+  #[test]
+  fn test_interrupt_clear() {
+    let mut counter = FrameCounter::new();
+    clocks(&mut counter, 29828);
+    assert!(counter.is_interrupted());
+    // the flag is set again on each of its 3 cycles, a $4015 read clears it after them
+    counter.clear_interrupt();
+    assert!(!counter.is_interrupted());
+    clocks(&mut counter, 1);
+    assert!(counter.is_interrupted());
+    counter.clear_interrupt();
+    clocks(&mut counter, 1);
+    assert!(counter.is_interrupted());
+    counter.clear_interrupt();
+    clocks(&mut counter, 1);
+    assert!(!counter.is_interrupted());
+  }
+
+  #[test]
+  fn test_irq_inhibit() {
+    let mut counter = FrameCounter::new();
+    clocks(&mut counter, 29830);
+    assert!(counter.is_interrupted());
+    counter.write(0x40, false);
+    assert!(!counter.is_interrupted());
+    clocks(&mut counter, 29830 * 2);
+    assert!(!counter.is_interrupted());
+  }
+}
@@ -0,0 +1,212 @@
+use super::constants::*;
+use super::blip::BlipBuffer;
+use super::filter::{Filter, FilterKind, NES_FILTERS};
+use super::channel::{Channel, ChannelGains};
+use super::super::types::Data;
+
+// ref. http://wiki.nesdev.com/w/index.php/APU_Mixer#Lookup_Table
+#[derive(Debug)]
+pub struct Mixer {
+  pulse_table: Vec<f32>,
+  tnd_table: Vec<f32>,
+  gains: ChannelGains,
+  volume: f32, // fade, e.g. NSF
+  master_volume: f32,
+
+  blip: BlipBuffer,
+  filters: Vec<Filter>,
+  cycle: u32, // from the start of this frame
+  level: f32,
+  levels: Vec<f32>,
+  buffer: Vec<i16>,
+}
+
+impl Mixer {
+  pub fn new(sample_rate: f64) -> Self {
+    let pulse_table = (0..31)
+      .map(|n| if n == 0 { 0.0 } else { 95.52 / (8128.0 / n as f32 + 100.0) })
+      .collect();
+    let tnd_table = (0..203)
+      .map(|n| if n == 0 { 0.0 } else { 163.67 / (24329.0 / n as f32 + 100.0) })
+      .collect();
+    let mut mixer = Mixer {
+      pulse_table,
+      tnd_table,
+      gains: ChannelGains::new(),
+      volume: 1.0,
+      master_volume: 1.0,
+      blip: BlipBuffer::new(CPU_CLOCK as f64, sample_rate),
+      filters: Vec::new(),
+      cycle: 0,
+      level: 0.0,
+      levels: Vec::new(),
+      buffer: Vec::new(),
+    };
+    mixer.set_filters(&NES_FILTERS);
+    mixer
+  }
+
+  // outputs of square0, square1, triangle, noise: 0 - 15 and dmc: 0 - 127
+  pub fn mix(&self, outputs: [Data; 5], expansion: f32) -> f32 {
+    let [square0, square1, triangle, noise, dmc] = outputs;
+    if self.gains.is_unity() {
+      let pulse = self.pulse_table[(square0 + square1) as usize];
+      let tnd = self.tnd_table[3 * triangle as usize + 2 * noise as usize + dmc as usize];
+      return pulse + tnd + expansion;
+    }
+    // the same curves as the tables with the outputs scaled before the non-linear mix
+    let gain = |channel: Channel, output: Data| self.gains.gain(channel) * output as f32;
+    let pulse = gain(Channel::Pulse1, square0) + gain(Channel::Pulse2, square1);
+    let tnd = 3.0 * gain(Channel::Triangle, triangle) + 2.0 * gain(Channel::Noise, noise) + gain(Channel::Dmc, dmc);
+    let pulse = if pulse == 0.0 { 0.0 } else { 95.52 / (8128.0 / pulse + 100.0) };
+    let tnd = if tnd == 0.0 { 0.0 } else { 163.67 / (24329.0 / tnd + 100.0) };
+    pulse + tnd + expansion * self.gains.gain(Channel::Expansion)
+  }
+
+  // each channel alone on the mixer curves, before the channel gains
+  pub fn channel_levels(&self, outputs: [Data; 5], expansion: f32) -> [f32; 6] {
+    let [square0, square1, triangle, noise, dmc] = outputs;
+    [
+      self.pulse_table[square0 as usize],
+      self.pulse_table[square1 as usize],
+      self.tnd_table[3 * triangle as usize],
+      self.tnd_table[2 * noise as usize],
+      self.tnd_table[dmc as usize],
+      expansion,
+    ]
+  }
+
+  pub fn gains_mut(&mut self) -> &mut ChannelGains {
+    &mut self.gains
+  }
+
+  // called every cpu cycle, only the changes of the level go to the blip buffer
+  pub fn push(&mut self, level: f32) {
+    if level != self.level {
+      self.blip.add_delta(self.cycle, level - self.level);
+      self.level = level;
+    }
+    self.cycle += 1;
+  }
+
+  // resample the levels of this frame into the pcm buffer
+  pub fn end_frame(&mut self) {
+    self.blip.end_frame(self.cycle);
+    self.cycle = 0;
+    self.levels.clear();
+    self.blip.read_samples(&mut self.levels);
+    for level in self.levels.iter() {
+      let level = self.filters.iter_mut().fold(*level, |level, filter| filter.process(level)) * self.volume * self.master_volume;
+      self.buffer.push((level.clamp(-1.0, 1.0) * i16::MAX as f32) as i16);
+    }
+  }
+
+  pub fn sample_rate(&self) -> f64 {
+    self.blip.sample_rate()
+  }
+
+  pub fn set_sample_rate(&mut self, sample_rate: f64) {
+    if sample_rate == self.blip.sample_rate() {
+      return;
+    }
+    self.blip.set_sample_rate(sample_rate);
+    for filter in self.filters.iter_mut() {
+      filter.set_sample_rate(sample_rate as f32);
+    }
+  }
+
+  pub fn set_clock_rate(&mut self, clock_rate: f64) {
+    self.blip.set_clock_rate(clock_rate);
+  }
+
+  pub fn set_filters(&mut self, filters: &[FilterKind]) {
+    let sample_rate = self.blip.sample_rate() as f32;
+    self.filters = filters.iter().map(|kind| Filter::new(*kind, sample_rate)).collect();
+  }
+
+  pub fn set_volume(&mut self, volume: f32) {
+    self.volume = volume;
+  }
+
+  pub fn volume(&self) -> f32 {
+    self.volume
+  }
+
+  pub fn set_master_volume(&mut self, volume: f32) {
+    self.master_volume = volume;
+  }
+
+  pub fn samples(&self) -> &[i16] {
+    &self.buffer
+  }
+
+  pub fn clear(&mut self) {
+    self.buffer.clear();
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_mix() {
+    let mixer = Mixer::new(SAMPLE_RATE);
+    assert_eq!(mixer.mix([0, 0, 0, 0, 0], 0.0), 0.0);
+    // full scale is just under 1.0
+    let max = mixer.mix([15, 15, 15, 15, 127], 0.0);
+    assert!(max > 0.99 && max < 1.01);
+    // non-linear, two pulses are quieter than twice one pulse
+    assert!(mixer.mix([15, 15, 0, 0, 0], 0.0) < mixer.mix([15, 0, 0, 0, 0], 0.0) * 2.0);
+  }
+
+  #[test]
+  fn test_channel_gains() {
+    let mut mixer = Mixer::new(SAMPLE_RATE);
+    let outputs = [15, 8, 15, 4, 64];
+    let level = mixer.mix(outputs, 0.25);
+    // unity gains through the formulas are the same as the tables
+    mixer.gains_mut().set_gain(Channel::Noise, 1.0001);
+    assert!((mixer.mix(outputs, 0.25) - level).abs() < 0.001);
+    mixer.gains_mut().set_gain(Channel::Noise, 1.0);
+    mixer.gains_mut().set_soloed(Channel::Pulse1, true);
+    assert_eq!(mixer.mix(outputs, 0.25), mixer.pulse_table[15]);
+    mixer.gains_mut().set_soloed(Channel::Pulse1, false);
+    mixer.gains_mut().set_muted(Channel::Expansion, true);
+    assert!((mixer.mix(outputs, 0.25) - (level - 0.25)).abs() < 0.0001);
+  }
+
+  #[test]
+  fn test_end_frame() {
+    let mut mixer = Mixer::new(SAMPLE_RATE);
+    for _ in 0..CPU_CLOCK / 60 {
+      mixer.push(0.5);
+    }
+    mixer.end_frame();
+    // 734.99 samples a frame
+    assert_eq!(mixer.samples().len(), 734);
+    // the step is filtered by the high-pass filters
+    assert!(mixer.samples()[10] > 0);
+    assert!(mixer.samples()[733] < mixer.samples()[10]);
+    mixer.clear();
+    mixer.set_volume(0.0);
+    for _ in 0..100 {
+      mixer.push(0.5);
+    }
+    mixer.end_frame();
+    assert!(!mixer.samples().is_empty());
+    assert!(mixer.samples().iter().all(|&s| s == 0));
+  }
+
+  #[test]
+  fn test_no_filters() {
+    let mut mixer = Mixer::new(48000.0);
+    mixer.set_filters(&[]);
+    for _ in 0..CPU_CLOCK / 60 {
+      mixer.push(0.5);
+    }
+    mixer.end_frame();
+    assert_eq!(mixer.samples().len(), 799);
+    assert_eq!(mixer.samples()[798], i16::MAX / 2);
+  }
+}
@@ -3,84 +3,130 @@ mod triangle;
 mod noise;
 mod dmc;
 mod constants;
+mod mixer;
+mod blip;
+mod filter;
+mod frame_counter;
+mod channel;
+mod wav;
+mod recorder;
+mod vgm;
+mod scope;
 
 use self::constants::*;
+pub use self::constants::CPU_CLOCK;
 use self::square::Square;
 use self::triangle::Triangle;
 use self::noise::Noise;
 use self::dmc::DMC;
+use self::mixer::Mixer;
+use self::frame_counter::{FrameCounter, FrameClock};
+use self::recorder::Recorder;
+use self::vgm::VgmLogger;
+use self::scope::Scope;
+pub use self::channel::{Channel, ChannelState, CHANNELS};
+pub use self::recorder::Recording;
+pub use self::filter::FilterKind;
 use super::types::{Data, Addr};
+use super::cassette_paser::Region;
 use super::mapper::Mapper;
 use super::Rom;
 use super::Ram;
 use super::CpuRegister;
 
+extern "C" {
+  fn play_audio(ptr: *const i16, len: usize);
+  fn get_sample_rate() -> f64;
+}
+
 #[derive(Debug)]
 pub struct Apu {
   squares: (Square, Square),
   triangle: Triangle,
   noise: Noise,
   dmc: DMC,
-  cycle: u16,
-  step: usize,
-  sequencer_mode: bool, // t => mode 1, f => mode 0
-  enable_irq: bool,
+  mixer: Mixer,
+  frame_counter: FrameCounter,
+  timing: ApuTiming,
+  is_odd_cycle: bool,
+  oam_dma_cycles: u16, // left of the OAM DMA running on the bus
+  recorder: Option<Recorder>,
+  vgm: Option<VgmLogger>,
+  scope: Option<Scope>,
+  cycles: u64, // from power on, the time of the register writes
+  registers: [Data; 0x18], // last written, the initial state of a vgm log
 }
 
 impl Apu {
   pub fn new() -> Self {
     Apu {
       squares: (Square::new(0), Square::new(1)),
-      triangle: Triangle::new(2),
+      triangle: Triangle::new(),
       noise: Noise::new(),
-      dmc: DMC::new(3),
-      cycle: 0,
-      step: 0,
-      sequencer_mode: false,
-      enable_irq: false,
+      dmc: DMC::new(),
+      mixer: Mixer::new(SAMPLE_RATE),
+      frame_counter: FrameCounter::new(),
+      timing: NTSC_TIMING,
+      is_odd_cycle: false,
+      oam_dma_cycles: 0,
+      recorder: None,
+      vgm: None,
+      scope: None,
+      cycles: 0,
+      registers: [0; 0x18],
     }
   }
   pub fn run<T: CpuRegister>(&mut self, cycle: u16,register: &mut T, mapper: &mut dyn Mapper, sram: &Ram, prg_rom: &Rom, stall: &mut u8) {
-    self.cycle += cycle;
+    self.fill_vgm_samples(mapper, sram, prg_rom);
     for _ in 0..cycle {
-      self.step_timers(mapper, sram, prg_rom, stall);
-    }
-    if self.cycle >= DIVIDE_COUNT_FOR_240HZ {
-      // TODO: invoked by 240hz
-      self.cycle -= DIVIDE_COUNT_FOR_240HZ;
-      if self.sequencer_mode {
-        self.update_by_sequence_mode1();
-      } else {
-        self.update_by_sequence_mdoe0(register);
+      self.cycles += 1;
+      match self.frame_counter.step() {
+        FrameClock::Quarter => self.update_envelope(),
+        FrameClock::Half => {
+          self.update_envelope();
+          self.update_counters();
+        }
+        FrameClock::None => (),
       }
-    }
-  }
-
-  // step 4
-  fn update_by_sequence_mdoe0<T: CpuRegister>(&mut self, register: &mut T) {
-    if self.step % 2 == 1 {
-      self.update_counters();
-    }
-    self.step += 1;
-    if self.step == 4 {
-      if self.enable_irq {
-        register.set_interrupt_irq();
+      if self.step_timers(mapper, sram, prg_rom) {
+        *stall += self.dmc_stall_cycles();
       }
-      self.step = 0;
+      // a looped sample
+      if let Some((address, length)) = self.dmc.take_restarted_sample() {
+        if let Some(vgm) = self.vgm.as_mut() {
+          vgm.request_sample(false, address, length);
+          self.fill_vgm_samples(mapper, sram, prg_rom);
+        }
+      }
+      self.oam_dma_cycles = self.oam_dma_cycles.saturating_sub(1);
+      let outputs = [
+        self.squares.0.output(),
+        self.squares.1.output(),
+        self.triangle.output(),
+        self.noise.output(),
+        self.dmc.output(),
+      ];
+      let expansion = mapper.expansion_audio();
+      let level = self.mixer.mix(outputs, expansion);
+      self.mixer.push(level);
+      if let Some(recorder) = self.recorder.as_mut() {
+        recorder.push(outputs, expansion, level);
+      }
+      if let Some(scope) = self.scope.as_mut() {
+        scope.push(self.mixer.channel_levels(outputs, expansion), self.mixer.sample_rate(), self.timing.cpu_clock as f64);
+      }
+    }
+    // the irq line is held until the flags are cleared
+    if self.frame_counter.is_interrupted() || self.dmc.is_interrupted() {
+      register.set_interrupt_irq();
     }
-    self.update_envelope();
   }
 
-  // step 5
-  fn update_by_sequence_mode1(&mut self) {
-    if self.step % 2 == 0 {
-      self.update_counters();
-    }
-    self.step += 1;
-    if self.step == 5 {
-      self.step = 0;
-    } else {
-      self.update_envelope();
+  fn fill_vgm_samples(&mut self, mapper: &mut dyn Mapper, sram: &Ram, prg_rom: &Rom) {
+    if let Some(vgm) = self.vgm.as_mut() {
+      if vgm.has_pending_samples() {
+        vgm.fill_samples(|addr| mapper.read(addr, prg_rom, sram));
+      }
     }
   }
 
@@ -88,6 +134,7 @@ impl Apu {
   fn update_envelope(&mut self) {
     self.squares.0.update_envelope();
     self.squares.1.update_envelope();
+    self.triangle.update_linear_counter();
     self.noise.update_envelope();
   }
 
@@ -99,12 +146,171 @@ impl Apu {
     self.noise.update_counter();
   }
 
-  // TODO:
-  fn step_timers(&mut self, mapper: &mut dyn Mapper, sram: &Ram, prg_rom: &Rom, stall: &mut u8) {
-    self.dmc.step_timer(mapper, sram, prg_rom, stall);
-    // if cycle%2 == 0 {
-    //   self.noise.step_timer();
-    // }
+  // returns true when the dmc reader fetched a sample byte
+  fn step_timers(&mut self, mapper: &mut dyn Mapper, sram: &Ram, prg_rom: &Rom) -> bool {
+    // squares are clocked by the apu cycle (cpu / 2)
+    if self.is_odd_cycle {
+      self.squares.0.step_timer();
+      self.squares.1.step_timer();
+    }
+    self.is_odd_cycle = !self.is_odd_cycle;
+    self.triangle.step_timer();
+    self.noise.step_timer();
+    self.dmc.step_timer(mapper, sram, prg_rom)
+  }
+
+  // ref. http://wiki.nesdev.com/w/index.php/DMA#DMC_DMA_during_OAM_DMA
+  // the dmc fetch halts the cpu for 4 cycles, it only takes 2 during OAM DMA
+  // except at the end of the transfer
+  fn dmc_stall_cycles(&self) -> u8 {
+    match self.oam_dma_cycles {
+      0 => 4,
+      1 => 3,
+      2 => 1,
+      _ => 2,
+    }
+  }
+
+  // the cpu clock, frame counter and period tables of the console, Multi runs as NTSC
+  pub fn set_region(&mut self, region: Region) {
+    self.timing = match region {
+      Region::Pal => PAL_TIMING,
+      Region::Dendy => DENDY_TIMING,
+      Region::Ntsc | Region::Multi => NTSC_TIMING,
+    };
+    self.mixer.set_clock_rate(self.timing.cpu_clock as f64);
+    self.frame_counter.set_steps(self.timing.frame_steps);
+    self.noise.set_periods(self.timing.noise_periods);
+    self.dmc.set_periods(self.timing.dmc_periods);
+  }
+
+  pub fn cpu_clock(&self) -> usize {
+    self.timing.cpu_clock
+  }
+
+  // OAM DMA takes the bus for the next cycles
+  pub fn start_oam_dma(&mut self, cycles: u16) {
+    self.oam_dma_cycles = cycles;
+  }
+
+  pub fn is_odd_cycle(&self) -> bool {
+    self.is_odd_cycle
+  }
+
+  // 0.0 - 1.0, multiplied to the mixed output (e.g. NSF fade out)
+  pub fn set_volume(&mut self, volume: f32) {
+    self.mixer.set_volume(volume);
+    if let Some(recorder) = self.recorder.as_mut() {
+      recorder.set_volume(volume);
+    }
+  }
+
+  // 0.0 - 1.0, the listening level on top of set_volume, recordings are not affected
+  pub fn set_master_volume(&mut self, volume: f32) {
+    self.mixer.set_master_volume(volume);
+  }
+
+  // multiplied to the channel output before the mix, 1.0 as is
+  pub fn set_channel_gain(&mut self, channel: Channel, gain: f32) {
+    self.mixer.gains_mut().set_gain(channel, gain);
+  }
+
+  pub fn set_channel_muted(&mut self, channel: Channel, is_muted: bool) {
+    self.mixer.gains_mut().set_muted(channel, is_muted);
+  }
+
+  // while any channel is soloed the others are silent
+  pub fn set_channel_soloed(&mut self, channel: Channel, is_soloed: bool) {
+    self.mixer.gains_mut().set_soloed(channel, is_soloed);
+  }
+
+  // output rate in Hz, it can be changed anytime for dynamic rate control
+  pub fn set_sample_rate(&mut self, sample_rate: f64) {
+    self.mixer.set_sample_rate(sample_rate);
+  }
+
+  // high-pass / low-pass chain after the resampler, NES_FILTERS by default, empty for the raw mix
+  pub fn set_filters(&mut self, filters: &[FilterKind]) {
+    self.mixer.set_filters(filters);
+  }
+
+  // hand the samples generated in this frame to the host,
+  // then follow the rate the host asks for the next frame
+  pub fn flush(&mut self) {
+    if let Some(recorder) = self.recorder.as_mut() {
+      recorder.end_frame();
+    }
+    self.mixer.end_frame();
+    let samples = self.mixer.samples();
+    let sample_rate = unsafe {
+      play_audio(samples.as_ptr(), samples.len());
+      get_sample_rate()
+    };
+    self.mixer.clear();
+    self.set_sample_rate(sample_rate);
+  }
+
+  // record the output at SAMPLE_RATE, optionally with a track per channel
+  pub fn start_recording(&mut self, per_channel: bool) {
+    let mut recorder = Recorder::new(SAMPLE_RATE as u32, per_channel);
+    recorder.set_volume(self.mixer.volume());
+    recorder.set_clock_rate(self.timing.cpu_clock as f64);
+    self.recorder = Some(recorder);
+  }
+
+  // WAV files of the frames flushed since start_recording
+  pub fn stop_recording(&mut self) -> Option<Recording> {
+    self.recorder.take().map(|recorder| recorder.finish())
+  }
+
+  // keep the last len samples of each channel at the output rate, None to stop
+  pub fn set_scope_length(&mut self, len: Option<usize>) {
+    self.scope = len.map(Scope::new);
+  }
+
+  // oldest first, the level of the channel alone on the mixer curve
+  pub fn scope_samples(&self, channel: Channel) -> Option<Vec<f32>> {
+    self.scope.as_ref().map(|scope| scope.samples(channel))
+  }
+
+  // the expansion channel has no state here
+  pub fn channel_state(&self, channel: Channel) -> ChannelState {
+    match channel {
+      Channel::Pulse1 => self.squares.0.state(),
+      Channel::Pulse2 => self.squares.1.state(),
+      Channel::Triangle => self.triangle.state(),
+      Channel::Noise => self.noise.state(),
+      Channel::Dmc => self.dmc.state(),
+      Channel::Expansion => ChannelState::default(),
+    }
+  }
+
+  // log the register writes as VGM from now, starting with the last written values
+  pub fn start_vgm_log(&mut self) {
+    let mut vgm = VgmLogger::new(self.cycles, self.timing.cpu_clock, self.timing.frame_rate);
+    for addr in (0x00..=0x13).chain([0x15, 0x17].iter().copied()) {
+      vgm.write(self.cycles, addr, self.registers[addr as usize]);
+    }
+    self.vgm = Some(vgm);
+  }
+
+  // the player loops back to this point
+  pub fn mark_vgm_loop(&mut self) {
+    if let Some(vgm) = self.vgm.as_mut() {
+      vgm.mark_loop(self.cycles);
+    }
+  }
+
+  pub fn stop_vgm_log(&mut self) -> Option<Vec<u8>> {
+    let cycles = self.cycles;
+    self.vgm.take().map(|vgm| vgm.finish(cycles))
+  }
+
+  // cartridge sound registers, only FDS sound can be in the vgm log
+  pub fn write_expansion(&mut self, addr: Addr, data: Data) {
+    if let Some(vgm) = self.vgm.as_mut() {
+      vgm.write_expansion(self.cycles, addr, data);
+    }
   }
 
   pub fn read(&mut self, addr: Addr) -> Data {
@@ -135,8 +341,20 @@ impl Apu {
         } else {
           0x10
         };
-        d | n | t | s1 | s0
-        }
+        let i = if self.dmc.is_interrupted() {
+          0x80
+        } else {
+          0x00
+        };
+        let f = if self.frame_counter.is_interrupted() {
+          0x40
+        } else {
+          0x00
+        };
+        // reading clears the frame interrupt
+        self.frame_counter.clear_interrupt();
+        i | f | d | n | t | s1 | s0
+      }
       _ => 0,
     }
   }
@@ -186,12 +404,211 @@ impl Apu {
         }
       }
       0x17 => {
-        self.sequencer_mode = data & 0x80 == 0x80;
-        self.enable_irq = data & 0x40 != 0x40; // actually it keeps wherer irq is disabled
-        self.step = 0;
-        self.cycle = 0;
+        self.frame_counter.write(data, self.is_odd_cycle);
       }
       _ => (),
     }
+    if addr < 0x18 {
+      self.registers[addr as usize] = data;
+      if let Some(vgm) = self.vgm.as_mut() {
+        vgm.write(self.cycles, addr, data);
+        // the sample bytes are read through the mapper on the next run
+        if let Some((address, length)) = self.dmc.take_restarted_sample() {
+          vgm.request_sample(true, address, length);
+        }
+      }
+    }
   }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+  use super::*;
+  use super::super::mapper::Mapper0;
+  use super::super::cpu_register::Register;
+  use super::filter::NES_FILTERS;
+
+  // returns the stalled cycles
+  fn run_cycles(apu: &mut Apu, register: &mut Register, cycles: u32) -> u8 {
+    let mut mapper = Mapper0::new();
+    let rom = Rom::new(vec![0; 0x4000]);
+    let sram = Ram::new(vec![]);
+    let mut stall = 0;
+    for _ in 0..cycles {
+      apu.run(1, register, &mut mapper, &sram, &rom, &mut stall);
+    }
+    stall
+  }
+
+  #[test]
+  fn test_frame_interrupt() {
+    let mut apu = Apu::new();
+    let mut register = Register::new();
+    register.set_status_interrupt(false);
+    run_cycles(&mut apu, &mut register, 29828);
+    assert_eq!(apu.read(0x15) & 0x40, 0x40);
+    // reading $4015 clears the flag
+    assert_eq!(apu.read(0x15) & 0x40, 0x00);
+    run_cycles(&mut apu, &mut register, 29830);
+    assert!(register.is_interrupt_irq_enabled());
+  }
+
+  #[test]
+  fn test_irq_inhibit() {
+    let mut apu = Apu::new();
+    let mut register = Register::new();
+    register.set_status_interrupt(false);
+    apu.write(0x17, 0x40);
+    run_cycles(&mut apu, &mut register, 29830 * 2);
+    assert_eq!(apu.read(0x15) & 0x40, 0x00);
+    assert!(!register.is_interrupt_irq_enabled());
+  }
+
+  #[test]
+  fn test_length_counter_by_half_frames() {
+    let mut apu = Apu::new();
+    let mut register = Register::new();
+    apu.write(0x15, 0x01);
+    apu.write(0x00, 0x10);
+    apu.write(0x03, 0x18); // length 2
+    assert_eq!(apu.read(0x15) & 0x01, 0x01);
+    run_cycles(&mut apu, &mut register, 14913);
+    assert_eq!(apu.read(0x15) & 0x01, 0x01);
+    run_cycles(&mut apu, &mut register, 29829 - 14913);
+    assert_eq!(apu.read(0x15) & 0x01, 0x00);
+  }
+
+  #[test]
+  fn test_dmc_stall() {
+    let mut apu = Apu::new();
+    let mut register = Register::new();
+    apu.write(0x10, 0x0F); // 54 cycles a bit
+    apu.write(0x13, 0x01); // 17 bytes
+    apu.write(0x15, 0x10);
+    assert_eq!(apu.read(0x15) & 0x10, 0x10);
+    assert_eq!(run_cycles(&mut apu, &mut register, 1), 4);
+    // the next byte is fetched after the first output cycle (8 bits)
+    apu.start_oam_dma(513);
+    assert_eq!(run_cycles(&mut apu, &mut register, 513), 2);
+    assert_eq!(run_cycles(&mut apu, &mut register, 54 * 8), 4);
+  }
+
+  #[test]
+  fn test_pal_timing() {
+    let mut apu = Apu::new();
+    let mut register = Register::new();
+    apu.set_region(Region::Pal);
+    assert_eq!(apu.cpu_clock(), PAL_CPU_CLOCK);
+    register.set_status_interrupt(false);
+    run_cycles(&mut apu, &mut register, 29830);
+    assert_eq!(apu.read(0x15) & 0x40, 0x00);
+    run_cycles(&mut apu, &mut register, 33252 - 29830);
+    assert_eq!(apu.read(0x15) & 0x40, 0x40);
+    // the dmc takes 50 cycles a bit, the region is switched after the rate is written
+    let mut apu = Apu::new();
+    apu.write(0x10, 0x0F);
+    apu.write(0x0E, 0x0F);
+    apu.set_region(Region::Pal);
+    assert_eq!(apu.channel_state(Channel::Noise).period, 0xEC2);
+    apu.write(0x13, 0x02);
+    apu.write(0x15, 0x10);
+    assert_eq!(run_cycles(&mut apu, &mut register, 1 + 350), 4);
+    assert_eq!(run_cycles(&mut apu, &mut register, 1), 4);
+    assert_eq!(run_cycles(&mut apu, &mut register, 50 * 8 - 1), 0);
+    assert_eq!(run_cycles(&mut apu, &mut register, 1), 4);
+  }
+
+  #[test]
+  fn test_dmc_interrupt() {
+    let mut apu = Apu::new();
+    let mut register = Register::new();
+    register.set_status_interrupt(false);
+    apu.write(0x17, 0x40);
+    apu.write(0x10, 0x8F); // irq
+    apu.write(0x13, 0x00); // 1 byte
+    apu.write(0x15, 0x10);
+    run_cycles(&mut apu, &mut register, 1);
+    assert_eq!(apu.read(0x15) & 0x90, 0x80);
+    assert!(register.is_interrupt_irq_enabled());
+    // writing $4015 acknowledges it
+    apu.write(0x15, 0x00);
+    assert_eq!(apu.read(0x15) & 0x80, 0x00);
+  }
+
+  #[test]
+  fn test_vgm_log() {
+    let mut apu = Apu::new();
+    let mut register = Register::new();
+    apu.write(0x00, 0xBF);
+    run_cycles(&mut apu, &mut register, 100);
+    apu.start_vgm_log();
+    apu.write(0x13, 0x01); // 17 bytes
+    apu.write(0x15, 0x10);
+    run_cycles(&mut apu, &mut register, 29830);
+    apu.mark_vgm_loop();
+    let vgm = apu.stop_vgm_log().unwrap();
+    let data = &vgm[0x100..];
+    // the initial state then the sample before the $4015 write
+    assert_eq!(&data[0..3], &[0xB4, 0x00, 0xBF]);
+    let block = data.windows(3).position(|w| w == [0x67, 0x66, 0xC2]).unwrap();
+    assert_eq!(&data[block + 3..block + 9], &[19, 0, 0, 0, 0x00, 0xC0]);
+    assert_eq!(&data[block + 26..block + 29], &[0xB4, 0x15, 0x10]);
+    assert_eq!(&data[block + 29..], &[0x62, 0x66]);
+    assert!(apu.stop_vgm_log().is_none());
+  }
+
+  #[test]
+  fn test_channel_state_and_scope() {
+    let mut apu = Apu::new();
+    let mut register = Register::new();
+    apu.set_scope_length(Some(100));
+    apu.write(0x15, 0x01);
+    apu.write(0x00, 0x9F); // duty 2, constant volume 15
+    apu.write(0x02, 0xFD);
+    apu.write(0x03, 0x08);
+    run_cycles(&mut apu, &mut register, 10000);
+    let state = apu.channel_state(Channel::Pulse1);
+    assert!(state.is_enabled);
+    assert_eq!(state.period, 0xFD);
+    assert_eq!(state.duty, Some(2));
+    assert_eq!(state.volume, 15);
+    assert_eq!(state.length, 254);
+    assert!(state.envelope.unwrap().is_constant);
+    assert_eq!(apu.channel_state(Channel::Noise).length, 0);
+    let samples = apu.scope_samples(Channel::Pulse1).unwrap();
+    assert_eq!(samples.len(), 100);
+    assert!(samples.iter().any(|&s| s > 0.1) && samples.contains(&0.0));
+    // period 0 triangle stays at the middle
+    let triangle = apu.scope_samples(Channel::Triangle).unwrap();
+    assert!(triangle.iter().all(|&s| s == triangle[0]));
+    apu.set_scope_length(None);
+    assert!(apu.scope_samples(Channel::Pulse1).is_none());
+  }
+
+  #[test]
+  fn test_set_filters() {
+    let run = |filters: &[FilterKind]| {
+      let mut apu = Apu::new();
+      let mut register = Register::new();
+      apu.set_filters(filters);
+      apu.write(0x15, 0x01);
+      apu.write(0x00, 0x9F);
+      apu.write(0x02, 0xFD);
+      apu.write(0x03, 0x08);
+      run_cycles(&mut apu, &mut register, 30000);
+      apu.mixer.end_frame();
+      apu.mixer.samples().to_vec()
+    };
+    let nes = run(&NES_FILTERS);
+    let raw = run(&[]);
+    let low_pass = run(&[FilterKind::LowPass(1000.0)]);
+    assert_eq!(nes.len(), raw.len());
+    assert_ne!(nes, raw);
+    assert_ne!(low_pass, raw);
+    // without the high-pass filters the square stays above the center, only the band-limited edges ring below it
+    assert!(*raw.iter().min().unwrap() > -1000);
+    assert!(*nes.iter().min().unwrap() < -2000);
+    // the low-pass rounds off the edges, so no sample jumps as far as the raw ones
+    let max_step = |samples: &[i16]| samples.windows(2).map(|w| (w[1] as i32 - w[0] as i32).abs()).max().unwrap();
+    assert!(max_step(&low_pass) < max_step(&raw));
+  }
+}
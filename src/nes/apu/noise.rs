@@ -1,4 +1,5 @@
 use super::constants::*;
+use super::channel::{ChannelState, EnvelopeState};
 use super::super::types::{Data, Addr};
 
 #[derive(Debug)]
@@ -10,6 +11,8 @@ pub struct Noise {
   // 0x0e
   mode_flag: bool,
   timer_period: usize,
+  period_index: usize,
+  periods: &'static [u16], // of the region
   // 0x0f
   length_counter: usize,
 
@@ -21,13 +24,6 @@ pub struct Noise {
   enabled: bool,
 }
 
-extern "C" {
-  fn set_noise_frequency(freq: f32);
-  fn set_noise_volume(volume: f32);
-  fn stop_noise();
-  fn start_noise();
-}
-
 impl Noise {
   pub fn new() -> Self {
     Noise {
@@ -35,7 +31,9 @@ impl Noise {
       is_envelope_enabled: false,
       envelope_period_and_volume: 0x0F,
       mode_flag: false, // T->short, F->long
-      timer_period: 0x00,
+      timer_period: NOISE_TIMER_PERIOD_TABLE[0] as usize,
+      period_index: 0,
+      periods: NOISE_TIMER_PERIOD_TABLE,
       length_counter: 0x00,
 
       shift_register: 0x01,
@@ -53,77 +51,77 @@ impl Noise {
         self.is_length_counter_enabled = data & 0x20 == 0x00;
         self.is_envelope_enabled = data & 0x10 == 0x00;
         self.envelope_period_and_volume = data as usize & 0x0F;
-        self.is_envelope_start = true;
-        self.set_volume();
       }
       0x02 => {
         self.mode_flag = data & 0x80 == 0x80;
-        self.set_frequency(data);
-        self.timer_period = data as usize & 0x0F; //timer period. current not uesd. it is used in step timer.
+        self.period_index = data as usize & 0x0F;
+        self.timer_period = self.periods[self.period_index] as usize;
       }
       0x03 => {
-        self.length_counter = COUNTER_TABLE[(data as usize & 0xF8) >> 3] as usize;
+        if self.enabled {
+          self.length_counter = COUNTER_TABLE[(data as usize & 0xF8) >> 3] as usize;
+        }
         self.is_envelope_start = true;
-        self.set_volume();
       }
       _ => ()
     }
   }
 
-  pub fn start(&self) {
-    unsafe { start_noise() };
-  }
-
-  pub fn stop(&self) {
-      unsafe { stop_noise() };
+  // the period of the last $400E write follows the table
+  pub fn set_periods(&mut self, periods: &'static [u16]) {
+    self.periods = periods;
+    self.timer_period = periods[self.period_index] as usize;
   }
 
   pub fn enable(&mut self) {
     self.enabled = true;
-    self.start();
   }
 
   pub fn disable(&mut self) {
-      self.enabled = false;
-      self.stop();
-  }
-
-  fn set_volume(&self) {
-    unsafe {
-      set_noise_volume(self.get_volume())
-    }
+    self.enabled = false;
+    self.length_counter = 0;
   }
 
   pub fn has_count_end(&self) -> bool {
     self.length_counter == 0
   }
 
-  fn get_volume(&self) -> f32 {
-    // let vol = if !self.enabled || self.length_counter == 0 || self.shift_register & 0x01 == 0x01 {
-    //   0
-    let vol = if !self.enabled || self.length_counter == 0 {
-      0
-    } else{
-      if self.is_envelope_enabled {
-        self.envelope_volume
-      } else {
-        self.envelope_period_and_volume
-      }
+  pub fn state(&self) -> ChannelState {
+    ChannelState {
+      is_enabled: self.enabled,
+      period: self.timer_period as u16,
+      duty: None,
+      volume: if self.length_counter == 0 { 0 } else { self.volume() },
+      length: self.length_counter as u16,
+      envelope: Some(EnvelopeState {
+        is_constant: !self.is_envelope_enabled,
+        is_loop: !self.is_length_counter_enabled,
+        period: self.envelope_period_and_volume as u8,
+        decay: self.envelope_volume as u8,
+      }),
+    }
+  }
+
+  fn volume(&self) -> Data {
+    let vol = if self.is_envelope_enabled {
+      self.envelope_volume
+    } else {
+      self.envelope_period_and_volume
     };
-    vol as f32 / (GROBAL_GAIN)
+    vol as Data
   }
 
-  fn set_frequency(&self, data: Data) {
-    unsafe {
-      set_noise_frequency(CPU_CLOCK as f32 /
-                            NOISE_TIMER_PERIOD_TABLE[data as usize & 0x0F] as f32 /
-                            2f32); //?
+  // 0 - 15
+  pub fn output(&self) -> Data {
+    if self.length_counter == 0 || self.shift_register & 0x01 == 0x01 {
+      return 0;
     }
+    self.volume()
   }
+
   // step envelope
   pub fn update_envelope(&mut self) {
     self.step_envelope();
-    self.set_volume();
   }
 
   // step length
@@ -131,12 +129,10 @@ impl Noise {
     self.step_length();
   }
 
-  // not use.
-  // shift_register used to random generator.
-  // current random noise generated by WebAudio
+  // clocked every cpu cycle, the period table is in cpu cycles
   pub fn step_timer(&mut self) {
     if self.timer_counter == 0 {
-      self.timer_counter = self.timer_period;
+      self.timer_counter = self.timer_period - 1;
       let shift = if self.mode_flag {
         6
       } else {
@@ -144,7 +140,7 @@ impl Noise {
       };
       let b1 = self.shift_register & 0x01;
       let b2 = (self.shift_register >> shift) & 0x01;
-      self.shift_register >>= shift;
+      self.shift_register >>= 1;
       self.shift_register |= (b1^b2) << 14
     } else {
       self.timer_counter -= 1;
@@ -163,7 +159,6 @@ impl Noise {
         self.envelope_volume -= 1;
       } else if !self.is_length_counter_enabled {
         self.envelope_volume = 0x0F;
-        self.stop();
       }
       self.envelope_generator_counter = self.envelope_period_and_volume;
     }
@@ -174,4 +169,23 @@ impl Noise {
       self.length_counter -= 1;
     }
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_shift_register() {
+    let mut noise = Noise::new();
+    noise.enable();
+    noise.write(0x00, 0x3F);
+    noise.write(0x03, 0x08);
+    // bit0 is set after reset, so the first output is silent
+    assert_eq!(noise.output(), 0);
+    noise.step_timer();
+    // 0x0001 -> feedback 1 into bit 14
+    assert_eq!(noise.shift_register, 0x4000);
+    assert_eq!(noise.output(), 15);
+  }
+}
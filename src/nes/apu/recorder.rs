@@ -0,0 +1,120 @@
+use super::mixer::Mixer;
+use super::channel::CHANNELS;
+use super::wav;
+use super::super::types::Data;
+
+// WAV files of a recording
+#[derive(Debug)]
+pub struct Recording {
+  pub mix: Vec<u8>,
+  pub channels: Vec<(&'static str, Vec<u8>)>, // empty unless recorded per channel
+}
+
+// captures the output at a fixed rate, apart from the host rate control
+#[derive(Debug)]
+pub struct Recorder {
+  sample_rate: u32,
+  mix: Mixer,
+  mix_samples: Vec<i16>,
+  channels: Vec<(Mixer, Vec<i16>)>,
+}
+
+impl Recorder {
+  pub fn new(sample_rate: u32, per_channel: bool) -> Self {
+    let channels = if per_channel {
+      CHANNELS.iter().map(|_| (Mixer::new(sample_rate as f64), Vec::new())).collect()
+    } else {
+      Vec::new()
+    };
+    Recorder {
+      sample_rate,
+      mix: Mixer::new(sample_rate as f64),
+      mix_samples: Vec::new(),
+      channels,
+    }
+  }
+
+  // called every cpu cycle with the channel outputs and the mixed level
+  pub fn push(&mut self, outputs: [Data; 5], expansion: f32, level: f32) {
+    self.mix.push(level);
+    if self.channels.is_empty() {
+      return;
+    }
+    let levels = self.mix.channel_levels(outputs, expansion);
+    for ((mixer, _), level) in self.channels.iter_mut().zip(levels.iter()) {
+      mixer.push(*level);
+    }
+  }
+
+  pub fn set_volume(&mut self, volume: f32) {
+    self.mix.set_volume(volume);
+    for (mixer, _) in self.channels.iter_mut() {
+      mixer.set_volume(volume);
+    }
+  }
+
+  // the cpu clock of the region
+  pub fn set_clock_rate(&mut self, clock_rate: f64) {
+    self.mix.set_clock_rate(clock_rate);
+    for (mixer, _) in self.channels.iter_mut() {
+      mixer.set_clock_rate(clock_rate);
+    }
+  }
+
+  pub fn end_frame(&mut self) {
+    end_frame(&mut self.mix, &mut self.mix_samples);
+    for (mixer, samples) in self.channels.iter_mut() {
+      end_frame(mixer, samples);
+    }
+  }
+
+  pub fn finish(self) -> Recording {
+    let sample_rate = self.sample_rate;
+    Recording {
+      mix: wav::encode(&self.mix_samples, sample_rate),
+      channels: CHANNELS
+        .iter()
+        .zip(self.channels.iter())
+        .map(|(channel, (_, samples))| (channel.name(), wav::encode(samples, sample_rate)))
+        .collect(),
+    }
+  }
+}
+
+fn end_frame(mixer: &mut Mixer, samples: &mut Vec<i16>) {
+  mixer.end_frame();
+  samples.extend_from_slice(mixer.samples());
+  mixer.clear();
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_per_channel() {
+    let mut recorder = Recorder::new(44100, true);
+    for i in 0..29780 {
+      // a square wave on pulse 2 only
+      let square1 = if (i / 100) % 2 == 0 { 15 } else { 0 };
+      recorder.push([0, square1, 0, 0, 0], 0.0, 0.0);
+    }
+    recorder.end_frame();
+    let recording = recorder.finish();
+    assert_eq!(recording.channels.len(), 6);
+    let is_silent = |wav: &Vec<u8>| wav[44..].iter().all(|b| *b == 0);
+    assert!(is_silent(&recording.mix));
+    assert_eq!(recording.channels[1].0, "pulse2");
+    assert!(!is_silent(&recording.channels[1].1));
+    assert!(is_silent(&recording.channels[0].1));
+    assert!(is_silent(&recording.channels[5].1));
+  }
+
+  #[test]
+  fn test_mix_only() {
+    let recorder = Recorder::new(48000, false);
+    let recording = recorder.finish();
+    assert!(recording.channels.is_empty());
+    assert_eq!(recording.mix.len(), 44);
+  }
+}
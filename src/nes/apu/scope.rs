@@ -0,0 +1,59 @@
+use super::channel::{Channel, CHANNELS};
+
+// the last samples of each channel at the output rate, for oscilloscope views
+#[derive(Debug)]
+pub struct Scope {
+  buffers: Vec<Vec<f32>>,
+  position: usize, // next to write, the oldest sample
+  phase: f64,      // output samples to the next take, in cpu cycles
+}
+
+impl Scope {
+  pub fn new(len: usize) -> Self {
+    Scope {
+      buffers: CHANNELS.iter().map(|_| vec![0.0; len.max(1)]).collect(),
+      position: 0,
+      phase: 0.0,
+    }
+  }
+
+  // called every cpu cycle with the level of each channel alone
+  pub fn push(&mut self, levels: [f32; 6], sample_rate: f64, clock_rate: f64) {
+    self.phase += sample_rate;
+    if self.phase < clock_rate {
+      return;
+    }
+    self.phase -= clock_rate;
+    for (buffer, level) in self.buffers.iter_mut().zip(levels.iter()) {
+      buffer[self.position] = *level;
+    }
+    self.position = (self.position + 1) % self.buffers[0].len();
+  }
+
+  // oldest first
+  pub fn samples(&self, channel: Channel) -> Vec<f32> {
+    let buffer = &self.buffers[channel.index()];
+    let mut samples = buffer[self.position..].to_vec();
+    samples.extend_from_slice(&buffer[..self.position]);
+    samples
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use super::super::constants::CPU_CLOCK;
+
+  #[test]
+  fn test_ring_buffer() {
+    let mut scope = Scope::new(4);
+    // 44100 Hz takes a sample every 40.6 cycles
+    for i in 0..CPU_CLOCK / 44100 * 6 {
+      let level = (i / 41) as f32;
+      scope.push([level, 0.0, 0.0, 0.0, 0.0, -level], 44100.0, CPU_CLOCK as f64);
+    }
+    assert_eq!(scope.samples(Channel::Pulse1), vec![1.0, 2.0, 3.0, 4.0]);
+    assert_eq!(scope.samples(Channel::Expansion), vec![-1.0, -2.0, -3.0, -4.0]);
+    assert_eq!(scope.samples(Channel::Noise), vec![0.0; 4]);
+  }
+}
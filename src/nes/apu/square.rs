@@ -1,231 +1,287 @@
 use super::constants::*;
+use super::channel::{ChannelState, EnvelopeState};
 use super::super::types::{Data, Addr};
 
 #[derive(Debug)]
 pub struct Square {
-  index: usize,
+  index: usize, // pulse 1 negates by one's complement
   // $4000
+  duty: usize,
   is_length_counter_enabled: bool,
   is_envelope_enabled: bool,
   is_envelope_loop_enabled: bool,
   envelope_period_and_volume: usize,
   // $4001
   is_sweep_enabled: bool,
-  sweep_unit_divider: usize,
-  is_sweep_direction_upper: bool,
+  sweep_period: usize,
+  is_sweep_negated: bool,
   sweep_shift_amount: usize,
   // $4003 &0x04 << 8 | $4002
   divider_frequency: usize,
   length_counter: usize,
 
-  frequency: usize,
-  sweep_unit_counter: usize,
+  timer_counter: usize,
+  sequence_step: usize,
+  sweep_divider: usize,
+  is_sweep_reload: bool,
+  is_envelope_start: bool,
   envelope_generator_counter: usize,
   envelope_volume: usize,
-  is_sweep_overflowed: bool,
   enabled: bool,
-  playing: bool,
-}
-
-extern "C" {
-  fn start_oscillator(index: usize);
-  fn stop_oscillator(index: usize);
-  fn set_oscillator_frequency(index: usize, freq: usize);
-  fn change_oscillator_frequency(index: usize, freq: usize);
-  fn set_oscillator_volume(index: usize, volume: f32);
-  fn set_oscillator_duty(index: usize, duty: f32);
-
 }
 
 impl Square {
   pub fn new(index: usize) -> Self {
     Square {
       index,
+      duty: 0,
       is_length_counter_enabled: false,
       is_envelope_enabled: false,
       is_envelope_loop_enabled: false,
       envelope_period_and_volume: 0x0F,
       is_sweep_enabled: false,
-      sweep_unit_divider: 1,
-      is_sweep_direction_upper: true,
+      sweep_period: 0,
+      is_sweep_negated: false,
       sweep_shift_amount: 0,
       divider_frequency: 1,
       length_counter: 0,
 
-      frequency: 0,
-      sweep_unit_counter: 0,
+      timer_counter: 0,
+      sequence_step: 0,
+      sweep_divider: 0,
+      is_sweep_reload: false,
+      is_envelope_start: false,
       envelope_generator_counter: 0,
       envelope_volume: 0,
-      is_sweep_overflowed: false,
       enabled: false,
-      playing: false,
     }
   }
 
   pub fn write(&mut self, addr: Addr, data: Data) {
     match addr {
       0x00 => {
-        let duty = (data >> 6) & 0x3;
+        self.duty = (data as usize >> 6) & 0x3;
         self.is_envelope_loop_enabled = (data & 0x20) == 0x20;
         self.is_length_counter_enabled = !self.is_envelope_loop_enabled; // opposite loop flag
         self.is_envelope_enabled = (data & 0x10) != 0x10; //actually register keep loop is disabled on nes
         self.envelope_period_and_volume = data as usize & 0x0F;
-        unsafe {
-          set_oscillator_volume(self.index, self.get_volume());
-          set_oscillator_duty(self.index, self.get_duty(duty as usize));
-        }
       }
       0x01 => {
         self.is_sweep_enabled = data & 0x80 == 0x80;
-        self.sweep_unit_divider = ((data as usize >> 4) & 0x07) + 1;
-        self.is_sweep_direction_upper = data & 0x08 == 0x08;
+        self.sweep_period = (data as usize >> 4) & 0x07;
+        self.is_sweep_negated = data & 0x08 == 0x08;
         self.sweep_shift_amount = data as usize & 0x07;
+        self.is_sweep_reload = true;
       }
       0x02 => {
         self.divider_frequency = (self.divider_frequency & 0x700) | data as usize;
-        self.is_sweep_overflowed = false;
-        self.update_frequency();
-        self.change_frequency();
       }
       0x03 => {
         self.divider_frequency &= 0xFF;
         self.divider_frequency |= (data as usize & 0x7) << 8;
-        self.is_sweep_overflowed = false;
-        if self.is_length_counter_enabled {
-          self.length_counter = COUNTER_TABLE[(data & 0xF8) as usize >> 3] as usize / 2;
-        }
-        self.update_frequency();
-        self.sweep_unit_counter = 0;
-        // envelope
-        self.envelope_generator_counter = self.envelope_period_and_volume;
-        self.envelope_volume = 0x0F;
         if self.enabled {
-          self.start();
+          self.length_counter = COUNTER_TABLE[(data & 0xF8) as usize >> 3] as usize;
         }
+        // restart the sequencer and the envelope
+        self.sequence_step = 0;
+        self.is_envelope_start = true;
       }
       _ => ()
     }
   }
 
-  fn change_frequency(&self){
-    unsafe {
-      change_oscillator_frequency(self.index, self.frequency);
-    }
-  }
-
-  pub fn update_frequency(&mut self) {
-    self.frequency = CPU_CLOCK / ((self.divider_frequency + 1) * 16) as usize;
-  }
-
   pub fn enable(&mut self) {
     self.enabled = true;
-    self.start();
   }
 
   pub fn disable(&mut self) {
     self.enabled = false;
-    self.stop();
+    self.length_counter = 0;
   }
 
-  pub fn start(&mut self) {
-    if !self.playing {
-      self.playing = true;
-      unsafe {
-        start_oscillator(self.index);
-        set_oscillator_frequency(self.index, self.frequency);
-      };
-    } else {
-      self.change_frequency();
-    }
+  pub fn has_count_end(&self) -> bool {
+    self.length_counter == 0
   }
 
-  pub fn stop(&mut self) {
-    if self.playing {
-      self.playing = false;
-      unsafe {
-        stop_oscillator(self.index);
-      };
+  // clocked every other cpu cycle
+  pub fn step_timer(&mut self) {
+    if self.timer_counter == 0 {
+      self.timer_counter = self.divider_frequency;
+      self.sequence_step = (self.sequence_step + 1) & 0x07;
+    } else {
+      self.timer_counter -= 1;
     }
   }
 
-  pub fn has_count_end(&self) -> bool {
-    self.length_counter == 0
-  }
-
+  // length counter & sweep, clocked by half frames
   pub fn update_counters(&mut self ) {
     if self.is_length_counter_enabled && self.length_counter > 0 {
       self.length_counter -= 1;
-      if self.length_counter == 0 {
-          self.stop();
-      }
     }
+    self.step_sweep();
+  }
 
-    if !self.is_sweep_enabled || !self.playing {
-      return;
-    };
+  // ref. http://wiki.nesdev.com/w/index.php/APU_Sweep
+  fn step_sweep(&mut self) {
+    if self.sweep_divider == 0 && self.is_sweep_enabled && self.sweep_shift_amount > 0 && !self.is_sweep_muted() {
+      self.divider_frequency = self.sweep_target();
+    }
+    if self.sweep_divider == 0 || self.is_sweep_reload {
+      self.sweep_divider = self.sweep_period;
+      self.is_sweep_reload = false;
+    } else {
+      self.sweep_divider -= 1;
+    }
+  }
 
-    self.sweep_unit_counter += 1;
-    if self.sweep_unit_counter % self.sweep_unit_divider == 0 {
-        self.sweep_unit_counter = 0;
-        if self.is_sweep_direction_upper {
-            self.divider_frequency = self.divider_frequency -
-                                         (self.divider_frequency >>
-                                          self.sweep_shift_amount);
-        } else {
-            self.divider_frequency = self.divider_frequency +
-                                         (self.divider_frequency >>
-                                          self.sweep_shift_amount);
-
-        };
-        if self.divider_frequency > 0x7FF || self.divider_frequency < 8 {
-          self.is_sweep_overflowed = true;
-          self.stop();
-        }else {
-          self.is_sweep_overflowed = false;
-        }
-        self.update_frequency();
-        self.change_frequency();
+  // pulse 1 adds the one's complement, pulse 2 the two's complement
+  fn sweep_target(&self) -> usize {
+    let change = self.divider_frequency >> self.sweep_shift_amount;
+    if !self.is_sweep_negated {
+      self.divider_frequency + change
+    } else if self.index == 0 {
+      self.divider_frequency.saturating_sub(change + 1)
+    } else {
+      self.divider_frequency - change
     }
   }
 
+  // the target is always checked even if the sweep is disabled
+  fn is_sweep_muted(&self) -> bool {
+    self.divider_frequency < 8 || self.sweep_target() > 0x7FF
+  }
+
   // divider Excitation
   pub fn update_envelope(&mut self) {
-    self.envelope_generator_counter -= 1;
-    if self.envelope_generator_counter <= 0 {
+    if self.is_envelope_start {
+      self.envelope_volume = 0x0F;
+      self.envelope_generator_counter = self.envelope_period_and_volume;
+      self.is_envelope_start = false;
+    } else if self.envelope_generator_counter > 0 {
+      self.envelope_generator_counter -= 1;
+    } else {
       self.envelope_generator_counter = self.envelope_period_and_volume;
       if self.envelope_volume > 0 {
         self.envelope_volume -= 1;
-      } else {
-        self.envelope_volume = if self.is_envelope_loop_enabled {
-          0x0F
-        } else {
-          0x00
-        };
+      } else if self.is_envelope_loop_enabled {
+        self.envelope_volume = 0x0F;
       }
     }
-    unsafe {
-      set_oscillator_volume(self.index, self.get_volume());
+  }
+
+  pub fn state(&self) -> ChannelState {
+    let is_silent = self.has_count_end() || self.is_sweep_muted();
+    ChannelState {
+      is_enabled: self.enabled,
+      period: self.divider_frequency as u16,
+      duty: Some(self.duty as u8),
+      volume: if is_silent { 0 } else { self.volume() },
+      length: self.length_counter as u16,
+      envelope: Some(EnvelopeState {
+        is_constant: !self.is_envelope_enabled,
+        is_loop: self.is_envelope_loop_enabled,
+        period: self.envelope_period_and_volume as u8,
+        decay: self.envelope_volume as u8,
+      }),
     }
   }
 
-  fn get_volume(&self) -> f32 {
-    let vol = if !self.enabled || self.is_sweep_overflowed { // || duty == 0
-      0
-    } else if self.is_envelope_enabled {
+  fn volume(&self) -> Data {
+    let vol = if self.is_envelope_enabled {
       self.envelope_volume
     } else {
       self.envelope_period_and_volume
     };
-    vol as f32 / (GROBAL_GAIN)
+    vol as Data
   }
 
-  pub fn get_duty(&self, duty: usize) -> f32 {
-    match duty {
-      0x00 => 0.125,
-      0x01 => 0.25,
-      0x02 => 0.5,
-      0x03 => 0.75,
-      _ => 0.0,
+  // 0 - 15
+  pub fn output(&self) -> Data {
+    if self.has_count_end() || self.is_sweep_muted() {
+      return 0;
     }
+    if SQUARE_DUTY_TABLE[self.duty][self.sequence_step] == 0 {
+      return 0;
+    }
+    self.volume()
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_duty_sequence() {
+    let mut square = Square::new(0);
+    square.enable();
+    square.write(0x00, 0x9F); // 50%, constant volume 15
+    square.write(0x02, 0x08);
+    square.write(0x03, 0x08);
+    let mut outputs = vec![];
+    for _ in 0..8 {
+      outputs.push(square.output());
+      for _ in 0..9 {
+        square.step_timer();
+      }
+    }
+    assert_eq!(outputs, vec![0, 15, 15, 15, 15, 0, 0, 0]);
+  }
+
+  #[test]
+  fn test_silent_when_disabled() {
+    let mut square = Square::new(0);
+    square.write(0x00, 0xBF);
+    square.write(0x02, 0x08);
+    square.write(0x03, 0x08);
+    assert!(square.has_count_end());
+    assert_eq!(square.output(), 0);
+  }
+
+  #[test]
+  fn test_sweep_negate() {
+    let mut pulse1 = Square::new(0);
+    let mut pulse2 = Square::new(1);
+    for square in [&mut pulse1, &mut pulse2].iter_mut() {
+      square.write(0x01, 0x89); // enabled, period 0, negate, shift 1
+      square.write(0x02, 0x00);
+      square.write(0x03, 0x01); // 0x100
+      square.update_counters();
+    }
+    assert_eq!(pulse1.divider_frequency, 0x7F);
+    assert_eq!(pulse2.divider_frequency, 0x80);
+  }
+
+  #[test]
+  fn test_sweep_mutes_when_disabled() {
+    let mut square = Square::new(1);
+    square.enable();
+    square.write(0x00, 0xDF); // 75%, the first step is high
+    square.write(0x01, 0x01); // disabled, shift 1
+    square.write(0x02, 0x56);
+    square.write(0x03, 0x0D); // 0x556 + 0x2AB > 0x7FF
+    assert_eq!(square.output(), 0);
+    square.write(0x03, 0x0C); // 0x456 + 0x22B
+    assert_eq!(square.output(), 15);
+  }
+
+  #[test]
+  fn test_sweep_reload() {
+    let mut square = Square::new(1);
+    square.write(0x02, 0x00);
+    square.write(0x03, 0x01);
+    square.write(0x01, 0xA1); // enabled, period 2, shift 1
+    // the divider is 0 at first
+    square.update_counters();
+    assert_eq!(square.divider_frequency, 0x180);
+    square.update_counters();
+    // the reload flag resets the divider to 2 instead of going 0
+    square.write(0x01, 0xA1);
+    for _ in 0..3 {
+      square.update_counters();
+    }
+    assert_eq!(square.divider_frequency, 0x180);
+    square.update_counters();
+    assert_eq!(square.divider_frequency, 0x240);
+  }
+}
@@ -1,9 +1,9 @@
 use super::constants::*;
+use super::channel::ChannelState;
 use super::super::types::{Data, Addr};
 
 #[derive(Debug)]
 pub struct Triangle {
-  index: usize,
   // 0x4008
   is_length_enabled: bool,
   counter_period: usize,
@@ -14,23 +14,14 @@ pub struct Triangle {
   counter_reload: bool,
 
   linear_counter: usize,
-  frequency: usize,
+  timer_counter: usize,
+  sequence_step: usize,
   enabled: bool,
-  playing: bool,
-}
-
-extern "C" {
-  fn start_oscillator(index: usize);
-  fn stop_oscillator(index: usize);
-  fn set_oscillator_frequency(index: usize, freq: usize);
-  fn change_oscillator_frequency(index: usize, freq: usize);
-  fn set_oscillator_volume(index: usize, volume: f32);
 }
 
 impl Triangle {
-  pub fn new(index: usize) -> Self {
+  pub fn new() -> Self {
     Triangle {
-      index,
       is_length_enabled: false,
       counter_period: 0,
       timer_period: 0,
@@ -38,9 +29,9 @@ impl Triangle {
       counter_reload: false,
 
       linear_counter: 0,
-      frequency: 0,
+      timer_counter: 0,
+      sequence_step: 0,
       enabled: false,
-      playing: false,
     }
   }
 
@@ -53,29 +44,39 @@ impl Triangle {
       0x02 => {
         self.timer_period &= 0x700;
         self.timer_period |= data as usize;
-        self.update_frequency();
-        self.change_frequency();
       }
       0x03 => {
         self.timer_period &= 0xFF;
         self.timer_period |= (data as usize & 0x7) << 8;
-        self.length_counter = COUNTER_TABLE[(data & 0xF8) as usize >> 3] as usize / 2;
-        self.update_frequency();
         if self.enabled {
-          self.start();
+          self.length_counter = COUNTER_TABLE[(data & 0xF8) as usize >> 3] as usize;
         }
         self.counter_reload = true
       }
       _ => (),
     }
   }
-  // length coutner
+
+  // clocked every cpu cycle, the sequencer only moves while both counters are non-zero
+  pub fn step_timer(&mut self) {
+    if self.timer_counter == 0 {
+      self.timer_counter = self.timer_period;
+      if self.length_counter > 0 && self.linear_counter > 0 {
+        self.sequence_step = (self.sequence_step + 1) & 0x1F;
+      }
+    } else {
+      self.timer_counter -= 1;
+    }
+  }
+
+  // length coutner, clocked by half frames
   pub fn update_counter(&mut self) {
     self.step_length();
+  }
+
+  // clocked by quarter frames
+  pub fn update_linear_counter(&mut self) {
     self.step_linear_counter();
-    if self.length_counter == 0  || self.linear_counter == 0 {
-      self.stop();
-    }
   }
 
   fn step_length(&mut self) {
@@ -100,62 +101,58 @@ impl Triangle {
     self.length_counter == 0
   }
 
-  fn update_frequency(&mut self) {
-    self.frequency = CPU_CLOCK / ((self.timer_period + 1) * 32) as usize;
-  }
-
-  fn change_frequency(&self) {
-    unsafe {
-      change_oscillator_frequency(self.index, self.frequency);
-    }
-  }
-
-  fn set_volume(&mut self){
-    unsafe { set_oscillator_volume(self.index, self.get_volume()) }
-  }
-
-  // current volume is set manually
-  // actually set automatically
-  // TODO: therefore, call stop on update_counter()
-  fn get_volume(&self) -> f32 {
-    let vol = if !self.enabled || self.length_counter == 0 || self.linear_counter == 0 {
-      0 as f32
-    } else {
-      32.0 / (16.0 / GROBAL_GAIN) as f32
-    };
-    vol
-  }
-
   pub fn enable(&mut self) {
     self.enabled = true;
-    self.start();
   }
 
   pub fn disable(&mut self) {
     self.enabled = false;
-    self.stop();
+    self.length_counter = 0;
   }
 
-  pub fn start(&mut self) {
-    if !self.playing {
-      self.playing = true;
-      unsafe {
-        start_oscillator(self.index);
-        set_oscillator_frequency(self.index, self.frequency);
-      };
-    } else {
-      self.change_frequency();
+  pub fn state(&self) -> ChannelState {
+    let is_running = self.length_counter > 0 && self.linear_counter > 0;
+    ChannelState {
+      is_enabled: self.enabled,
+      period: self.timer_period as u16,
+      duty: None,
+      volume: if is_running { 15 } else { 0 },
+      length: self.length_counter as u16,
+      envelope: None,
     }
-    self.set_volume();
   }
 
-  pub fn stop(&mut self) {
-    if self.playing {
-      unsafe {
-        stop_oscillator(self.index);
-        set_oscillator_volume(self.index, 0.0);
-      }
-      self.playing = false
+  // 0 - 15, holds the last step when the counters are silenced
+  pub fn output(&self) -> Data {
+    if self.timer_period < 2 {
+      // ultrasonic, real hardware averages to the middle
+      return 7;
     }
+    TRIANGLE_SEQUENCE[self.sequence_step]
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_sequence_needs_counters() {
+    let mut triangle = Triangle::new();
+    triangle.enable();
+    triangle.write(0x02, 0x10);
+    triangle.write(0x03, 0x08);
+    assert_eq!(triangle.output(), 15);
+    for _ in 0..0x11 {
+      triangle.step_timer();
+    }
+    // linear counter is not loaded yet
+    assert_eq!(triangle.output(), 15);
+    triangle.write(0x00, 0x7F);
+    triangle.update_linear_counter();
+    for _ in 0..0x11 {
+      triangle.step_timer();
+    }
+    assert_eq!(triangle.output(), 14);
+  }
+}
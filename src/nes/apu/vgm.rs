@@ -0,0 +1,230 @@
+use super::super::types::{Data, Addr};
+
+// ref. https://vgmrips.net/wiki/VGM_Specification
+const VERSION: u32 = 0x171;
+const HEADER_SIZE: usize = 0x100;
+const VGM_RATE: u64 = 44100;
+const FDS_FLAG: u32 = 0x8000_0000;
+const DATA_BLOCK_NES_RAM: Data = 0xC2;
+
+// logs the apu register writes as VGM commands
+#[derive(Debug)]
+pub struct VgmLogger {
+  start_cycle: u64,
+  cpu_clock: u64,
+  frame_rate: u32,
+  commands: Vec<u8>,
+  samples: u64,       // waited so far
+  last_write: usize,  // position of the last register write
+  loop_point: Option<(usize, u64)>, // (position, samples)
+  has_fds: bool,
+  // dmc samples ($8000 - $FFFF) already in the player ram
+  dmc_memory: Vec<Option<Data>>,
+  pending_samples: Vec<(usize, Addr, Addr)>, // (position, address, length)
+}
+
+impl VgmLogger {
+  pub fn new(cycle: u64, cpu_clock: usize, frame_rate: u32) -> Self {
+    VgmLogger {
+      start_cycle: cycle,
+      cpu_clock: cpu_clock as u64,
+      frame_rate,
+      commands: Vec::new(),
+      samples: 0,
+      last_write: 0,
+      loop_point: None,
+      has_fds: false,
+      dmc_memory: vec![None; 0x8000],
+      pending_samples: Vec::new(),
+    }
+  }
+
+  // $4000 - $4017, addr is from $4000
+  pub fn write(&mut self, cycle: u64, addr: Addr, data: Data) {
+    self.write_register(cycle, addr as Data, data);
+  }
+
+  // FDS sound, $4023 and $4040 - $409F
+  pub fn write_expansion(&mut self, cycle: u64, addr: Addr, data: Data) {
+    let register = match addr {
+      0x4023 => 0x3F,
+      0x4040..=0x407F => (addr - 0x4000) as Data,
+      0x4080..=0x409E => (addr - 0x4080 + 0x20) as Data,
+      _ => return,
+    };
+    self.has_fds = true;
+    self.write_register(cycle, register, data);
+  }
+
+  fn write_register(&mut self, cycle: u64, register: Data, data: Data) {
+    self.wait_until(cycle);
+    self.last_write = self.commands.len();
+    self.commands.extend_from_slice(&[0xB4, register, data]);
+  }
+
+  // the dmc started the sample by the last write ($4015) or by looping
+  pub fn request_sample(&mut self, by_write: bool, address: Addr, length: Addr) {
+    let position = if by_write { self.last_write } else { self.commands.len() };
+    self.pending_samples.push((position, address, length));
+  }
+
+  pub fn has_pending_samples(&self) -> bool {
+    !self.pending_samples.is_empty()
+  }
+
+  // put the sample bytes the player does not have yet before the command started it
+  pub fn fill_samples<F: FnMut(Addr) -> Data>(&mut self, mut read: F) {
+    let mut pending: Vec<_> = self.pending_samples.drain(..).collect();
+    pending.sort_by_key(|p| std::cmp::Reverse(p.0));
+    for (position, address, length) in pending {
+      let mut bytes = Vec::with_capacity(length as usize);
+      let mut addr = address;
+      for _ in 0..length {
+        bytes.push(read(addr));
+        addr = if addr == 0xFFFF { 0x8000 } else { addr + 1 };
+      }
+      let is_loaded = bytes.iter().enumerate().all(|(i, data)| {
+        self.dmc_memory[Self::memory_index(address, i)] == Some(*data)
+      });
+      if is_loaded {
+        continue;
+      }
+      for (i, data) in bytes.iter().enumerate() {
+        self.dmc_memory[Self::memory_index(address, i)] = Some(*data);
+      }
+      // a sample crossing $FFFF continues from $8000
+      let head = bytes.len().min(0x10000 - address as usize);
+      let mut block = Self::data_block(address, &bytes[..head]);
+      if head < bytes.len() {
+        block.extend(Self::data_block(0x8000, &bytes[head..]));
+      }
+      self.insert(position, &block);
+    }
+  }
+
+  fn memory_index(address: Addr, offset: usize) -> usize {
+    (address as usize - 0x8000 + offset) % 0x8000
+  }
+
+  fn data_block(address: Addr, bytes: &[Data]) -> Vec<u8> {
+    let mut block = vec![0x67, 0x66, DATA_BLOCK_NES_RAM];
+    block.extend_from_slice(&(bytes.len() as u32 + 2).to_le_bytes());
+    block.extend_from_slice(&address.to_le_bytes());
+    block.extend_from_slice(bytes);
+    block
+  }
+
+  fn insert(&mut self, position: usize, bytes: &[u8]) {
+    self.commands.splice(position..position, bytes.iter().copied());
+    if let Some((loop_position, _)) = self.loop_point.as_mut() {
+      if *loop_position >= position {
+        *loop_position += bytes.len();
+      }
+    }
+    for pending in self.pending_samples.iter_mut() {
+      if pending.0 >= position {
+        pending.0 += bytes.len();
+      }
+    }
+  }
+
+  // playback loops back to here from the end
+  pub fn mark_loop(&mut self, cycle: u64) {
+    self.wait_until(cycle);
+    self.loop_point = Some((self.commands.len(), self.samples));
+  }
+
+  fn wait_until(&mut self, cycle: u64) {
+    let target = (cycle - self.start_cycle) * VGM_RATE / self.cpu_clock;
+    let mut samples = target.saturating_sub(self.samples);
+    self.samples += samples;
+    while samples > 0 {
+      match samples {
+        735 => self.commands.push(0x62),
+        882 => self.commands.push(0x63),
+        1..=16 => self.commands.push(0x70 + samples as u8 - 1),
+        _ => {
+          let n = samples.min(0xFFFF) as u16;
+          self.commands.push(0x61);
+          self.commands.extend_from_slice(&n.to_le_bytes());
+          samples -= n as u64;
+          continue;
+        }
+      }
+      samples = 0;
+    }
+  }
+
+  pub fn finish(mut self, cycle: u64) -> Vec<u8> {
+    self.wait_until(cycle);
+    self.commands.push(0x66);
+    let mut header = vec![0u8; HEADER_SIZE];
+    let mut put = |offset: usize, value: u32| header[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    put(0x00, u32::from_le_bytes(*b"Vgm "));
+    put(0x04, (HEADER_SIZE + self.commands.len() - 4) as u32);
+    put(0x08, VERSION);
+    put(0x18, self.samples as u32);
+    if let Some((position, samples)) = self.loop_point {
+      put(0x1C, (HEADER_SIZE + position - 0x1C) as u32);
+      put(0x20, (self.samples - samples) as u32);
+    }
+    put(0x24, self.frame_rate);
+    put(0x34, (HEADER_SIZE - 0x34) as u32);
+    put(0x84, self.cpu_clock as u32 | if self.has_fds { FDS_FLAG } else { 0 });
+    header.extend_from_slice(&self.commands);
+    header
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use super::super::constants::CPU_CLOCK;
+
+  fn read_u32(vgm: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([vgm[offset], vgm[offset + 1], vgm[offset + 2], vgm[offset + 3]])
+  }
+
+  #[test]
+  fn test_header_and_commands() {
+    let mut logger = VgmLogger::new(1000, CPU_CLOCK, 60);
+    logger.write(1000, 0x00, 0xBF);
+    logger.mark_loop(1000 + 29830);
+    logger.write(1000 + 29830, 0x17, 0x40);
+    logger.write_expansion(1000 + 29830, 0x4080, 0x80);
+    logger.write_expansion(1000 + 29830, 0x5000, 0x01);
+    let vgm = logger.finish(1000 + CPU_CLOCK as u64);
+    assert_eq!(&vgm[0..4], b"Vgm ");
+    assert_eq!(read_u32(&vgm, 0x04) as usize, vgm.len() - 4);
+    assert_eq!(read_u32(&vgm, 0x08), 0x171);
+    assert_eq!(read_u32(&vgm, 0x18), 44100);
+    assert_eq!(read_u32(&vgm, 0x84), CPU_CLOCK as u32 | FDS_FLAG);
+    let data = 0x34 + read_u32(&vgm, 0x34) as usize;
+    assert_eq!(&vgm[data..data + 4], &[0xB4, 0x00, 0xBF, 0x62]);
+    assert_eq!(0x1C + read_u32(&vgm, 0x1C) as usize, data + 4);
+    assert_eq!(read_u32(&vgm, 0x20), 44100 - 735);
+    assert_eq!(&vgm[data + 4..data + 10], &[0xB4, 0x17, 0x40, 0xB4, 0x20, 0x80]);
+    // 43365 samples
+    assert_eq!(&vgm[data + 10..], &[0x61, 0x65, 0xA9, 0x66]);
+  }
+
+  #[test]
+  fn test_dmc_samples() {
+    let mut logger = VgmLogger::new(0, CPU_CLOCK, 60);
+    logger.write(0, 0x12, 0xFF);
+    logger.write(0, 0x15, 0x10);
+    logger.request_sample(true, 0xFFC0, 0x41);
+    logger.fill_samples(|addr| (addr & 0xFF) as Data);
+    // looping the same bytes needs no block
+    logger.request_sample(false, 0xFFC0, 0x41);
+    logger.fill_samples(|addr| (addr & 0xFF) as Data);
+    let vgm = logger.finish(0);
+    let data = &vgm[HEADER_SIZE..];
+    assert_eq!(&data[0..3], &[0xB4, 0x12, 0xFF]);
+    // $FFC0 - $FFFF then $8000
+    assert_eq!(&data[3..12], &[0x67, 0x66, 0xC2, 0x42, 0x00, 0x00, 0x00, 0xC0, 0xFF]);
+    assert_eq!(data[12], 0xC0);
+    assert_eq!(&data[76..85], &[0x67, 0x66, 0xC2, 0x03, 0x00, 0x00, 0x00, 0x00, 0x80]);
+    assert_eq!(&data[85..], &[0x00, 0xB4, 0x15, 0x10, 0x66]);
+  }
+}
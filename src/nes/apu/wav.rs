@@ -0,0 +1,39 @@
+// ref. http://soundfile.sapp.org/doc/WaveFormat/
+// 16 bit mono PCM
+pub fn encode(samples: &[i16], sample_rate: u32) -> Vec<u8> {
+  let data_size = (samples.len() * 2) as u32;
+  let mut buf = Vec::with_capacity(44 + data_size as usize);
+  buf.extend_from_slice(b"RIFF");
+  buf.extend_from_slice(&(36 + data_size).to_le_bytes());
+  buf.extend_from_slice(b"WAVE");
+  buf.extend_from_slice(b"fmt ");
+  buf.extend_from_slice(&16u32.to_le_bytes());
+  buf.extend_from_slice(&1u16.to_le_bytes()); // PCM
+  buf.extend_from_slice(&1u16.to_le_bytes()); // mono
+  buf.extend_from_slice(&sample_rate.to_le_bytes());
+  buf.extend_from_slice(&(sample_rate * 2).to_le_bytes()); // byte rate
+  buf.extend_from_slice(&2u16.to_le_bytes()); // block align
+  buf.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+  buf.extend_from_slice(b"data");
+  buf.extend_from_slice(&data_size.to_le_bytes());
+  for sample in samples {
+    buf.extend_from_slice(&sample.to_le_bytes());
+  }
+  buf
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_encode() {
+    let wav = encode(&[0x0102, -1], 44100);
+    assert_eq!(wav.len(), 48);
+    assert_eq!(&wav[0..4], b"RIFF");
+    assert_eq!(&wav[4..8], &40u32.to_le_bytes());
+    assert_eq!(&wav[24..28], &44100u32.to_le_bytes());
+    assert_eq!(&wav[40..44], &4u32.to_le_bytes());
+    assert_eq!(&wav[44..], &[0x02, 0x01, 0xFF, 0xFF]);
+  }
+}
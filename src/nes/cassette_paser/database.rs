@@ -0,0 +1,162 @@
+use std::fmt;
+use super::super::hash;
+use super::super::types::Data;
+use super::{Cassette, Region};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RomHash {
+  pub crc32: u32,
+  pub sha1: [Data; 20],
+}
+
+impl RomHash {
+  // Hash of PRG-ROM followed by CHR-ROM (no header), the key used by rom databases.
+  // Disk images are hashed by their sides instead of the BIOS.
+  pub fn of(cassette: &Cassette) -> Self {
+    let mut buf = if cassette.disk_sides.is_empty() {
+      cassette.program_rom.clone()
+    } else {
+      cassette.disk_sides.concat()
+    };
+    if !cassette.is_character_ram {
+      buf.extend_from_slice(&cassette.character_ram);
+    }
+    RomHash {
+      crc32: hash::crc32(&buf),
+      sha1: hash::sha1(&buf),
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct GameEntry {
+  pub crc32: u32,
+  pub sha1: [Data; 20],
+  pub name: &'static str,
+  pub mapper: u16,
+  pub submapper: Data,
+  pub is_horizontal_mirror: Option<bool>, // None: mirroring is controlled by the mapper
+  pub has_battery: bool,
+  pub region: Region,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeaderOverride {
+  Mapper { header: u16, database: u16 },
+  Submapper { header: Data, database: Data },
+  HorizontalMirror { header: bool, database: bool },
+  Battery { header: bool, database: bool },
+  Region { header: Region, database: Region },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DatabaseMatch {
+  pub name: &'static str,
+  pub overrides: Vec<HeaderOverride>,
+}
+
+impl fmt::Display for DatabaseMatch {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "rom database: {}", self.name)?;
+    if self.overrides.is_empty() {
+      return write!(f, " (header is correct)");
+    }
+    for o in self.overrides.iter() {
+      write!(f, ", {:?}", o)?;
+    }
+    Ok(())
+  }
+}
+
+// Keyed by crc32 and sha1 of PRG-ROM + CHR-ROM, both have to match.
+// The bundled test roms only, game entries need hashes checked against a dump database.
+// ref. https://wiki.nesdev.com/w/index.php/NES_2.0_submappers
+pub static GAME_DATABASE: &[GameEntry] = &[
+  GameEntry { crc32: 0x158B_0388, sha1: [0x41, 0x31, 0x30, 0x7F, 0x0F, 0x69, 0xF2, 0xA5, 0xC5, 0x4B, 0x7D, 0x43, 0x83, 0x28, 0xC5, 0xB2, 0xA5, 0xED, 0x08, 0x20], name: "nestest", mapper: 0, submapper: 0, is_horizontal_mirror: Some(true), has_battery: false, region: Region::Ntsc },
+  GameEntry { crc32: 0x8031_DAAD, sha1: [0x5A, 0x94, 0x2A, 0x78, 0xAE, 0x04, 0xE9, 0x3A, 0x22, 0x86, 0x1B, 0x48, 0xE1, 0xC8, 0x1D, 0xC5, 0x68, 0xA0, 0x3F, 0xEA], name: "mmc3_test 1-clocking", mapper: 4, submapper: 0, is_horizontal_mirror: None, has_battery: false, region: Region::Ntsc },
+  GameEntry { crc32: 0xBCEF_E65B, sha1: [0xF8, 0x6C, 0x9C, 0x54, 0xD3, 0x61, 0x07, 0x4B, 0x4B, 0xC7, 0xBB, 0x1E, 0xC7, 0x78, 0xF4, 0x05, 0x6C, 0x02, 0xD9, 0x43], name: "mmc3_test 2-details", mapper: 4, submapper: 0, is_horizontal_mirror: None, has_battery: false, region: Region::Ntsc },
+  GameEntry { crc32: 0x57EC_F527, sha1: [0x40, 0x01, 0x09, 0xB0, 0x56, 0xC7, 0x63, 0x14, 0x73, 0x9D, 0x0A, 0xE8, 0xCC, 0x04, 0x71, 0x63, 0xEF, 0x85, 0x04, 0x7D], name: "mmc3_test 3-A12_clocking", mapper: 4, submapper: 0, is_horizontal_mirror: None, has_battery: false, region: Region::Ntsc },
+  GameEntry { crc32: 0x8AD8_A602, sha1: [0x16, 0x32, 0xEB, 0xAC, 0xE9, 0x72, 0xE2, 0xD4, 0x62, 0xE4, 0x5F, 0x50, 0xDF, 0xC5, 0x70, 0x19, 0xA4, 0xE1, 0x44, 0x63], name: "mmc3_test 4-scanline_timing", mapper: 4, submapper: 0, is_horizontal_mirror: None, has_battery: false, region: Region::Ntsc },
+  GameEntry { crc32: 0x7EF5_27B5, sha1: [0xB4, 0x86, 0x9D, 0xE4, 0xFD, 0xAC, 0x0F, 0x0F, 0x86, 0xB6, 0x93, 0x6D, 0x47, 0x69, 0x2B, 0xDA, 0x81, 0xD5, 0x92, 0x05], name: "mmc3_test 5-MMC3", mapper: 4, submapper: 0, is_horizontal_mirror: None, has_battery: false, region: Region::Ntsc },
+  // Tests the MMC3A / Sharp IRQ behaviour, which is submapper 4.
+  GameEntry { crc32: 0x633A_FE6F, sha1: [0x2F, 0x29, 0xF3, 0xDC, 0x72, 0x40, 0x27, 0xFA, 0xD9, 0x26, 0xBC, 0x9D, 0x44, 0x70, 0xA4, 0x81, 0x88, 0x4E, 0x42, 0xA5], name: "mmc3_test 6-MMC3_alt", mapper: 4, submapper: 4, is_horizontal_mirror: None, has_battery: false, region: Region::Ntsc },
+  GameEntry { crc32: 0x0586_A2E4, sha1: [0x2F, 0x39, 0x04, 0xCE, 0xAB, 0xA5, 0x01, 0x91, 0x91, 0xAE, 0x1C, 0xCA, 0x7A, 0x5A, 0xCA, 0x41, 0xD9, 0xD9, 0x90, 0xD5], name: "apu lin_ctr", mapper: 0, submapper: 0, is_horizontal_mirror: Some(true), has_battery: false, region: Region::Ntsc },
+  GameEntry { crc32: 0xF319_3E1E, sha1: [0xD1, 0x8A, 0x86, 0x78, 0x90, 0x9A, 0xCE, 0x08, 0xFD, 0x30, 0x0E, 0xE0, 0x8C, 0x2C, 0x3B, 0xE5, 0xB1, 0xD8, 0x98, 0x1E], name: "apu square_timer_div2", mapper: 0, submapper: 0, is_horizontal_mirror: Some(true), has_battery: false, region: Region::Ntsc },
+  GameEntry { crc32: 0x547E_C139, sha1: [0xC4, 0x1A, 0x7A, 0xBF, 0xA9, 0xEB, 0x18, 0x44, 0x78, 0xB9, 0x60, 0x39, 0xEC, 0x57, 0xD8, 0xDF, 0x87, 0x99, 0xD7, 0x40], name: "apu sweep_cutoff", mapper: 0, submapper: 0, is_horizontal_mirror: Some(true), has_battery: false, region: Region::Ntsc },
+  GameEntry { crc32: 0xB53A_B8C0, sha1: [0x3E, 0xAA, 0xF5, 0x20, 0xEF, 0x9B, 0xFD, 0x04, 0x7F, 0xF1, 0x43, 0x00, 0xBC, 0xFE, 0x75, 0x80, 0xBD, 0x7E, 0xE8, 0xB0], name: "apu sweep_sub", mapper: 0, submapper: 0, is_horizontal_mirror: Some(true), has_battery: false, region: Region::Ntsc },
+  GameEntry { crc32: 0xD2FA_F96F, sha1: [0x2A, 0xA4, 0x55, 0x94, 0x63, 0x3D, 0x6F, 0xAB, 0x17, 0x0E, 0x63, 0x96, 0x17, 0x72, 0x84, 0x29, 0x11, 0x4B, 0x6F, 0xAB], name: "apu test_apu_env", mapper: 0, submapper: 0, is_horizontal_mirror: Some(true), has_battery: false, region: Region::Ntsc },
+];
+
+pub fn lookup(entries: &'static [GameEntry], hash: &RomHash) -> Option<&'static GameEntry> {
+  entries.iter().find(|e| e.crc32 == hash.crc32 && e.sha1 == hash.sha1)
+}
+
+// Overwrite the header derived fields by the database entry and report what differed.
+pub fn apply_entry(cassette: &mut Cassette, entry: &'static GameEntry) -> DatabaseMatch {
+  let mut overrides = vec![];
+  if cassette.mapper != entry.mapper {
+    overrides.push(HeaderOverride::Mapper { header: cassette.mapper, database: entry.mapper });
+    cassette.mapper = entry.mapper;
+  }
+  if cassette.submapper != entry.submapper {
+    overrides.push(HeaderOverride::Submapper { header: cassette.submapper, database: entry.submapper });
+    cassette.submapper = entry.submapper;
+  }
+  if let Some(is_horizontal_mirror) = entry.is_horizontal_mirror {
+    if cassette.is_horizontal_mirror != is_horizontal_mirror {
+      overrides.push(HeaderOverride::HorizontalMirror { header: cassette.is_horizontal_mirror, database: is_horizontal_mirror });
+      cassette.is_horizontal_mirror = is_horizontal_mirror;
+    }
+  }
+  if cassette.has_battery != entry.has_battery {
+    overrides.push(HeaderOverride::Battery { header: cassette.has_battery, database: entry.has_battery });
+    cassette.has_battery = entry.has_battery;
+  }
+  if cassette.region != entry.region {
+    overrides.push(HeaderOverride::Region { header: cassette.region, database: entry.region });
+    cassette.region = entry.region;
+  }
+  DatabaseMatch { name: entry.name, overrides }
+}
+
+pub fn apply(cassette: &mut Cassette) -> Option<DatabaseMatch> {
+  lookup(GAME_DATABASE, &cassette.hash).map(|entry| apply_entry(cassette, entry))
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  static TEST_DATABASE: &[GameEntry] = &[
+    GameEntry { crc32: 0x1234_5678, sha1: [0xAB; 20], name: "bad header", mapper: 4, submapper: 0, is_horizontal_mirror: Some(false), has_battery: true, region: Region::Pal },
+  ];
+
+  #[test]
+  fn test_apply_entry() {
+    let buf = std::fs::read("roms/nestest.nes").unwrap();
+    let mut cassette = super::super::parse(&buf).unwrap();
+    let entry = lookup(TEST_DATABASE, &RomHash { crc32: 0x1234_5678, sha1: [0xAB; 20] }).unwrap();
+    let m = apply_entry(&mut cassette, entry);
+    assert_eq!(m.overrides, vec![
+      HeaderOverride::Mapper { header: 0, database: 4 },
+      HeaderOverride::HorizontalMirror { header: true, database: false },
+      HeaderOverride::Battery { header: false, database: true },
+      HeaderOverride::Region { header: Region::Ntsc, database: Region::Pal },
+    ]);
+    assert_eq!(cassette.mapper, 4);
+    assert!(!cassette.is_horizontal_mirror);
+    assert!(lookup(TEST_DATABASE, &RomHash { crc32: 0, sha1: [0xAB; 20] }).is_none());
+    // a crc32 collision is not a match
+    assert!(lookup(TEST_DATABASE, &RomHash { crc32: 0x1234_5678, sha1: [0; 20] }).is_none());
+  }
+
+  #[test]
+  fn test_apply_bundled_rom() {
+    let buf = std::fs::read("roms/6-MMC3_alt.nes").unwrap();
+    let cassette = super::super::parse(&buf).unwrap();
+    let m = cassette.database_match.unwrap();
+    assert_eq!(m.name, "mmc3_test 6-MMC3_alt");
+    assert_eq!(m.overrides, vec![HeaderOverride::Submapper { header: 0, database: 4 }]);
+    assert_eq!(cassette.submapper, 4);
+  }
+}
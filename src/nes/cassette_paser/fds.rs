@@ -0,0 +1,106 @@
+use super::super::types::Data;
+use super::{identify, Cassette, RomError, RomHash, Region};
+
+// ref. https://wiki.nesdev.com/w/index.php/FDS_file_format
+pub const FDS_MAPPER: u16 = 20;
+pub const BIOS_SIZE: usize = 0x2000;
+pub const DISK_SIDE_SIZE: usize = 65500;
+const FWNES_MAGIC: &[Data] = b"FDS\x1A";
+const FWNES_HEADER_SIZE: usize = 0x10;
+const DISK_INFO_MAGIC: &[Data] = b"\x01*NINTENDO-HVC*";
+
+pub fn is_disk_image(buf: &[Data]) -> bool {
+  buf.starts_with(FWNES_MAGIC) || buf.starts_with(DISK_INFO_MAGIC)
+}
+
+// .fds with or without the fwNES header, plus the 8KB disksys.rom BIOS.
+pub fn parse(buf: &[Data], bios: &[Data]) -> Result<Cassette, RomError> {
+  if !is_disk_image(buf) {
+    return Err(RomError::InvalidMagic);
+  }
+  if bios.len() != BIOS_SIZE {
+    return Err(RomError::InvalidBios { actual: bios.len() });
+  }
+  let body = if buf.starts_with(FWNES_MAGIC) {
+    if buf.len() < FWNES_HEADER_SIZE {
+      return Err(RomError::TruncatedHeader { actual: buf.len() });
+    }
+    let declared = buf[4] as usize * DISK_SIDE_SIZE;
+    let body = &buf[FWNES_HEADER_SIZE..];
+    if body.len() < declared {
+      return Err(RomError::TruncatedProgramRom { expected: declared, actual: body.len() });
+    }
+    body
+  } else {
+    buf
+  };
+  if body.is_empty() || body.len() % DISK_SIDE_SIZE != 0 {
+    return Err(RomError::SizeMismatch {
+      expected: (body.len() / DISK_SIDE_SIZE).max(1) * DISK_SIDE_SIZE,
+      actual: body.len(),
+    });
+  }
+  let disk_sides: Vec<Vec<Data>> = body.chunks(DISK_SIDE_SIZE).map(|s| s.to_vec()).collect();
+  if let Some(side) = disk_sides.iter().position(|s| !s.starts_with(DISK_INFO_MAGIC)) {
+    return Err(RomError::InvalidDiskSide(side));
+  }
+  println!("disk side count is {}", disk_sides.len());
+
+  Ok(identify(Cassette {
+    is_horizontal_mirror: true,
+    character_ram: vec!(0;0x2000),
+    program_rom: bios.to_vec(),
+    mapper: FDS_MAPPER,
+    submapper: 0,
+    has_battery: false,
+    program_ram_size: 0x8000,
+    region: Region::Ntsc,
+    input_device: 0,
+    is_character_ram: true,
+    hash: RomHash { crc32: 0, sha1: [0; 20] },
+    database_match: None,
+    disk_sides,
+    nsf: None,
+  }))
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn build_side() -> Vec<Data> {
+    let mut side = DISK_INFO_MAGIC.to_vec();
+    side.resize(DISK_SIDE_SIZE, 0);
+    side
+  }
+
+  #[test]
+  fn test_parse() {
+    let bios = vec![0; BIOS_SIZE];
+    let mut buf = b"FDS\x1A\x02".to_vec();
+    buf.resize(FWNES_HEADER_SIZE, 0);
+    buf.extend(build_side());
+    buf.extend(build_side());
+    let cassette = parse(&buf, &bios).unwrap();
+    assert_eq!(cassette.mapper, FDS_MAPPER);
+    assert_eq!(cassette.disk_sides.len(), 2);
+    assert_eq!(cassette.program_rom.len(), BIOS_SIZE);
+
+    let headerless = parse(&buf[FWNES_HEADER_SIZE..], &bios).unwrap();
+    assert_eq!(headerless.disk_sides, cassette.disk_sides);
+    assert_eq!(super::super::load(&buf).unwrap_err(), RomError::MissingBios);
+  }
+
+  #[test]
+  fn test_parse_errors() {
+    let side = build_side();
+    assert_eq!(parse(&side, &[0; 16]).unwrap_err(), RomError::InvalidBios { actual: 16 });
+    assert_eq!(
+      parse(&side[..100], &[0; BIOS_SIZE]).unwrap_err(),
+      RomError::SizeMismatch { expected: DISK_SIDE_SIZE, actual: 100 }
+    );
+    let mut buf = side.clone();
+    buf.extend(vec![0; DISK_SIDE_SIZE]);
+    assert_eq!(parse(&buf, &[0; BIOS_SIZE]).unwrap_err(), RomError::InvalidDiskSide(1));
+  }
+}
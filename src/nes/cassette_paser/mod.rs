@@ -1,42 +1,329 @@
-use std::str;
-use super::types::{Data};
+mod database;
+mod unif;
+pub mod fds;
+pub mod nsf;
+
+use std::fmt;
+use super::types::{Data, Addr};
+use super::patch::PatchError;
+pub use self::database::{DatabaseMatch, RomHash};
+pub use self::nsf::NsfInfo;
 
 const NES_HEADER_SIZE: usize = 0x0010;
+const TRAINER_SIZE: usize = 0x0200;
 const PROGRAM_ROM_SIZE: usize = 0x4000;
 const CHARACTER_ROM_SIZE: usize = 0x2000;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Region {
+  Ntsc,
+  Pal,
+  Multi,
+  Dendy,
+}
+
 #[derive(Debug)]
 pub struct Cassette {
   pub is_horizontal_mirror: bool,
   pub character_ram: Vec<Data>,
   pub program_rom: Vec<Data>,
-  pub mapper: Data,
+  pub mapper: u16,
+  pub submapper: Data,
+  pub has_battery: bool,
+  pub program_ram_size: usize,
+  pub region: Region,
+  pub input_device: Data, // NES 2.0 default expansion device, 0 is unspecified
+  pub is_character_ram: bool, // character_ram is blank CHR-RAM, not dumped CHR-ROM
+  pub hash: RomHash,
+  pub database_match: Option<DatabaseMatch>,
+  pub disk_sides: Vec<Vec<Data>>, // Famicom Disk System, program_rom is the BIOS
+  pub nsf: Option<NsfInfo>, // NSF player, program_rom is the tune aligned to 4KB banks
 }
 
-pub fn parse(buf: &mut [Data]) -> Cassette {
-  let name = buf[0..3].to_vec();
-  let ines = str::from_utf8(&name).unwrap();
-  if ines != "NES" {
-    panic!("Invalid *.nes file.")
-  };
+#[derive(Debug, PartialEq)]
+pub enum RomError {
+  InvalidMagic,
+  TruncatedHeader { actual: usize },
+  TruncatedTrainer { expected: usize, actual: usize },
+  TruncatedProgramRom { expected: usize, actual: usize },
+  TruncatedCharacterRom { expected: usize, actual: usize },
+  SizeMismatch { expected: usize, actual: usize },
+  NoProgramRom,
+  UnsupportedConsoleType(Data),
+  TruncatedChunk { id: String, expected: usize, actual: usize },
+  MissingChunk(&'static str),
+  UnknownBoard(String),
+  UnsupportedMapper(u16),
+  Patch(PatchError),
+  MissingBios,
+  InvalidBios { actual: usize },
+  InvalidDiskSide(usize),
+  InvalidLoadAddress(Addr),
+}
+
+impl fmt::Display for RomError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      RomError::InvalidMagic => write!(f, "Invalid *.nes file: missing \"NES\\x1A\" magic."),
+      RomError::TruncatedHeader { actual } => {
+        write!(f, "Invalid *.nes file: header needs {} bytes but file has {}.", NES_HEADER_SIZE, actual)
+      }
+      RomError::TruncatedTrainer { expected, actual } => {
+        write!(f, "Truncated trainer: expected {} bytes, found {}.", expected, actual)
+      }
+      RomError::TruncatedProgramRom { expected, actual } => {
+        write!(f, "Truncated program rom: expected {} bytes, found {}.", expected, actual)
+      }
+      RomError::TruncatedCharacterRom { expected, actual } => {
+        write!(f, "Truncated character rom: expected {} bytes, found {}.", expected, actual)
+      }
+      RomError::SizeMismatch { expected, actual } => {
+        write!(f, "Header declares {} bytes but file has {}.", expected, actual)
+      }
+      RomError::NoProgramRom => write!(f, "Header declares zero program rom banks."),
+      RomError::UnsupportedConsoleType(t) => {
+        let name = match t {
+          1 => "Vs. System",
+          2 => "PlayChoice-10",
+          _ => "extended console",
+        };
+        write!(f, "Unsupported console type: {} ({}).", name, t)
+      }
+      RomError::TruncatedChunk { id, expected, actual } => {
+        write!(f, "Truncated {} chunk: expected {} bytes, found {}.", id, expected, actual)
+      }
+      RomError::MissingChunk(id) => write!(f, "Invalid *.unf file: missing {} chunk.", id),
+      RomError::UnknownBoard(name) => write!(f, "Unknown UNIF board: {}.", name),
+      RomError::UnsupportedMapper(mapper) => write!(f, "Unsupported mapper: {}.", mapper),
+      RomError::Patch(e) => write!(f, "Failed to apply patch: {}", e),
+      RomError::MissingBios => write!(f, "Famicom Disk System images need the disksys.rom BIOS."),
+      RomError::InvalidBios { actual } => {
+        write!(f, "Invalid FDS BIOS: expected {} bytes, found {}.", fds::BIOS_SIZE, actual)
+      }
+      RomError::InvalidDiskSide(side) => write!(f, "Invalid *.fds file: side {} has no disk info block.", side),
+      RomError::InvalidLoadAddress(addr) => write!(f, "Invalid *.nsf file: load address 0x{:04X} is below the program area.", addr),
+    }
+  }
+}
+
+impl std::error::Error for RomError {}
+
+impl From<PatchError> for RomError {
+  fn from(e: PatchError) -> Self {
+    RomError::Patch(e)
+  }
+}
+
+// Detect the file format by its magic bytes.
+pub fn load(buf: &[Data]) -> Result<Cassette, RomError> {
+  if buf.starts_with(unif::UNIF_MAGIC) {
+    unif::parse(buf)
+  } else if nsf::is_nsf(buf) {
+    nsf::parse(buf)
+  } else if fds::is_disk_image(buf) {
+    Err(RomError::MissingBios)
+  } else {
+    parse(buf)
+  }
+}
+
+// iNES / NES 2.0
+pub fn parse(buf: &[Data]) -> Result<Cassette, RomError> {
+  if buf.len() < 4 || &buf[0..4] != b"NES\x1A" {
+    return Err(RomError::InvalidMagic);
+  }
+  if buf.len() < NES_HEADER_SIZE {
+    return Err(RomError::TruncatedHeader { actual: buf.len() });
+  }
   let program_rom_pages = buf[4] as usize;
   println!("program rom size is {}", program_rom_pages);
+  if program_rom_pages == 0 {
+    return Err(RomError::NoProgramRom);
+  }
   let character_rom_pages = buf[5] as usize;
   println!("character rom size is {}", character_rom_pages);
+  let is_nes2 = buf[7] & 0x0C == 0x08;
   let is_horizontal_mirror = (buf[6] & 0x01) != 0x01;
-  let mapper = ((buf[6] & 0xF0) >> 4) | buf[7] & 0xF0;
+  let has_battery = buf[6] & 0x02 == 0x02;
+  // Old dumps often have garbage (e.g. "DiskDude!") in bytes 7-15, which corrupts the upper mapper nibble.
+  let is_dirty_ines = !is_nes2 && buf[12..16].iter().any(|&b| b != 0);
+  let console_type = buf[7] & 0x03;
+  if !is_dirty_ines && console_type != 0 {
+    return Err(RomError::UnsupportedConsoleType(console_type));
+  }
+  let mut mapper = ((buf[6] & 0xF0) >> 4) as u16;
+  if !is_dirty_ines {
+    mapper |= (buf[7] & 0xF0) as u16;
+  }
+  let (submapper, program_ram_size, region, input_device) = if is_nes2 {
+    mapper |= ((buf[8] & 0x0F) as u16) << 8;
+    let shift_size = |shift: Data| if shift == 0 { 0 } else { 64 << shift as usize };
+    let region = match buf[12] & 0x03 {
+      0 => Region::Ntsc,
+      1 => Region::Pal,
+      2 => Region::Multi,
+      _ => Region::Dendy,
+    };
+    (buf[8] >> 4, shift_size(buf[10] & 0x0F) + shift_size(buf[10] >> 4), region, buf[15] & 0x3F)
+  } else {
+    let region = if !is_dirty_ines && buf[9] & 0x01 == 0x01 { Region::Pal } else { Region::Ntsc };
+    let pages = if is_dirty_ines || buf[8] == 0 { 1 } else { buf[8] as usize };
+    (0, pages * 0x2000, region, 0)
+  };
   println!("mapper type is {}", mapper);
-  let character_rom_start = NES_HEADER_SIZE + program_rom_pages * PROGRAM_ROM_SIZE;
+
+  let program_rom_start = if buf[6] & 0x04 == 0x04 {
+    let trainer_end = NES_HEADER_SIZE + TRAINER_SIZE;
+    if buf.len() < trainer_end {
+      return Err(RomError::TruncatedTrainer { expected: TRAINER_SIZE, actual: buf.len() - NES_HEADER_SIZE });
+    }
+    trainer_end
+  } else {
+    NES_HEADER_SIZE
+  };
+  let character_rom_start = program_rom_start + program_rom_pages * PROGRAM_ROM_SIZE;
+  if buf.len() < character_rom_start {
+    return Err(RomError::TruncatedProgramRom {
+      expected: program_rom_pages * PROGRAM_ROM_SIZE,
+      actual: buf.len() - program_rom_start,
+    });
+  }
   let character_rom_end = character_rom_start + character_rom_pages * CHARACTER_ROM_SIZE;
-  let c_ram = if character_rom_start != character_rom_end {
+  if buf.len() < character_rom_end {
+    return Err(RomError::TruncatedCharacterRom {
+      expected: character_rom_pages * CHARACTER_ROM_SIZE,
+      actual: buf.len() - character_rom_start,
+    });
+  }
+  // padding or a title after CHR-ROM is left out
+  if buf.len() > character_rom_end {
+    println!("ignore {} bytes after character rom", buf.len() - character_rom_end);
+  }
+
+  let is_character_ram = character_rom_start == character_rom_end;
+  let c_ram = if !is_character_ram {
     buf[character_rom_start..character_rom_end].to_vec()
   } else {
     vec!(0;0x2000)
   };
-  Cassette {
+  Ok(identify(Cassette {
     is_horizontal_mirror,
-    program_rom: buf[NES_HEADER_SIZE..character_rom_start].to_vec(),
+    program_rom: buf[program_rom_start..character_rom_start].to_vec(),
     character_ram: c_ram,
     mapper,
+    submapper,
+    has_battery,
+    program_ram_size,
+    region,
+    input_device,
+    is_character_ram,
+    hash: RomHash { crc32: 0, sha1: [0; 20] },
+    database_match: None,
+    disk_sides: vec![],
+    nsf: None,
+  }))
+}
+
+// Hash the dumped data and correct the header fields by the rom database.
+fn identify(mut cassette: Cassette) -> Cassette {
+  cassette.hash = RomHash::of(&cassette);
+  cassette.database_match = database::apply(&mut cassette);
+  cassette
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn build_rom(prg_pages: u8, chr_pages: u8, flags6: u8, flags7: u8) -> Vec<Data> {
+    let mut buf = vec![0x4E, 0x45, 0x53, 0x1A, prg_pages, chr_pages, flags6, flags7];
+    buf.resize(NES_HEADER_SIZE, 0);
+    buf.resize(NES_HEADER_SIZE + prg_pages as usize * PROGRAM_ROM_SIZE + chr_pages as usize * CHARACTER_ROM_SIZE, 0);
+    buf
+  }
+
+  #[test]
+  fn test_parse() {
+    let buf = build_rom(2, 1, 0x41, 0x00);
+    let cassette = parse(&buf).unwrap();
+    assert_eq!(cassette.mapper, 4);
+    assert!(!cassette.is_horizontal_mirror);
+    assert_eq!(cassette.program_rom.len(), 2 * PROGRAM_ROM_SIZE);
+    assert_eq!(cassette.character_ram.len(), CHARACTER_ROM_SIZE);
+    assert_eq!(cassette.program_ram_size, 0x2000);
+    assert_eq!(cassette.region, Region::Ntsc);
+  }
+
+  #[test]
+  fn test_parse_nes2_header() {
+    let mut buf = build_rom(1, 0, 0x03, 0x18);
+    buf[8] = 0x21; // submapper 2, mapper bits 8-11
+    buf[10] = 0x70; // 8KB battery-backed PRG-NVRAM
+    buf[12] = 0x01; // PAL
+    buf[15] = 0x08; // Zapper
+    let cassette = parse(&buf).unwrap();
+    assert_eq!(cassette.mapper, 0x110);
+    assert_eq!(cassette.submapper, 2);
+    assert!(cassette.has_battery);
+    assert!(cassette.is_character_ram);
+    assert_eq!(cassette.program_ram_size, 0x2000);
+    assert_eq!(cassette.region, Region::Pal);
+    assert_eq!(cassette.input_device, 0x08);
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn test_parse_dirty_ines_header() {
+    let mut buf = build_rom(1, 1, 0x10, 0x40);
+    buf[7..16].copy_from_slice(b"DiskDude!");
+    assert_eq!(parse(&buf).unwrap().mapper, 1);
+    // the console type bits of the garbage are not checked either
+    buf[7..16].copy_from_slice(b"CopyRight");
+    assert_eq!(parse(&buf).unwrap().mapper, 1);
+  }
+
+  #[test]
+  fn test_parse_bundled_roms() {
+    let roms = ["roms/nestest.nes", "roms/sample1.nes", "roms/5-MMC3.nes", "roms/apu/sweep_sub.nes"];
+    for rom in roms.iter() {
+      let buf = std::fs::read(rom).unwrap();
+      assert!(parse(&buf).is_ok(), "{}", rom);
+    }
+  }
+
+  #[test]
+  fn test_parse_invalid_magic() {
+    let mut buf = build_rom(1, 1, 0, 0);
+    buf[0] = b'X';
+    assert_eq!(parse(&buf).unwrap_err(), RomError::InvalidMagic);
+    assert_eq!(parse(&buf[0..2]).unwrap_err(), RomError::InvalidMagic);
+  }
+
+  #[test]
+  fn test_parse_truncated() {
+    let buf = build_rom(1, 1, 0, 0);
+    assert_eq!(parse(&buf[0..8]).unwrap_err(), RomError::TruncatedHeader { actual: 8 });
+    assert_eq!(
+      parse(&buf[0..0x1010]).unwrap_err(),
+      RomError::TruncatedProgramRom { expected: PROGRAM_ROM_SIZE, actual: 0x1000 }
+    );
+    assert_eq!(
+      parse(&buf[0..0x5010]).unwrap_err(),
+      RomError::TruncatedCharacterRom { expected: CHARACTER_ROM_SIZE, actual: 0x1000 }
+    );
+  }
+
+  #[test]
+  fn test_parse_trailing_data() {
+    let mut buf = build_rom(1, 1, 0, 0);
+    buf.extend_from_slice(b"title\0");
+    let cassette = parse(&buf).unwrap();
+    assert_eq!(cassette.program_rom.len(), PROGRAM_ROM_SIZE);
+    assert_eq!(cassette.character_ram.len(), CHARACTER_ROM_SIZE);
+  }
+
+  #[test]
+  fn test_parse_invalid_header_fields() {
+    assert_eq!(parse(&build_rom(0, 1, 0, 0)).unwrap_err(), RomError::NoProgramRom);
+    assert_eq!(parse(&build_rom(1, 1, 0, 0x01)).unwrap_err(), RomError::UnsupportedConsoleType(1));
+  }
+}
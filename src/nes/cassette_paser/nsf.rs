@@ -0,0 +1,339 @@
+use super::super::types::{Data, Addr, Word};
+use super::{identify, Cassette, RomError, RomHash, Region};
+
+// ref. https://wiki.nesdev.com/w/index.php/NSF
+//      https://wiki.nesdev.com/w/index.php/NSF2
+pub const NSF_MAGIC: &[Data] = b"NESM\x1A";
+// not an iNES mapper number, the player mapper is selected by Cassette::nsf
+pub const NSF_MAPPER: u16 = 0xFFFF;
+pub const NSF_HEADER_SIZE: usize = 0x80;
+const CHUNK_HEADER_SIZE: usize = 8;
+const BANK_SIZE: usize = 0x1000;
+
+// $7B expansion sound chips
+pub const CHIP_VRC6: Data = 0x01;
+pub const CHIP_VRC7: Data = 0x02;
+pub const CHIP_FDS: Data = 0x04;
+pub const CHIP_MMC5: Data = 0x08;
+pub const CHIP_N163: Data = 0x10;
+pub const CHIP_SUNSOFT5B: Data = 0x20;
+// no OPLL emulation, VRC7 channels stay silent
+const UNSUPPORTED_CHIPS: Data = CHIP_VRC7;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NsfInfo {
+  pub version: Data,
+  pub total_songs: Data,
+  pub starting_song: Data, // 1 origin
+  pub load_addr: Addr,
+  pub init_addr: Addr,
+  pub play_addr: Addr,
+  pub title: String,
+  pub artist: String,
+  pub copyright: String,
+  pub ripper: String, // NSF2 auth chunk only
+  pub play_speed_ntsc: Word, // 1/1000000 sec
+  pub play_speed_pal: Word,
+  // 4KB banks mapped to $6000 - $FFFF, the first 2 are used only by FDS tunes
+  pub banks: [Data; 10],
+  pub region: Region,
+  pub expansion: Data,
+  pub track_lengths: Vec<Option<u32>>, // msec
+  pub track_fades: Vec<Option<u32>>,   // msec
+  pub track_labels: Vec<String>,
+}
+
+impl NsfInfo {
+  pub fn has_chip(&self, chip: Data) -> bool {
+    self.expansion & chip == chip
+  }
+
+  // expansion chips the tune uses but the player can not play
+  pub fn unsupported_chips(&self) -> Data {
+    self.expansion & UNSUPPORTED_CHIPS
+  }
+
+  // cpu cycles between PLAY calls
+  pub fn play_period(&self, cpu_clock: usize) -> u32 {
+    let speed = if self.region == Region::Pal { self.play_speed_pal } else { self.play_speed_ntsc };
+    (speed as u64 * cpu_clock as u64 / 1_000_000) as u32
+  }
+}
+
+pub fn is_nsf(buf: &[Data]) -> bool {
+  buf.starts_with(NSF_MAGIC)
+}
+
+fn read_string(buf: &[Data]) -> String {
+  let s = buf.split(|&b| b == 0).next().unwrap_or(&[]);
+  String::from_utf8_lossy(s).trim().to_string()
+}
+
+fn read_strings(buf: &[Data]) -> Vec<String> {
+  let mut strings: Vec<String> = buf.split(|&b| b == 0).map(read_string).collect();
+  // a terminated list ends with an empty string
+  if buf.last() == Some(&0) {
+    strings.pop();
+  }
+  strings
+}
+
+fn read_word(buf: &[Data], pos: usize) -> Word {
+  buf[pos] as Word | (buf[pos + 1] as Word) << 8
+}
+
+// "time" and "fade" chunks, -1 is the player default
+fn read_times(buf: &[Data]) -> Vec<Option<u32>> {
+  buf.chunks(4).filter(|c| c.len() == 4).map(|c| {
+    let ms = i32::from_le_bytes([c[0], c[1], c[2], c[3]]);
+    if ms < 0 { None } else { Some(ms as u32) }
+  }).collect()
+}
+
+// NSFe style metadata chunks after the program data of NSF2
+fn read_metadata(buf: &[Data], info: &mut NsfInfo) -> Result<(), RomError> {
+  let mut pos = 0;
+  while pos < buf.len() {
+    if buf.len() < pos + CHUNK_HEADER_SIZE {
+      return Err(RomError::TruncatedChunk {
+        id: "NSF2 metadata".to_string(),
+        expected: CHUNK_HEADER_SIZE,
+        actual: buf.len() - pos,
+      });
+    }
+    let len = u32::from_le_bytes([buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]]) as usize;
+    let id = &buf[pos + 4..pos + 8];
+    let start = pos + CHUNK_HEADER_SIZE;
+    if buf.len() - start < len {
+      return Err(RomError::TruncatedChunk {
+        id: String::from_utf8_lossy(id).into_owned(),
+        expected: len,
+        actual: buf.len() - start,
+      });
+    }
+    let data = &buf[start..start + len];
+    match id {
+      b"time" => info.track_lengths = read_times(data),
+      b"fade" => info.track_fades = read_times(data),
+      b"tlbl" => info.track_labels = read_strings(data),
+      b"auth" => {
+        let strings = read_strings(data);
+        let mut fields = strings.into_iter();
+        for field in [&mut info.title, &mut info.artist, &mut info.copyright, &mut info.ripper] {
+          match fields.next() {
+            Some(s) if !s.is_empty() => *field = s,
+            _ => (),
+          }
+        }
+      }
+      b"NEND" => break,
+      _ => println!("skip NSF2 chunk {}", String::from_utf8_lossy(id)),
+    }
+    pos = start + len;
+  }
+  Ok(())
+}
+
+pub fn parse(buf: &[Data]) -> Result<Cassette, RomError> {
+  if !is_nsf(buf) {
+    return Err(RomError::InvalidMagic);
+  }
+  if buf.len() < NSF_HEADER_SIZE {
+    return Err(RomError::TruncatedHeader { actual: buf.len() });
+  }
+  let version = buf[0x05];
+  let load_addr = read_word(buf, 0x08);
+  let header_banks = &buf[0x70..0x78];
+  let is_bankswitched = header_banks.iter().any(|&b| b != 0);
+  let expansion = buf[0x7B];
+  let is_fds = expansion & CHIP_FDS == CHIP_FDS;
+  let region = match buf[0x7A] & 0x03 {
+    0x01 => Region::Pal,
+    0x02 | 0x03 => Region::Multi,
+    _ => Region::Ntsc,
+  };
+
+  // NSF2 declares the program length, metadata follows the program data
+  let program_len = if version >= 2 {
+    buf[0x7D] as usize | (buf[0x7E] as usize) << 8 | (buf[0x7F] as usize) << 16
+  } else {
+    0
+  };
+  let body = &buf[NSF_HEADER_SIZE..];
+  let (program, metadata) = if program_len == 0 {
+    (body, &body[body.len()..])
+  } else if body.len() < program_len {
+    return Err(RomError::TruncatedProgramRom { expected: program_len, actual: body.len() });
+  } else {
+    body.split_at(program_len)
+  };
+  if program.is_empty() {
+    return Err(RomError::NoProgramRom);
+  }
+
+  // Align the program data to 4KB banks
+  // Without bankswitching, data is loaded at load_addr and the banks are fixed.
+  let base: Addr = if is_fds { 0x6000 } else { 0x8000 };
+  let mut banks = [0; 10];
+  let padding = if is_bankswitched {
+    banks[2..].copy_from_slice(header_banks);
+    // FDS tunes also switch $6000 and $7000 by $5FF6 / $5FF7, initialized by bytes 6 and 7
+    banks[0] = header_banks[6];
+    banks[1] = header_banks[7];
+    (load_addr & 0x0FFF) as usize
+  } else {
+    if load_addr < base {
+      return Err(RomError::InvalidLoadAddress(load_addr));
+    }
+    let first = ((base - 0x6000) as usize) / BANK_SIZE;
+    for (i, bank) in banks.iter_mut().enumerate().skip(first) {
+      *bank = (i - first) as Data;
+    }
+    (load_addr - base) as usize
+  };
+  let mut program_rom = vec![0; padding];
+  program_rom.extend_from_slice(program);
+  let len = program_rom.len().div_ceil(BANK_SIZE) * BANK_SIZE;
+  program_rom.resize(len, 0);
+
+  let mut info = NsfInfo {
+    version,
+    total_songs: buf[0x06],
+    starting_song: buf[0x07].max(1),
+    load_addr,
+    init_addr: read_word(buf, 0x0A),
+    play_addr: read_word(buf, 0x0C),
+    title: read_string(&buf[0x0E..0x2E]),
+    artist: read_string(&buf[0x2E..0x4E]),
+    copyright: read_string(&buf[0x4E..0x6E]),
+    ripper: String::new(),
+    play_speed_ntsc: read_word(buf, 0x6E),
+    play_speed_pal: read_word(buf, 0x78),
+    banks,
+    region,
+    expansion,
+    track_lengths: vec![],
+    track_fades: vec![],
+    track_labels: vec![],
+  };
+  read_metadata(metadata, &mut info)?;
+  println!("nsf {} / {} / {}, {} songs", info.title, info.artist, info.copyright, info.total_songs);
+
+  Ok(identify(Cassette {
+    is_horizontal_mirror: true,
+    character_ram: vec!(0;0x2000),
+    program_rom,
+    mapper: NSF_MAPPER,
+    submapper: 0,
+    has_battery: false,
+    program_ram_size: if is_fds { 0xA000 } else { 0x2000 },
+    region,
+    input_device: 0,
+    is_character_ram: true,
+    hash: RomHash { crc32: 0, sha1: [0; 20] },
+    database_match: None,
+    disk_sides: vec![],
+    nsf: Some(info),
+  }))
+}
+
+#[cfg(test)]
+pub mod test {
+  use super::*;
+
+  pub fn build_nsf(load_addr: Addr, banks: &[Data; 8], program: &[Data]) -> Vec<Data> {
+    let mut buf = NSF_MAGIC.to_vec();
+    buf.extend_from_slice(&[0x01, 0x03, 0x02]);
+    buf.extend_from_slice(&load_addr.to_le_bytes());
+    buf.extend_from_slice(&0x8000u16.to_le_bytes()); // init
+    buf.extend_from_slice(&0x8003u16.to_le_bytes()); // play
+    buf.resize(0x0E, 0);
+    buf.extend_from_slice(b"Title\0");
+    buf.resize(0x2E, 0);
+    buf.extend_from_slice(b"Artist\0");
+    buf.resize(0x4E, 0);
+    buf.extend_from_slice(b"2020 Someone\0");
+    buf.resize(0x6E, 0);
+    buf.extend_from_slice(&16639u16.to_le_bytes());
+    buf.extend_from_slice(banks);
+    buf.extend_from_slice(&19997u16.to_le_bytes());
+    buf.resize(NSF_HEADER_SIZE, 0);
+    buf.extend_from_slice(program);
+    buf
+  }
+
+  fn chunk(id: &[Data], data: &[Data]) -> Vec<Data> {
+    let mut buf = (data.len() as u32).to_le_bytes().to_vec();
+    buf.extend_from_slice(id);
+    buf.extend_from_slice(data);
+    buf
+  }
+
+  #[test]
+  fn test_parse() {
+    let buf = build_nsf(0x8100, &[0; 8], &[0xEA; 0x10]);
+    let cassette = super::super::load(&buf).unwrap();
+    let info = cassette.nsf.unwrap();
+    assert_eq!(info.title, "Title");
+    assert_eq!(info.artist, "Artist");
+    assert_eq!(info.copyright, "2020 Someone");
+    assert_eq!(info.total_songs, 3);
+    assert_eq!(info.starting_song, 2);
+    assert_eq!(info.banks, [0, 0, 0, 1, 2, 3, 4, 5, 6, 7]);
+    assert_eq!(info.play_period(1789772), 29780);
+    assert_eq!(cassette.program_rom.len(), 0x1000);
+    assert_eq!(cassette.program_rom[0x100], 0xEA);
+    assert_eq!(cassette.mapper, NSF_MAPPER);
+    assert_eq!(info.unsupported_chips(), 0);
+  }
+
+  #[test]
+  fn test_unsupported_chips() {
+    let mut buf = build_nsf(0x8000, &[0; 8], &[0xEA; 0x10]);
+    buf[0x7B] = CHIP_VRC6 | CHIP_VRC7;
+    let info = parse(&buf).unwrap().nsf.unwrap();
+    assert_eq!(info.unsupported_chips(), CHIP_VRC7);
+  }
+
+  #[test]
+  fn test_parse_bankswitched() {
+    let buf = build_nsf(0x8100, &[0, 1, 2, 3, 0, 1, 2, 3], &[0xEA; 0x2000]);
+    let cassette = parse(&buf).unwrap();
+    assert_eq!(cassette.nsf.unwrap().banks, [2, 3, 0, 1, 2, 3, 0, 1, 2, 3]);
+    assert_eq!(cassette.program_rom.len(), 0x3000);
+    assert_eq!(cassette.program_rom[0x0FF], 0x00);
+    assert_eq!(cassette.program_rom[0x100], 0xEA);
+  }
+
+  #[test]
+  fn test_parse_nsf2_metadata() {
+    let mut buf = build_nsf(0x8000, &[0; 8], &[0xEA; 0x10]);
+    buf[0x05] = 0x02;
+    buf[0x7D] = 0x10;
+    let mut times = 90_000i32.to_le_bytes().to_vec();
+    times.extend_from_slice(&(-1i32).to_le_bytes());
+    buf.extend(chunk(b"time", &times));
+    buf.extend(chunk(b"tlbl", b"Opening\0Field\0"));
+    buf.extend(chunk(b"auth", b"Game\0Composer\0\0Ripper\0"));
+    buf.extend(chunk(b"NEND", &[]));
+    let info = parse(&buf).unwrap().nsf.unwrap();
+    assert_eq!(info.track_lengths, vec![Some(90_000), None]);
+    assert_eq!(info.track_labels, vec!["Opening".to_string(), "Field".to_string()]);
+    assert_eq!(info.title, "Game");
+    assert_eq!(info.artist, "Composer");
+    assert_eq!(info.copyright, "2020 Someone");
+    assert_eq!(info.ripper, "Ripper");
+  }
+
+  #[test]
+  fn test_parse_errors() {
+    let buf = build_nsf(0x8000, &[0; 8], &[]);
+    assert_eq!(parse(&buf).unwrap_err(), RomError::NoProgramRom);
+    let buf = build_nsf(0x6000, &[0; 8], &[0xEA]);
+    assert_eq!(parse(&buf).unwrap_err(), RomError::InvalidLoadAddress(0x6000));
+    let mut buf = build_nsf(0x8000, &[0; 8], &[0xEA; 0x10]);
+    buf[0x05] = 0x02;
+    buf[0x7D] = 0x20;
+    assert_eq!(parse(&buf).unwrap_err(), RomError::TruncatedProgramRom { expected: 0x20, actual: 0x10 });
+  }
+}
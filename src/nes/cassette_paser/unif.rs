@@ -0,0 +1,213 @@
+use super::super::types::Data;
+use super::{identify, Cassette, RomError, RomHash, Region};
+use super::super::mapper::is_supported;
+
+// ref. https://wiki.nesdev.com/w/index.php/UNIF
+pub const UNIF_MAGIC: &[Data] = b"UNIF";
+const UNIF_HEADER_SIZE: usize = 0x20;
+const CHUNK_HEADER_SIZE: usize = 8;
+
+// Board names without the "NES-" / "HVC-" / "UNL-" like prefix.
+// Mostly licensed boards, unlicensed and multicart ones need their mappers first.
+static BOARD_TABLE: &[(&str, u16)] = &[
+  ("NROM", 0), ("NROM-128", 0), ("NROM-256", 0), ("RROM", 0), ("RROM-128", 0),
+  ("SAROM", 1), ("SBROM", 1), ("SCROM", 1), ("SEROM", 1), ("SFROM", 1), ("SGROM", 1), ("SHROM", 1),
+  ("SJROM", 1), ("SKROM", 1), ("SLROM", 1), ("SL1ROM", 1), ("SNROM", 1), ("SOROM", 1), ("SUROM", 1), ("SXROM", 1),
+  ("UNROM", 2), ("UOROM", 2),
+  ("CNROM", 3),
+  ("TBROM", 4), ("TEROM", 4), ("TFROM", 4), ("TGROM", 4), ("TKROM", 4), ("TLROM", 4), ("TL1ROM", 4),
+  ("TR1ROM", 4), ("TSROM", 4), ("TVROM", 4),
+  ("EKROM", 5), ("ELROM", 5), ("ETROM", 5), ("EWROM", 5),
+  ("AMROM", 7), ("ANROM", 7), ("AN1ROM", 7), ("AOROM", 7),
+  ("PNROM", 9), ("PEEOROM", 9),
+  ("FJROM", 10), ("FKROM", 10),
+  ("CPROM", 13),
+  ("BNROM", 34),
+  ("GNROM", 66), ("MHROM", 66),
+  ("TLSROM", 118), ("TKSROM", 118),
+  ("TQROM", 119),
+  ("H2288", 123),
+  ("8237", 215),
+];
+
+pub fn board_to_mapper(board: &str) -> Option<u16> {
+  let name = match board.find('-') {
+    Some(i) if ["NES", "HVC", "UNL", "BMC", "BTL"].contains(&&board[..i]) => &board[i + 1..],
+    _ => board,
+  };
+  BOARD_TABLE.iter().find(|(b, _)| *b == name).map(|(_, m)| *m)
+}
+
+struct Chunk<'a> {
+  id: &'a [Data],
+  data: &'a [Data],
+}
+
+fn read_chunks(buf: &[Data]) -> Result<Vec<Chunk<'_>>, RomError> {
+  let mut chunks = vec![];
+  let mut pos = UNIF_HEADER_SIZE;
+  while pos < buf.len() {
+    if buf.len() < pos + CHUNK_HEADER_SIZE {
+      return Err(RomError::TruncatedChunk {
+        id: String::from_utf8_lossy(&buf[pos..]).into_owned(),
+        expected: CHUNK_HEADER_SIZE,
+        actual: buf.len() - pos,
+      });
+    }
+    let id = &buf[pos..pos + 4];
+    let len = u32::from_le_bytes([buf[pos + 4], buf[pos + 5], buf[pos + 6], buf[pos + 7]]) as usize;
+    let start = pos + CHUNK_HEADER_SIZE;
+    if buf.len() - start < len {
+      return Err(RomError::TruncatedChunk {
+        id: String::from_utf8_lossy(id).into_owned(),
+        expected: len,
+        actual: buf.len() - start,
+      });
+    }
+    chunks.push(Chunk { id, data: &buf[start..start + len] });
+    pos = start + len;
+  }
+  Ok(chunks)
+}
+
+// Concatenate PRG0..PRGF (or CHR0..CHRF) in bank order.
+fn concat_banks(chunks: &[Chunk], prefix: &[Data]) -> Vec<Data> {
+  let mut buf = vec![];
+  for n in b"0123456789ABCDEF".iter() {
+    for c in chunks.iter().filter(|c| &c.id[..3] == prefix && c.id[3] == *n) {
+      buf.extend_from_slice(c.data);
+    }
+  }
+  buf
+}
+
+fn find<'a>(chunks: &'a [Chunk], id: &[Data]) -> Option<&'a [Data]> {
+  chunks.iter().find(|c| c.id == id).map(|c| c.data)
+}
+
+pub fn parse(buf: &[Data]) -> Result<Cassette, RomError> {
+  if !buf.starts_with(UNIF_MAGIC) {
+    return Err(RomError::InvalidMagic);
+  }
+  if buf.len() < UNIF_HEADER_SIZE {
+    return Err(RomError::TruncatedHeader { actual: buf.len() });
+  }
+  let chunks = read_chunks(buf)?;
+
+  let board = find(&chunks, b"MAPR").ok_or(RomError::MissingChunk("MAPR"))?;
+  let board = board.split(|&b| b == 0).next().unwrap_or(&[]);
+  let board = String::from_utf8_lossy(board).trim().to_string();
+  println!("unif board is {}", board);
+  let mapper = board_to_mapper(&board).ok_or(RomError::UnknownBoard(board))?;
+  if !is_supported(mapper) {
+    return Err(RomError::UnsupportedMapper(mapper));
+  }
+
+  let program_rom = concat_banks(&chunks, b"PRG");
+  if program_rom.is_empty() {
+    return Err(RomError::NoProgramRom);
+  }
+  let character_rom = concat_banks(&chunks, b"CHR");
+  let is_character_ram = character_rom.is_empty();
+
+  // 0: horizontal, 1: vertical, others (single screen, four screen, mapper controlled) use mapper default
+  let is_horizontal_mirror = find(&chunks, b"MIRR").and_then(|d| d.first()) != Some(&1);
+  let has_battery = find(&chunks, b"BATR").and_then(|d| d.first()).is_some_and(|&b| b != 0);
+  let region = match find(&chunks, b"TVCI").and_then(|d| d.first()) {
+    Some(1) => Region::Pal,
+    Some(2) => Region::Multi,
+    _ => Region::Ntsc,
+  };
+
+  Ok(identify(Cassette {
+    is_horizontal_mirror,
+    character_ram: if is_character_ram { vec!(0;0x2000) } else { character_rom },
+    program_rom,
+    mapper,
+    submapper: 0,
+    has_battery,
+    program_ram_size: 0x2000,
+    region,
+    input_device: 0,
+    is_character_ram,
+    hash: RomHash { crc32: 0, sha1: [0; 20] },
+    database_match: None,
+    disk_sides: vec![],
+    nsf: None,
+  }))
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn chunk(id: &[Data], data: &[Data]) -> Vec<Data> {
+    let mut buf = id.to_vec();
+    buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    buf.extend_from_slice(data);
+    buf
+  }
+
+  fn build_unif(chunks: &[Vec<Data>]) -> Vec<Data> {
+    let mut buf = b"UNIF".to_vec();
+    buf.extend_from_slice(&7u32.to_le_bytes());
+    buf.resize(UNIF_HEADER_SIZE, 0);
+    for c in chunks.iter() {
+      buf.extend_from_slice(c);
+    }
+    buf
+  }
+
+  #[test]
+  fn test_parse() {
+    let buf = build_unif(&[
+      chunk(b"MAPR", b"NES-TLROM\0"),
+      chunk(b"PRG1", &[2; 0x4000]),
+      chunk(b"PRG0", &[1; 0x4000]),
+      chunk(b"CHR0", &[3; 0x2000]),
+      chunk(b"MIRR", &[1]),
+      chunk(b"BATR", &[1]),
+      chunk(b"TVCI", &[1]),
+    ]);
+    let cassette = super::super::load(&buf).unwrap();
+    assert_eq!(cassette.mapper, 4);
+    assert_eq!(cassette.program_rom.len(), 0x8000);
+    assert_eq!(cassette.program_rom[0], 1);
+    assert_eq!(cassette.program_rom[0x4000], 2);
+    assert_eq!(cassette.character_ram, vec![3; 0x2000]);
+    assert!(!cassette.is_character_ram);
+    assert!(!cassette.is_horizontal_mirror);
+    assert!(cassette.has_battery);
+    assert_eq!(cassette.region, Region::Pal);
+  }
+
+  #[test]
+  fn test_parse_errors() {
+    let buf = build_unif(&[chunk(b"PRG0", &[0; 0x10])]);
+    assert_eq!(parse(&buf).unwrap_err(), RomError::MissingChunk("MAPR"));
+
+    let buf = build_unif(&[chunk(b"MAPR", b"UNL-UNKNOWN\0"), chunk(b"PRG0", &[0; 0x10])]);
+    assert_eq!(parse(&buf).unwrap_err(), RomError::UnknownBoard("UNL-UNKNOWN".to_string()));
+
+    let buf = build_unif(&[chunk(b"MAPR", b"NES-SNROM\0"), chunk(b"PRG0", &[0; 0x10])]);
+    assert_eq!(parse(&buf).unwrap_err(), RomError::UnsupportedMapper(1));
+
+    let buf = build_unif(&[chunk(b"MAPR", b"NES-NROM-256\0")]);
+    assert_eq!(parse(&buf).unwrap_err(), RomError::NoProgramRom);
+
+    let mut buf = build_unif(&[chunk(b"MAPR", b"NES-NROM-256\0"), chunk(b"PRG0", &[0; 0x10])]);
+    buf.truncate(buf.len() - 4);
+    assert_eq!(
+      parse(&buf).unwrap_err(),
+      RomError::TruncatedChunk { id: "PRG0".to_string(), expected: 0x10, actual: 0x0C }
+    );
+  }
+
+  #[test]
+  fn test_board_to_mapper() {
+    assert_eq!(board_to_mapper("NES-SNROM"), Some(1));
+    assert_eq!(board_to_mapper("HVC-CNROM"), Some(3));
+    assert_eq!(board_to_mapper("UNL-8237"), Some(215));
+    assert_eq!(board_to_mapper("NES-NOPE"), None);
+  }
+}
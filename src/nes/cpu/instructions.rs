@@ -247,7 +247,7 @@ pub fn cpy<T: CpuRegister, U: CpuBus>(operand: Word, register: &mut T, bus: &mut
 }
 
 pub fn dec<T: CpuRegister, U: CpuBus>(operand: Word, register: &mut T, bus: &mut U) {
-  let computed = bus.read(operand) as i8 -1;
+  let computed = (bus.read(operand) as i8).wrapping_sub(1);
   register
     .update_status_negative_by(computed as Data)
     .update_status_zero_by(computed as Data);
@@ -255,7 +255,7 @@ pub fn dec<T: CpuRegister, U: CpuBus>(operand: Word, register: &mut T, bus: &mut
 }
 
 pub fn dex<T: CpuRegister>(register: &mut T) {
-  let x = register.get_X() as i8 -1;
+  let x = (register.get_X() as i8).wrapping_sub(1);
   register
     .update_status_negative_by(x as Data)
     .update_status_zero_by(x as Data)
@@ -263,7 +263,7 @@ pub fn dex<T: CpuRegister>(register: &mut T) {
 }
 
 pub fn dey<T: CpuRegister>(register: &mut T) {
-  let y = register.get_Y() as i8 -1;
+  let y = (register.get_Y() as i8).wrapping_sub(1);
   register
     .update_status_negative_by(y as Data)
     .update_status_zero_by(y as Data)
@@ -287,7 +287,7 @@ pub fn eor<T: CpuRegister, U: CpuBus>(operand: Word, register: &mut T, bus: &mut
 }
 
 pub fn inc<T: CpuRegister, U: CpuBus>(operand: Word, register: &mut T, bus: &mut U) {
-  let computed = bus.read(operand) as i8 +1;
+  let computed = (bus.read(operand) as i8).wrapping_add(1);
   register
     .update_status_negative_by(computed as Data)
     .update_status_zero_by(computed as Data);
@@ -295,7 +295,7 @@ pub fn inc<T: CpuRegister, U: CpuBus>(operand: Word, register: &mut T, bus: &mut
 }
 
 pub fn inx<T: CpuRegister>(register: &mut T) {
-  let x = register.get_X() as i8 +1;
+  let x = (register.get_X() as i8).wrapping_add(1);
   register
     .update_status_negative_by(x as Data)
     .update_status_zero_by(x as Data)
@@ -303,7 +303,7 @@ pub fn inx<T: CpuRegister>(register: &mut T) {
 }
 
 pub fn iny<T: CpuRegister>(register: &mut T) {
-  let y = register.get_Y() as i8 +1;
+  let y = (register.get_Y() as i8).wrapping_add(1);
   register
     .update_status_negative_by(y as Data)
     .update_status_zero_by(y as Data)
@@ -1,7 +1,9 @@
-use super::types::{Data, Addr};
+use super::types::{Data, Addr, Word};
 use super::ram::Ram;
 use super::ppu::Ppu;
 
+const DMA_CYCLES: Word = 513;
+
 #[derive(Debug)]
 pub struct Dma {
   start_addr_top: Data,
@@ -25,11 +27,17 @@ impl Dma {
     self.should_run
   }
 
-  pub fn run(&mut self, ram: &Ram, ppu: &mut Ppu) {
+  // returns the cpu cycles, 513 and 1 more to align to a read cycle when it starts on an odd cycle
+  pub fn run(&mut self, ram: &Ram, ppu: &mut Ppu, is_odd_cycle: bool) -> Word {
     let addr = (self.start_addr_top as Addr) << 8;
     for i in 0..0x100 {
       ppu.transfer_sprite(i, ram.read(addr + i));
     }
     self.should_run = false;
+    if is_odd_cycle {
+      DMA_CYCLES + 1
+    } else {
+      DMA_CYCLES
+    }
   }
 }
\ No newline at end of file
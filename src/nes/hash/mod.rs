@@ -0,0 +1,84 @@
+use lazy_static::lazy_static;
+use super::types::Data;
+
+lazy_static! {
+  // ref. https://www.w3.org/TR/PNG/#D-CRCAppendix
+  static ref CRC32_TABLE: Vec<u32> = (0..256u32).map(|n| {
+    (0..8).fold(n, |c, _| if c & 1 == 1 { 0xEDB8_8320 ^ (c >> 1) } else { c >> 1 })
+  }).collect();
+}
+
+// CRC-32 (IEEE 802.3) as used by zip, UPS/BPS patches and rom databases.
+pub fn crc32(buf: &[Data]) -> u32 {
+  crc32_update(0, buf)
+}
+
+// Continue a crc32 over multiple buffers: crc32_update(crc32(a), b) == crc32(a ++ b)
+pub fn crc32_update(crc: u32, buf: &[Data]) -> u32 {
+  let crc = buf.iter().fold(!crc, |c, &b| CRC32_TABLE[((c ^ b as u32) & 0xFF) as usize] ^ (c >> 8));
+  !crc
+}
+
+// ref. https://tools.ietf.org/html/rfc3174
+pub fn sha1(buf: &[Data]) -> [Data; 20] {
+  let mut h: [u32; 5] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476, 0xC3D2_E1F0];
+  let mut message = buf.to_vec();
+  let bit_len = (buf.len() as u64).wrapping_mul(8);
+  message.push(0x80);
+  while message.len() % 64 != 56 {
+    message.push(0);
+  }
+  message.extend_from_slice(&bit_len.to_be_bytes());
+
+  for chunk in message.chunks(64) {
+    let mut w = [0u32; 80];
+    for (i, word) in chunk.chunks(4).enumerate() {
+      w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+    }
+    for i in 16..80 {
+      w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+    }
+    let (mut a, mut b, mut c, mut d, mut e) = (h[0], h[1], h[2], h[3], h[4]);
+    for (i, &word) in w.iter().enumerate() {
+      let (f, k) = match i {
+        0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+        20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+        40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+        _ => (b ^ c ^ d, 0xCA62_C1D6),
+      };
+      let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(word);
+      e = d;
+      d = c;
+      c = b.rotate_left(30);
+      b = a;
+      a = temp;
+    }
+    h[0] = h[0].wrapping_add(a);
+    h[1] = h[1].wrapping_add(b);
+    h[2] = h[2].wrapping_add(c);
+    h[3] = h[3].wrapping_add(d);
+    h[4] = h[4].wrapping_add(e);
+  }
+
+  let mut digest = [0; 20];
+  for (i, v) in h.iter().enumerate() {
+    digest[i * 4..i * 4 + 4].copy_from_slice(&v.to_be_bytes());
+  }
+  digest
+}
+
+#[test]
+fn test_crc32() {
+  assert_eq!(crc32(b""), 0);
+  assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+  assert_eq!(crc32_update(crc32(b"1234"), b"56789"), 0xCBF4_3926);
+}
+
+#[test]
+fn test_sha1() {
+  assert_eq!(sha1(b"abc"), [
+    0xA9, 0x99, 0x3E, 0x36, 0x47, 0x06, 0x81, 0x6A, 0xBA, 0x3E,
+    0x25, 0x71, 0x78, 0x50, 0xC2, 0x6C, 0x9C, 0xD0, 0xD8, 0x9D,
+  ]);
+  assert_eq!(sha1(b"")[0..4], [0xDA, 0x39, 0xA3, 0xEE]);
+}
@@ -0,0 +1,292 @@
+use super::super::{Addr, Data};
+
+// ref. https://wiki.nesdev.com/w/index.php/FDS_audio
+const MASTER_VOLUME_TABLE: [u32; 4] = [36, 24, 17, 14];
+// 4 resets the modulation counter
+const MOD_TABLE_RESET: i8 = 0x7F;
+const MOD_TABLE: [i8; 8] = [0, 1, 2, 4, MOD_TABLE_RESET, -4, -2, -1];
+
+// volume ($4080) and modulation ($4084) envelope with a 12bit frequency
+#[derive(Debug)]
+struct Envelope {
+  speed: Data,
+  gain: Data,
+  is_disabled: bool, // direct gain mode
+  is_increase: bool,
+  frequency: u16,
+  timer: u32,
+}
+
+impl Envelope {
+  fn new() -> Self {
+    Envelope {
+      speed: 0,
+      gain: 0,
+      is_disabled: true,
+      is_increase: false,
+      frequency: 0,
+      timer: 0,
+    }
+  }
+
+  fn write(&mut self, addr: Addr, data: Data, master_speed: Data) {
+    match addr & 0x03 {
+      0x00 => {
+        self.speed = data & 0x3F;
+        self.is_increase = data & 0x40 == 0x40;
+        self.is_disabled = data & 0x80 == 0x80;
+        self.reset_timer(master_speed);
+        if self.is_disabled {
+          self.gain = self.speed;
+        }
+      }
+      0x02 => self.frequency = (self.frequency & 0x0F00) | data as u16,
+      0x03 => self.frequency = (self.frequency & 0x00FF) | ((data as u16 & 0x0F) << 8),
+      _ => (),
+    }
+  }
+
+  fn reset_timer(&mut self, master_speed: Data) {
+    self.timer = 8 * (self.speed as u32 + 1) * master_speed as u32;
+  }
+
+  // true when the gain is updated
+  fn step(&mut self, master_speed: Data) -> bool {
+    if self.is_disabled || master_speed == 0 {
+      return false;
+    }
+    if self.timer > 0 {
+      self.timer -= 1;
+    }
+    if self.timer > 0 {
+      return false;
+    }
+    self.reset_timer(master_speed);
+    if self.is_increase && self.gain < 32 {
+      self.gain += 1;
+    } else if !self.is_increase && self.gain > 0 {
+      self.gain -= 1;
+    }
+    true
+  }
+}
+
+#[derive(Debug)]
+pub struct FdsAudio {
+  volume: Envelope,
+  modulator: Envelope,
+  wave_table: Vec<Data>,
+  wave_position: usize,
+  wave_accumulator: u16,
+  is_wave_write_enabled: bool,
+  is_wave_halted: bool,
+  is_envelope_disabled: bool,
+  master_volume: usize,
+  master_envelope_speed: Data,
+
+  mod_table: Vec<Data>,
+  mod_table_position: usize,
+  mod_accumulator: u16,
+  mod_counter: i8,
+  is_mod_disabled: bool,
+  mod_output: i32,
+
+  output: Data,
+}
+
+impl FdsAudio {
+  pub fn new() -> Self {
+    FdsAudio {
+      volume: Envelope::new(),
+      modulator: Envelope::new(),
+      wave_table: vec![0; 0x40],
+      wave_position: 0,
+      wave_accumulator: 0,
+      is_wave_write_enabled: false,
+      is_wave_halted: true,
+      is_envelope_disabled: false,
+      master_volume: 0,
+      master_envelope_speed: 0xE8,
+
+      mod_table: vec![0; 0x40],
+      mod_table_position: 0,
+      mod_accumulator: 0,
+      mod_counter: 0,
+      is_mod_disabled: true,
+      mod_output: 0,
+
+      output: 0,
+    }
+  }
+
+  pub fn read(&self, addr: Addr) -> Data {
+    match addr {
+      0x4040..=0x407F => self.wave_table[(addr & 0x3F) as usize],
+      0x4090 => self.volume.gain | 0x40,
+      0x4092 => self.modulator.gain | 0x40,
+      _ => 0,
+    }
+  }
+
+  pub fn write(&mut self, addr: Addr, data: Data) {
+    match addr {
+      0x4040..=0x407F if self.is_wave_write_enabled => {
+        self.wave_table[(addr & 0x3F) as usize] = data & 0x3F;
+      }
+      0x4080 | 0x4082 => self.volume.write(addr, data, self.master_envelope_speed),
+      0x4083 => {
+        self.volume.write(addr, data, self.master_envelope_speed);
+        self.is_wave_halted = data & 0x80 == 0x80;
+        self.is_envelope_disabled = data & 0x40 == 0x40;
+        if self.is_wave_halted {
+          self.wave_position = 0;
+          self.wave_accumulator = 0;
+        }
+        if self.is_envelope_disabled {
+          self.volume.reset_timer(self.master_envelope_speed);
+          self.modulator.reset_timer(self.master_envelope_speed);
+        }
+      }
+      0x4084 | 0x4086 => self.modulator.write(addr, data, self.master_envelope_speed),
+      0x4085 => {
+        self.set_mod_counter(data & 0x7F);
+        self.update_mod_output();
+      }
+      0x4087 => {
+        self.modulator.write(addr, data, self.master_envelope_speed);
+        self.is_mod_disabled = data & 0x80 == 0x80;
+        if self.is_mod_disabled {
+          self.mod_accumulator = 0;
+        }
+      }
+      // the table can be written only while the modulator is halted, each write fills 2 entries
+      0x4088 if self.is_mod_disabled => {
+        self.mod_table[self.mod_table_position] = data & 0x07;
+        self.mod_table[(self.mod_table_position + 1) & 0x3F] = data & 0x07;
+        self.mod_table_position = (self.mod_table_position + 2) & 0x3F;
+      }
+      0x4089 => {
+        self.master_volume = (data & 0x03) as usize;
+        self.is_wave_write_enabled = data & 0x80 == 0x80;
+      }
+      0x408A => self.master_envelope_speed = data,
+      _ => (),
+    }
+  }
+
+  fn set_mod_counter(&mut self, data: Data) {
+    // 7bit signed
+    self.mod_counter = ((data << 1) as i8) >> 1;
+  }
+
+  // pitch offset added to the wave frequency
+  fn update_mod_output(&mut self) {
+    let pitch = self.volume.frequency as i32;
+    let counter = self.mod_counter as i32;
+    let mut temp = counter * self.modulator.gain as i32;
+    let remainder = temp & 0x0F;
+    temp >>= 4;
+    if remainder > 0 && temp & 0x80 == 0 {
+      temp += if counter < 0 { -1 } else { 2 };
+    }
+    if temp >= 192 {
+      temp -= 256;
+    } else if temp < -64 {
+      temp += 256;
+    }
+    temp *= pitch;
+    let remainder = temp & 0x3F;
+    temp >>= 6;
+    if remainder >= 32 {
+      temp += 1;
+    }
+    self.mod_output = temp;
+  }
+
+  fn step_modulator(&mut self) -> bool {
+    if self.is_mod_disabled || self.modulator.frequency == 0 {
+      return false;
+    }
+    let (acc, overflow) = self.mod_accumulator.overflowing_add(self.modulator.frequency);
+    self.mod_accumulator = acc;
+    if !overflow {
+      return false;
+    }
+    let offset = MOD_TABLE[self.mod_table[self.mod_table_position] as usize];
+    if offset == MOD_TABLE_RESET {
+      self.mod_counter = 0;
+    } else {
+      self.set_mod_counter((self.mod_counter as i16 + offset as i16) as Data & 0x7F);
+    }
+    self.mod_table_position = (self.mod_table_position + 1) & 0x3F;
+    true
+  }
+
+  // clocked every cpu cycle
+  pub fn step(&mut self) {
+    if !self.is_wave_halted && !self.is_envelope_disabled {
+      self.volume.step(self.master_envelope_speed);
+      if self.modulator.step(self.master_envelope_speed) {
+        self.update_mod_output();
+      }
+    }
+    if self.step_modulator() {
+      self.update_mod_output();
+    }
+
+    if !self.is_wave_halted && !self.is_wave_write_enabled {
+      let frequency = self.volume.frequency as i32 + self.mod_output;
+      if frequency > 0 {
+        let (acc, overflow) = self.wave_accumulator.overflowing_add(frequency as u16);
+        self.wave_accumulator = acc;
+        if overflow {
+          self.wave_position = (self.wave_position + 1) & 0x3F;
+        }
+      }
+    }
+    // the output holds the last value while the wave table is writable
+    if !self.is_wave_write_enabled {
+      let level = self.volume.gain.min(32) as u32 * MASTER_VOLUME_TABLE[self.master_volume];
+      self.output = (self.wave_table[self.wave_position] as u32 * level / 1152) as Data;
+    }
+  }
+
+  // 0 - 63
+  pub fn output(&self) -> Data {
+    self.output
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_wave_output() {
+    let mut audio = FdsAudio::new();
+    audio.write(0x4089, 0x80);
+    for i in 0..0x40 {
+      audio.write(0x4040 + i, if i < 0x20 { 0x3F } else { 0x00 });
+    }
+    audio.write(0x4089, 0x00);
+    audio.write(0x4080, 0x80 | 0x20); // direct gain 32
+    audio.write(0x4082, 0x00);
+    audio.write(0x4083, 0x04); // frequency 0x400: 64 cycles per step
+    audio.step();
+    assert_eq!(audio.output(), 63);
+    for _ in 0..(64 * 0x20) {
+      audio.step();
+    }
+    assert_eq!(audio.output(), 0);
+    assert_eq!(audio.read(0x4090), 0x60);
+  }
+
+  #[test]
+  fn test_mod_counter() {
+    let mut audio = FdsAudio::new();
+    audio.write(0x4085, 0x7F);
+    assert_eq!(audio.mod_counter, -1);
+    audio.write(0x4085, 0x3F);
+    assert_eq!(audio.mod_counter, 63);
+  }
+}
@@ -0,0 +1,114 @@
+use super::super::Data;
+
+// The .fds format strips the gaps, block start marks and crcs which the drive actually sees.
+// They are restored on load so the BIOS reads the disk with real timings.
+// ref. https://wiki.nesdev.com/w/index.php/FDS_disk_format
+const LEADING_GAP_SIZE: usize = 28300 / 8;
+const BLOCK_GAP_SIZE: usize = 976 / 8;
+const BLOCK_START_MARK: Data = 0x80;
+const DUMMY_CRC: [Data; 2] = [0x4D, 0x62];
+
+const DISK_INFO_BLOCK: Data = 1;
+const FILE_AMOUNT_BLOCK: Data = 2;
+const FILE_HEADER_BLOCK: Data = 3;
+const FILE_DATA_BLOCK: Data = 4;
+
+// file size is stored in bytes 13-14 of the file header block
+fn block_size(block_type: Data, file_size: usize) -> Option<usize> {
+  match block_type {
+    DISK_INFO_BLOCK => Some(56),
+    FILE_AMOUNT_BLOCK => Some(2),
+    FILE_HEADER_BLOCK => Some(16),
+    FILE_DATA_BLOCK => Some(1 + file_size),
+    _ => None,
+  }
+}
+
+fn file_size(header: &[Data]) -> usize {
+  header[13] as usize | (header[14] as usize) << 8
+}
+
+pub fn add_gaps(side: &[Data]) -> Vec<Data> {
+  let mut disk = vec![0; LEADING_GAP_SIZE];
+  let mut pos = 0;
+  let mut size_of_file = 0;
+  while pos < side.len() {
+    let block_type = side[pos];
+    let len = match block_size(block_type, size_of_file) {
+      Some(len) if pos + len <= side.len() => len,
+      _ => break,
+    };
+    if block_type == FILE_HEADER_BLOCK {
+      size_of_file = file_size(&side[pos..pos + len]);
+    }
+    disk.push(BLOCK_START_MARK);
+    disk.extend_from_slice(&side[pos..pos + len]);
+    disk.extend_from_slice(&DUMMY_CRC);
+    disk.extend(vec![0; BLOCK_GAP_SIZE]);
+    pos += len;
+  }
+  // keep the unused area so that the BIOS can append files
+  disk.extend(vec![0; side.len() - pos]);
+  disk
+}
+
+pub fn remove_gaps(disk: &[Data], side_size: usize) -> Vec<Data> {
+  let mut side = Vec::with_capacity(side_size);
+  let mut pos = 0;
+  let mut size_of_file = 0;
+  loop {
+    while pos < disk.len() && disk[pos] != BLOCK_START_MARK {
+      pos += 1;
+    }
+    pos += 1;
+    if pos >= disk.len() {
+      break;
+    }
+    let len = match block_size(disk[pos], size_of_file) {
+      Some(len) if pos + len <= disk.len() => len,
+      _ => break,
+    };
+    if disk[pos] == FILE_HEADER_BLOCK {
+      size_of_file = file_size(&disk[pos..pos + len]);
+    }
+    side.extend_from_slice(&disk[pos..pos + len]);
+    pos += len + DUMMY_CRC.len();
+  }
+  side.resize(side_size, 0);
+  side
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn build_side() -> Vec<Data> {
+    let mut side = vec![DISK_INFO_BLOCK];
+    side.extend(b"*NINTENDO-HVC*".iter());
+    side.resize(56, 0);
+    side.extend_from_slice(&[FILE_AMOUNT_BLOCK, 1]);
+    let mut header = vec![FILE_HEADER_BLOCK; 16];
+    header[13] = 3;
+    header[14] = 0;
+    side.extend(header);
+    side.extend_from_slice(&[FILE_DATA_BLOCK, 0x80, 0xAA, 0x80]);
+    side.resize(200, 0);
+    side
+  }
+
+  #[test]
+  fn test_add_gaps() {
+    let side = build_side();
+    let disk = add_gaps(&side);
+    assert_eq!(disk[LEADING_GAP_SIZE], BLOCK_START_MARK);
+    assert_eq!(disk[LEADING_GAP_SIZE + 1..LEADING_GAP_SIZE + 57], side[0..56]);
+    assert_eq!(disk[LEADING_GAP_SIZE + 57..LEADING_GAP_SIZE + 59], DUMMY_CRC);
+    assert_eq!(disk.len(), LEADING_GAP_SIZE + 200 + 4 * (1 + 2 + BLOCK_GAP_SIZE));
+  }
+
+  #[test]
+  fn test_remove_gaps() {
+    let side = build_side();
+    assert_eq!(remove_gaps(&add_gaps(&side), side.len()), side);
+  }
+}
@@ -0,0 +1,468 @@
+pub mod audio;
+mod disk;
+
+use super::mapper::*;
+use super::Data;
+use super::Addr;
+use super::Word;
+use super::Ram;
+use super::Rom;
+use super::PpuConfig;
+use super::Ppu;
+use super::CpuRegister;
+use super::Register;
+use self::audio::FdsAudio;
+
+// ref. https://wiki.nesdev.com/w/index.php/Family_Computer_Disk_System
+const PRG_RAM_SIZE: usize = 0x8000;
+// cpu cycles the head takes to return to the start of the disk
+const HEAD_RETURN_DELAY: u32 = 50000;
+// 96.4 kbit/s: one byte every ~149 cpu cycles
+const BYTE_TRANSFER_DELAY: u32 = 149;
+// keep the drive empty for a moment so that the BIOS notices the side change
+const INSERT_DELAY: u32 = 1_789_773;
+const CRC_POLYNOMIAL: u16 = 0x8408;
+
+extern "C" {
+  fn save_disk(ptr: *const Data, len: usize);
+}
+
+#[derive(Debug)]
+pub struct MapperFds {
+  prg_ram: Vec<Data>,
+  sides: Vec<Vec<Data>>, // with gaps, as seen by the drive
+  side_size: usize,      // size of a side in the .fds file
+  is_modified: bool,
+  side: Option<usize>,
+  next_side: Option<usize>,
+  insert_delay: u32,
+
+  // $4020 - $4022 timer irq
+  irq_reload: Word,
+  irq_counter: Word,
+  is_irq_enabled: bool,
+  is_irq_repeat: bool,
+  is_timer_irq_pending: bool,
+
+  // $4023 - $4025 disk drive
+  is_disk_register_enabled: bool,
+  is_sound_register_enabled: bool,
+  write_data: Data,
+  is_motor_on: bool,
+  is_reset_transfer: bool,
+  is_read_mode: bool,
+  is_crc_control: bool,
+  is_disk_ready: bool,
+  is_disk_irq_enabled: bool,
+  is_disk_irq_pending: bool,
+
+  read_data: Data,
+  is_transfer_complete: bool,
+  position: usize,
+  delay: u32,
+  crc: Word,
+  was_crc_control: bool,
+  is_gap_ended: bool,
+  is_scanning: bool,
+  is_end_of_head: bool,
+
+  audio: FdsAudio,
+}
+
+impl MapperFds {
+  pub fn new(disk_sides: &[Vec<Data>]) -> Self {
+    MapperFds {
+      prg_ram: vec![0; PRG_RAM_SIZE],
+      sides: disk_sides.iter().map(|s| disk::add_gaps(s)).collect(),
+      side_size: disk_sides.first().map_or(0, |s| s.len()),
+      is_modified: false,
+      side: if disk_sides.is_empty() { None } else { Some(0) },
+      next_side: None,
+      insert_delay: 0,
+
+      irq_reload: 0,
+      irq_counter: 0,
+      is_irq_enabled: false,
+      is_irq_repeat: false,
+      is_timer_irq_pending: false,
+
+      is_disk_register_enabled: true,
+      is_sound_register_enabled: true,
+      write_data: 0,
+      is_motor_on: false,
+      is_reset_transfer: false,
+      is_read_mode: true,
+      is_crc_control: false,
+      is_disk_ready: false,
+      is_disk_irq_enabled: false,
+      is_disk_irq_pending: false,
+
+      read_data: 0,
+      is_transfer_complete: false,
+      position: 0,
+      delay: 0,
+      crc: 0,
+      was_crc_control: false,
+      is_gap_ended: false,
+      is_scanning: false,
+      is_end_of_head: true,
+
+      audio: FdsAudio::new(),
+    }
+  }
+
+  fn is_disk_inserted(&self) -> bool {
+    self.side.is_some()
+  }
+
+  fn write_control(&mut self, data: Data, ppu_cfg: &mut PpuConfig) {
+    self.is_disk_irq_pending = false;
+    self.is_motor_on = data & 0x01 == 0x01;
+    self.is_reset_transfer = data & 0x02 == 0x02;
+    self.is_read_mode = data & 0x04 == 0x04;
+    ppu_cfg.is_horizontal_mirror = data & 0x08 == 0x08;
+    self.is_crc_control = data & 0x10 == 0x10;
+    self.is_disk_ready = data & 0x40 == 0x40;
+    self.is_disk_irq_enabled = data & 0x80 == 0x80;
+  }
+
+  fn update_crc(&mut self, data: Data) {
+    for n in 0..8 {
+      let carry = self.crc & 1;
+      self.crc >>= 1;
+      if carry == 1 {
+        self.crc ^= CRC_POLYNOMIAL;
+      }
+      if data & (1 << n) != 0 {
+        self.crc ^= 0x8000;
+      }
+    }
+  }
+
+  fn read_disk(&self) -> Data {
+    match self.side {
+      Some(side) => self.sides[side].get(self.position).cloned().unwrap_or(0),
+      None => 0,
+    }
+  }
+
+  // the head writes 2 bytes behind the read position
+  fn write_disk(&mut self, data: Data) {
+    if let Some(side) = self.side {
+      if self.position >= 2 && self.position - 2 < self.sides[side].len() {
+        self.sides[side][self.position - 2] = data;
+        self.is_modified = true;
+      }
+    }
+  }
+
+  fn step_irq(&mut self) {
+    if !self.is_irq_enabled {
+      return;
+    }
+    if self.irq_counter == 0 {
+      self.is_timer_irq_pending = true;
+      self.irq_counter = self.irq_reload;
+      if !self.is_irq_repeat {
+        self.is_irq_enabled = false;
+      }
+    } else {
+      self.irq_counter -= 1;
+    }
+  }
+
+  fn step_insert(&mut self) {
+    if self.next_side.is_none() {
+      return;
+    }
+    if self.insert_delay > 0 {
+      self.insert_delay -= 1;
+    } else {
+      self.side = self.next_side.take();
+      println!("disk side {} inserted", self.side.unwrap_or(0));
+    }
+  }
+
+  fn step_disk(&mut self) {
+    if !self.is_disk_inserted() || !self.is_motor_on {
+      self.is_end_of_head = true;
+      self.is_scanning = false;
+      return;
+    }
+    if self.is_reset_transfer && !self.is_scanning {
+      return;
+    }
+    if self.is_end_of_head {
+      self.delay = HEAD_RETURN_DELAY;
+      self.is_end_of_head = false;
+      self.position = 0;
+      self.is_gap_ended = false;
+      return;
+    }
+    if self.delay > 0 {
+      self.delay -= 1;
+      return;
+    }
+
+    self.is_scanning = true;
+    let mut need_irq = self.is_disk_irq_enabled;
+    if self.is_read_mode {
+      let data = self.read_disk();
+      if !self.was_crc_control {
+        self.update_crc(data);
+      }
+      if !self.is_disk_ready {
+        self.is_gap_ended = false;
+        self.crc = 0;
+      } else if data != 0 && !self.is_gap_ended {
+        // the block start mark ends the gap, the BIOS waits for the next byte
+        self.is_gap_ended = true;
+        need_irq = false;
+      }
+      if self.is_gap_ended {
+        self.is_transfer_complete = true;
+        self.read_data = data;
+        if need_irq {
+          self.is_disk_irq_pending = true;
+        }
+      }
+    } else {
+      let mut data = 0;
+      if !self.is_crc_control {
+        self.is_transfer_complete = true;
+        data = self.write_data;
+        if need_irq {
+          self.is_disk_irq_pending = true;
+        }
+      }
+      if !self.is_disk_ready {
+        data = 0;
+      }
+      if !self.is_crc_control {
+        self.update_crc(data);
+      } else {
+        if !self.was_crc_control {
+          self.update_crc(0);
+          self.update_crc(0);
+        }
+        data = self.crc as Data;
+        self.crc >>= 8;
+      }
+      self.write_disk(data);
+      self.is_gap_ended = false;
+    }
+    self.was_crc_control = self.is_crc_control;
+
+    self.position += 1;
+    let len = self.side.map_or(0, |s| self.sides[s].len());
+    if self.position >= len {
+      self.is_motor_on = false;
+    } else {
+      self.delay = BYTE_TRANSFER_DELAY;
+    }
+  }
+}
+
+impl Mapper for MapperFds {
+  fn get_cram_index(&self, addr: Addr) -> Addr {
+    addr
+  }
+
+  fn read(&mut self, addr: Addr, prg_rom: &Rom, _sram: &Ram) -> Data {
+    match addr {
+      0x6000..=0xDFFF => self.prg_ram[(addr - 0x6000) as usize],
+      0xE000..=0xFFFF => prg_rom.read((addr - 0xE000) as u32),
+      _ => panic!("[READ] There is an illegal address (0x{:x}) access on Mapper.", addr),
+    }
+  }
+
+  fn write(&mut self, addr: Addr, data: Data, _sram: &mut Ram, _ppu_cfg: &mut PpuConfig) {
+    match addr {
+      0x6000..=0xDFFF => self.prg_ram[(addr - 0x6000) as usize] = data,
+      0xE000..=0xFFFF => (), // BIOS
+      _ => panic!("[WRITE] There is an illegal address (0x{:x}) access on Mapper.", addr),
+    }
+  }
+
+  fn step(&mut self, _ppu: &Ppu, _cpu_register: &mut Register) {}
+
+  fn read_expansion(&mut self, addr: Addr) -> Data {
+    match addr {
+      0x4030 => {
+        let mut data = 0;
+        if self.is_timer_irq_pending {
+          data |= 0x01;
+        }
+        if self.is_transfer_complete {
+          data |= 0x02;
+        }
+        self.is_transfer_complete = false;
+        self.is_timer_irq_pending = false;
+        self.is_disk_irq_pending = false;
+        data
+      }
+      0x4031 => {
+        self.is_transfer_complete = false;
+        self.is_disk_irq_pending = false;
+        self.read_data
+      }
+      0x4032 => {
+        let mut data = 0;
+        if !self.is_disk_inserted() {
+          data |= 0x05; // not inserted, write protected
+        }
+        if !self.is_disk_inserted() || !self.is_scanning {
+          data |= 0x02; // not ready
+        }
+        data
+      }
+      0x4033 => 0x80, // battery is good
+      0x4040..=0x4097 if self.is_sound_register_enabled => self.audio.read(addr),
+      _ => 0,
+    }
+  }
+
+  fn write_expansion(&mut self, addr: Addr, data: Data, ppu_cfg: &mut PpuConfig) {
+    match addr {
+      0x4020 => self.irq_reload = (self.irq_reload & 0xFF00) | data as Word,
+      0x4021 => self.irq_reload = (self.irq_reload & 0x00FF) | (data as Word) << 8,
+      0x4022 => {
+        self.is_irq_repeat = data & 0x01 == 0x01;
+        self.is_irq_enabled = data & 0x02 == 0x02 && self.is_disk_register_enabled;
+        if self.is_irq_enabled {
+          self.irq_counter = self.irq_reload;
+        } else {
+          self.is_timer_irq_pending = false;
+        }
+      }
+      0x4023 => {
+        self.is_disk_register_enabled = data & 0x01 == 0x01;
+        self.is_sound_register_enabled = data & 0x02 == 0x02;
+        if !self.is_disk_register_enabled {
+          self.is_irq_enabled = false;
+          self.is_timer_irq_pending = false;
+          self.is_disk_irq_pending = false;
+        }
+      }
+      0x4024 if self.is_disk_register_enabled => {
+        self.write_data = data;
+        self.is_transfer_complete = false;
+        self.is_disk_irq_pending = false;
+      }
+      0x4025 if self.is_disk_register_enabled => self.write_control(data, ppu_cfg),
+      0x4026 => (), // external connector
+      0x4040..=0x4097 if self.is_sound_register_enabled => self.audio.write(addr, data),
+      _ => (),
+    }
+  }
+
+  fn step_cpu(&mut self, cycle: Word, cpu_register: &mut Register) {
+    for _ in 0..cycle {
+      self.step_irq();
+      self.step_insert();
+      self.step_disk();
+      self.audio.step();
+    }
+    if self.is_timer_irq_pending || self.is_disk_irq_pending {
+      cpu_register.set_interrupt_irq();
+    }
+  }
+
+  fn expansion_audio(&self) -> f32 {
+    self.audio.output() as f32 / 63.0
+  }
+
+  fn insert_disk(&mut self, side: Option<usize>) {
+    self.side = None;
+    self.next_side = side.filter(|&s| s < self.sides.len());
+    self.insert_delay = INSERT_DELAY;
+  }
+
+  fn disk_side(&self) -> Option<usize> {
+    self.side.or(self.next_side)
+  }
+
+  fn disk_side_count(&self) -> usize {
+    self.sides.len()
+  }
+
+  fn save(&self) {
+    if !self.is_modified {
+      return;
+    }
+    let buf: Vec<Data> = self.sides.iter().flat_map(|s| disk::remove_gaps(s, self.side_size)).collect();
+    unsafe {
+      save_disk(buf.as_ptr(), buf.len());
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn build_side() -> Vec<Data> {
+    let mut side = vec![0x01];
+    side.extend(b"*NINTENDO-HVC*".iter());
+    side.resize(65500, 0);
+    side
+  }
+
+  #[test]
+  fn test_timer_irq() {
+    let mut mapper = MapperFds::new(&[build_side()]);
+    let mut register = Register::new();
+    let mut cfg = PpuConfig { is_horizontal_mirror: false };
+    mapper.write_expansion(0x4020, 0x10, &mut cfg);
+    mapper.write_expansion(0x4021, 0x00, &mut cfg);
+    mapper.write_expansion(0x4022, 0x02, &mut cfg);
+    mapper.step_cpu(0x10, &mut register);
+    assert!(!mapper.is_timer_irq_pending);
+    mapper.step_cpu(1, &mut register);
+    assert_eq!(mapper.read_expansion(0x4030) & 0x01, 0x01);
+    assert_eq!(mapper.read_expansion(0x4030) & 0x01, 0x00);
+    // not repeated
+    mapper.step_cpu(0x20, &mut register);
+    assert!(!mapper.is_timer_irq_pending);
+  }
+
+  #[test]
+  fn test_read_disk() {
+    let mut mapper = MapperFds::new(&[build_side()]);
+    let mut register = Register::new();
+    let mut cfg = PpuConfig { is_horizontal_mirror: false };
+    // motor on, read mode, horizontal mirroring
+    mapper.write_expansion(0x4025, 0x0D, &mut cfg);
+    assert!(cfg.is_horizontal_mirror);
+    mapper.step_cpu(1, &mut register);
+    mapper.step_cpu(HEAD_RETURN_DELAY as Word, &mut register);
+    // the drive is scanning the leading gap, the BIOS then waits for the start mark
+    mapper.write_expansion(0x4025, 0x4D, &mut cfg);
+    let mut data = vec![];
+    while data.len() < 15 {
+      mapper.step_cpu(1, &mut register);
+      if mapper.is_transfer_complete {
+        data.push(mapper.read_expansion(0x4031));
+      }
+    }
+    assert_eq!(mapper.read_expansion(0x4032) & 0x02, 0x00);
+    assert_eq!(data[0], 0x80);
+    assert_eq!(&data[1..15], b"\x01*NINTENDO-HVC");
+  }
+
+  #[test]
+  fn test_insert_disk() {
+    let mut mapper = MapperFds::new(&[build_side(), build_side()]);
+    let mut register = Register::new();
+    assert_eq!(mapper.disk_side_count(), 2);
+    mapper.insert_disk(Some(1));
+    assert_eq!(mapper.read_expansion(0x4032) & 0x01, 0x01);
+    mapper.step_cpu(0xFFFF, &mut register);
+    mapper.insert_disk(Some(1));
+    for _ in 0..(INSERT_DELAY / 0xFFFF + 1) {
+      mapper.step_cpu(0xFFFF, &mut register);
+    }
+    assert_eq!(mapper.read_expansion(0x4032) & 0x01, 0x00);
+    assert_eq!(mapper.disk_side(), Some(1));
+  }
+}
@@ -5,16 +5,46 @@ use super::Rom;
 use super::PpuConfig;
 use super::Ppu;
 use super::Register;
+use super::Word;
 
 pub trait Mapper {
   fn get_cram_index(&self, addr: Addr) -> Addr; // for ppu
   fn read(&mut self, addr: Addr, prg_rom: &Rom, sram: &Ram) -> Data;
   fn write(&mut self, addr: Addr, data: Data, sram: &mut Ram, ppu_cfg: &mut PpuConfig);
   fn step(&mut self, ppu: &Ppu, cpu_register: &mut Register);
+
+  // 0x4020 - 0x5FFF expansion area
+  fn read_expansion(&mut self, _addr: Addr) -> Data {
+    0
+  }
+
+  fn write_expansion(&mut self, _addr: Addr, _data: Data, _ppu_cfg: &mut PpuConfig) {}
+
+  // called with cpu cycles, for cpu clocked irq counters and expansion audio
+  fn step_cpu(&mut self, _cycle: Word, _cpu_register: &mut Register) {}
+
+  // expansion audio level mixed with the apu output, 0.0 - 1.0
+  fn expansion_audio(&self) -> f32 {
+    0.0
+  }
+
+  // Famicom Disk System: None ejects the disk
+  fn insert_disk(&mut self, _side: Option<usize>) {}
+
+  fn disk_side(&self) -> Option<usize> {
+    None
+  }
+
+  fn disk_side_count(&self) -> usize {
+    0
+  }
+
+  // write back modified data (e.g. disk sides) to the host
+  fn save(&self) {}
 }
 
 impl std::fmt::Debug for dyn Mapper {
   fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
       write!(f, "{}", "derp")
   }
-}
\ No newline at end of file
+}
@@ -19,13 +19,15 @@ pub struct Mapper4 {
   chr_offsets: Vec<i32>,
   reload: Data,
   counter: Data,
+  is_reload_requested: bool, // $C001
   irq_enabled: bool,
+  is_mmc3a: bool, // NES 2.0 submapper 4, the older IRQ behaviour
   PRG_ROM_LEN: usize,
   CHR_RAM_LEN: usize,
 }
 
 impl Mapper4 {
-  pub fn new(prg_rom_len: usize, chr_ram_len: usize) -> Self {
+  pub fn new(prg_rom_len: usize, chr_ram_len: usize, is_mmc3a: bool) -> Self {
     let mut m = Mapper4 {
       register: 0,
       registers: vec![0; 8],
@@ -35,7 +37,9 @@ impl Mapper4 {
       chr_offsets: vec![0; 0x8],
       reload: 0,
       counter: 0,
+      is_reload_requested: false,
       irq_enabled: false,
+      is_mmc3a,
       PRG_ROM_LEN: prg_rom_len,
       CHR_RAM_LEN: chr_ram_len,
     };
@@ -174,6 +178,7 @@ impl Mapper4 {
 
   fn write_irq_reload(&mut self) {
     self.counter = 0;
+    self.is_reload_requested = true;
   }
 
   fn write_irq_disable (&mut self) {
@@ -185,14 +190,19 @@ impl Mapper4 {
   }
 
 
+  // ref. https://wiki.nesdev.com/w/index.php/MMC3#IRQ_Specifics
   fn handle_scan_line(&mut self, cpu_register: &mut Register) {
-    if self.counter == 0 {
-      self. counter = self.reload
+    let count = self.counter;
+    if self.counter == 0 || self.is_reload_requested {
+      self.counter = self.reload;
     } else {
       self.counter -= 1;
-      if self.counter == 0 && self.irq_enabled {
-          cpu_register.set_interrupt_irq();
-      }
+    }
+    // MMC3A: no IRQ when the counter reached 0 on the previous clock and is reloaded with 0
+    let is_irq = self.counter == 0 && (!self.is_mmc3a || count > 0 || self.is_reload_requested);
+    self.is_reload_requested = false;
+    if is_irq && self.irq_enabled {
+      cpu_register.set_interrupt_irq();
     }
   }
 }
@@ -234,7 +244,7 @@ impl Mapper for Mapper4 {
     if ppu.cycle != 280 { // TODO: this *should* be 260
       return
     }
-    if 239 < ppu.line && ppu.line <261 {
+    if !ppu.is_render_line() {
       return
     }
     if !ppu.is_background_enabled() && !ppu.is_sprite_enabled() {
@@ -242,4 +252,33 @@ impl Mapper for Mapper4 {
     }
     self.handle_scan_line(cpu_register)
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  // whether each scanline sets the irq with the latch at 0
+  fn irqs_with_zero_latch(is_mmc3a: bool) -> Vec<bool> {
+    let mut mapper = Mapper4::new(0x8000, 0x2000, is_mmc3a);
+    let mut register = Register::new();
+    register.set_status_interrupt(false);
+    mapper.write_irq_latch(0);
+    mapper.write_irq_reload();
+    mapper.write_irq_enable();
+    (0..3).map(|_| {
+      mapper.handle_scan_line(&mut register);
+      let is_irq = register.is_interrupt_irq_enabled();
+      register.set_interrupt_none();
+      is_irq
+    }).collect()
+  }
+
+  #[test]
+  fn test_irq_reload_to_zero() {
+    // MMC3B / C: every clock reloads 0 and sets the irq
+    assert_eq!(irqs_with_zero_latch(false), vec![true, true, true]);
+    // MMC3A: only the reload by $C001 does
+    assert_eq!(irqs_with_zero_latch(true), vec![true, false, false]);
+  }
+}
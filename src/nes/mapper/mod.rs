@@ -2,24 +2,37 @@ mod mapper0;
 mod mapper3;
 mod mapper4;
 mod mapper;
+mod fds;
+mod nsf;
 
 pub use super::types::*;
 pub use super::ram::Ram;
 pub use super::rom::Rom;
 pub use super::ppu::*;
 pub use super::cpu_register::*;
-pub use super::cassette_paser::Cassette;
+pub use super::cassette_paser::{Cassette, NsfInfo};
 pub use self::mapper::Mapper;
 pub use self::mapper0::Mapper0;
 pub use self::mapper3::Mapper3;
 pub use self::mapper4::Mapper4;
+pub use self::fds::MapperFds;
+pub use self::nsf::MapperNsf;
+
+// the mappers Mapper::new builds, the others fall back to Mapper0
+pub fn is_supported(mapper: u16) -> bool {
+  matches!(mapper, 0 | 3 | 4)
+}
 
 impl dyn Mapper {
   pub fn new(cassette: &Cassette) -> Box<dyn Mapper> {
+    if let Some(nsf) = &cassette.nsf {
+      return Box::new(MapperNsf::new(&cassette.program_rom, nsf));
+    }
     match cassette.mapper {
       0 => Box::new(Mapper0::new()),
       3 => Box::new(Mapper3::new(cassette.program_rom.len() as u16)),
-      4 => Box::new(Mapper4::new(cassette.program_rom.len(), cassette.character_ram.len())),
+      4 => Box::new(Mapper4::new(cassette.program_rom.len(), cassette.character_ram.len(), cassette.submapper == 4)),
+      20 => Box::new(MapperFds::new(&cassette.disk_sides)),
       _ => Box::new(Mapper0::new()),
     }
   }
@@ -0,0 +1,206 @@
+use super::super::{Addr, Data, Word};
+
+// ref. https://wiki.nesdev.com/w/index.php/MMC5_audio
+// Pulses work like the apu ones without sweep, clocked by an internal 240Hz frame counter.
+const FRAME_PERIOD: u16 = 7457;
+const DUTY_TABLE: [[Data; 8]; 4] = [
+  [0, 1, 0, 0, 0, 0, 0, 0],
+  [0, 1, 1, 0, 0, 0, 0, 0],
+  [0, 1, 1, 1, 1, 0, 0, 0],
+  [1, 0, 0, 1, 1, 1, 1, 1],
+];
+const LENGTH_TABLE: [Data; 32] = [
+  10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+  12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+#[derive(Debug)]
+struct Pulse {
+  duty: usize,
+  is_halt: bool, // also envelope loop
+  is_constant_volume: bool,
+  volume: Data,
+  period: Word,
+  timer: Word,
+  sequence: usize,
+  length_counter: Data,
+  is_enabled: bool,
+  is_envelope_start: bool,
+  envelope_divider: Data,
+  envelope_decay: Data,
+}
+
+impl Pulse {
+  fn new() -> Self {
+    Pulse {
+      duty: 0,
+      is_halt: false,
+      is_constant_volume: false,
+      volume: 0,
+      period: 0,
+      timer: 0,
+      sequence: 0,
+      length_counter: 0,
+      is_enabled: false,
+      is_envelope_start: false,
+      envelope_divider: 0,
+      envelope_decay: 0,
+    }
+  }
+
+  fn write(&mut self, addr: Addr, data: Data) {
+    match addr & 0x03 {
+      0x00 => {
+        self.duty = (data >> 6) as usize;
+        self.is_halt = data & 0x20 == 0x20;
+        self.is_constant_volume = data & 0x10 == 0x10;
+        self.volume = data & 0x0F;
+      }
+      0x02 => self.period = (self.period & 0x0700) | data as Word,
+      0x03 => {
+        self.period = (self.period & 0x00FF) | ((data as Word & 0x07) << 8);
+        if self.is_enabled {
+          self.length_counter = LENGTH_TABLE[(data >> 3) as usize];
+        }
+        self.sequence = 0;
+        self.is_envelope_start = true;
+      }
+      _ => (),
+    }
+  }
+
+  fn set_enabled(&mut self, is_enabled: bool) {
+    self.is_enabled = is_enabled;
+    if !is_enabled {
+      self.length_counter = 0;
+    }
+  }
+
+  // pulses are clocked every other cpu cycle
+  fn step_timer(&mut self) {
+    if self.timer == 0 {
+      self.timer = self.period;
+      self.sequence = (self.sequence + 1) & 0x07;
+    } else {
+      self.timer -= 1;
+    }
+  }
+
+  fn step_frame(&mut self) {
+    if self.is_envelope_start {
+      self.is_envelope_start = false;
+      self.envelope_decay = 15;
+      self.envelope_divider = self.volume;
+    } else if self.envelope_divider == 0 {
+      self.envelope_divider = self.volume;
+      if self.envelope_decay > 0 {
+        self.envelope_decay -= 1;
+      } else if self.is_halt {
+        self.envelope_decay = 15;
+      }
+    } else {
+      self.envelope_divider -= 1;
+    }
+    if !self.is_halt && self.length_counter > 0 {
+      self.length_counter -= 1;
+    }
+  }
+
+  fn output(&self) -> Data {
+    if self.length_counter == 0 || DUTY_TABLE[self.duty][self.sequence] == 0 {
+      0
+    } else if self.is_constant_volume {
+      self.volume
+    } else {
+      self.envelope_decay
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct Mmc5Audio {
+  pulses: (Pulse, Pulse),
+  pcm: Data,
+  is_pcm_read_mode: bool,
+  cycle: u16,
+  is_odd_cycle: bool,
+}
+
+impl Mmc5Audio {
+  pub fn new() -> Self {
+    Mmc5Audio {
+      pulses: (Pulse::new(), Pulse::new()),
+      pcm: 0,
+      is_pcm_read_mode: false,
+      cycle: 0,
+      is_odd_cycle: false,
+    }
+  }
+
+  pub fn read(&self, addr: Addr) -> Data {
+    match addr {
+      0x5015 => {
+        let p0 = if self.pulses.0.length_counter > 0 { 0x01 } else { 0x00 };
+        let p1 = if self.pulses.1.length_counter > 0 { 0x02 } else { 0x00 };
+        p0 | p1
+      }
+      _ => 0,
+    }
+  }
+
+  pub fn write(&mut self, addr: Addr, data: Data) {
+    match addr {
+      0x5000..=0x5003 => self.pulses.0.write(addr, data),
+      0x5004..=0x5007 => self.pulses.1.write(addr, data),
+      0x5010 => self.is_pcm_read_mode = data & 0x01 == 0x01,
+      // writing 0 has no effect
+      0x5011 if !self.is_pcm_read_mode && data != 0 => self.pcm = data,
+      0x5015 => {
+        self.pulses.0.set_enabled(data & 0x01 == 0x01);
+        self.pulses.1.set_enabled(data & 0x02 == 0x02);
+      }
+      _ => (),
+    }
+  }
+
+  pub fn step(&mut self) {
+    self.is_odd_cycle = !self.is_odd_cycle;
+    if self.is_odd_cycle {
+      self.pulses.0.step_timer();
+      self.pulses.1.step_timer();
+    }
+    self.cycle += 1;
+    if self.cycle >= FRAME_PERIOD {
+      self.cycle = 0;
+      self.pulses.0.step_frame();
+      self.pulses.1.step_frame();
+    }
+  }
+
+  // pulses 0 - 30
+  pub fn pulse_output(&self) -> Data {
+    self.pulses.0.output() + self.pulses.1.output()
+  }
+
+  // raw 8bit pcm
+  pub fn pcm_output(&self) -> Data {
+    self.pcm
+  }
+}
+
+#[test]
+fn test_mmc5_pulse() {
+  let mut audio = Mmc5Audio::new();
+  audio.write(0x5015, 0x01);
+  audio.write(0x5000, 0xBF); // duty 50%, constant volume 15
+  audio.write(0x5002, 0x00);
+  audio.write(0x5003, 0x08);
+  assert_eq!(audio.read(0x5015), 0x01);
+  let high = (0..16).filter(|_| {
+    audio.step();
+    audio.pulse_output() == 15
+  }).count();
+  assert_eq!(high, 8);
+  audio.write(0x5011, 0x80);
+  assert_eq!(audio.pcm_output(), 0x80);
+}
@@ -0,0 +1,236 @@
+mod mmc5;
+mod n163;
+mod sunsoft5b;
+mod vrc6;
+
+use super::mapper::*;
+use super::Data;
+use super::Addr;
+use super::Word;
+use super::Ram;
+use super::Rom;
+use super::PpuConfig;
+use super::Ppu;
+use super::Register;
+use super::NsfInfo;
+use super::super::cassette_paser::nsf::{CHIP_FDS, CHIP_MMC5, CHIP_N163, CHIP_SUNSOFT5B, CHIP_VRC6};
+use super::fds::audio::FdsAudio;
+use self::mmc5::Mmc5Audio;
+use self::n163::N163Audio;
+use self::sunsoft5b::Sunsoft5bAudio;
+use self::vrc6::Vrc6Audio;
+
+// ref. https://wiki.nesdev.com/w/index.php/NSF#Bankswitching
+const BANK_SIZE: usize = 0x1000;
+
+// Expansion chip levels relative to the apu output, approximated from a full volume apu pulse.
+const FDS_LEVEL: f32 = 0.36 / 63.0;
+const VRC6_LEVEL: f32 = 0.15 / 15.0;
+const MMC5_PULSE_LEVEL: f32 = 0.15 / 15.0;
+const MMC5_PCM_LEVEL: f32 = 0.3 / 255.0;
+const N163_LEVEL: f32 = 0.3 / 120.0;
+const SUNSOFT5B_LEVEL: f32 = 0.2;
+
+#[derive(Debug)]
+pub struct MapperNsf {
+  image: Vec<Data>,
+  banks: [Data; 10], // $6000 - $FFFF
+  is_fds: bool,
+  ram: Vec<Data>,    // $6000 - $7FFF, FDS tunes run in $6000 - $FFFF ram
+  multiplier: (Data, Data),
+  fds: Option<FdsAudio>,
+  vrc6: Option<Vrc6Audio>,
+  mmc5: Option<Mmc5Audio>,
+  n163: Option<N163Audio>,
+  sunsoft5b: Option<Sunsoft5bAudio>,
+}
+
+impl MapperNsf {
+  pub fn new(image: &[Data], nsf: &NsfInfo) -> Self {
+    let is_fds = nsf.has_chip(CHIP_FDS);
+    MapperNsf {
+      image: image.to_vec(),
+      banks: nsf.banks,
+      is_fds,
+      ram: vec![0; if is_fds { 0xA000 } else { 0x2000 }],
+      multiplier: (0xFF, 0xFF),
+      fds: if is_fds { Some(FdsAudio::new()) } else { None },
+      vrc6: if nsf.has_chip(CHIP_VRC6) { Some(Vrc6Audio::new()) } else { None },
+      mmc5: if nsf.has_chip(CHIP_MMC5) { Some(Mmc5Audio::new()) } else { None },
+      n163: if nsf.has_chip(CHIP_N163) { Some(N163Audio::new()) } else { None },
+      sunsoft5b: if nsf.has_chip(CHIP_SUNSOFT5B) { Some(Sunsoft5bAudio::new()) } else { None },
+    }
+  }
+
+  fn read_image(&self, bank: Data, offset: usize) -> Data {
+    self.image.get(bank as usize * BANK_SIZE + offset).cloned().unwrap_or(0)
+  }
+
+  // $5FF6 - $5FFF
+  fn write_bank(&mut self, index: usize, bank: Data) {
+    self.banks[index] = bank;
+    if self.is_fds {
+      // FDS tunes copy the bank into ram instead of mapping it
+      let start = index * BANK_SIZE;
+      for offset in 0..BANK_SIZE {
+        self.ram[start + offset] = self.read_image(bank, offset);
+      }
+    }
+  }
+}
+
+impl Mapper for MapperNsf {
+  fn get_cram_index(&self, addr: Addr) -> Addr {
+    addr
+  }
+
+  fn read(&mut self, addr: Addr, _prg_rom: &Rom, _sram: &Ram) -> Data {
+    match addr {
+      0x6000..=0xFFFF if self.is_fds => self.ram[(addr - 0x6000) as usize],
+      0x6000..=0x7FFF => self.ram[(addr - 0x6000) as usize],
+      0x8000..=0xFFFF => {
+        let index = (addr as usize - 0x6000) / BANK_SIZE;
+        self.read_image(self.banks[index], addr as usize % BANK_SIZE)
+      }
+      _ => panic!("[READ] There is an illegal address (0x{:x}) access on Mapper.", addr),
+    }
+  }
+
+  fn write(&mut self, addr: Addr, data: Data, _sram: &mut Ram, _ppu_cfg: &mut PpuConfig) {
+    match addr {
+      0x6000..=0xFFFF if self.is_fds => self.ram[(addr - 0x6000) as usize] = data,
+      0x6000..=0x7FFF => self.ram[(addr - 0x6000) as usize] = data,
+      0x8000..=0xFFFF => {
+        if let Some(vrc6) = self.vrc6.as_mut() {
+          vrc6.write(addr, data);
+        }
+        if let Some(n163) = self.n163.as_mut() {
+          n163.write(addr, data);
+        }
+        if let Some(sunsoft5b) = self.sunsoft5b.as_mut() {
+          sunsoft5b.write(addr, data);
+        }
+      }
+      _ => panic!("[WRITE] There is an illegal address (0x{:x}) access on Mapper.", addr),
+    }
+  }
+
+  fn step(&mut self, _ppu: &Ppu, _cpu_register: &mut Register) {}
+
+  fn read_expansion(&mut self, addr: Addr) -> Data {
+    match addr {
+      0x4040..=0x4097 => self.fds.as_ref().map_or(0, |fds| fds.read(addr)),
+      0x4800..=0x4FFF => self.n163.as_mut().map_or(0, |n163| n163.read()),
+      0x5015 => self.mmc5.as_ref().map_or(0, |mmc5| mmc5.read(addr)),
+      0x5205 if self.mmc5.is_some() => (self.multiplier.0 as Word * self.multiplier.1 as Word) as Data,
+      0x5206 if self.mmc5.is_some() => ((self.multiplier.0 as Word * self.multiplier.1 as Word) >> 8) as Data,
+      _ => 0,
+    }
+  }
+
+  fn write_expansion(&mut self, addr: Addr, data: Data, _ppu_cfg: &mut PpuConfig) {
+    match addr {
+      0x4040..=0x4097 => {
+        if let Some(fds) = self.fds.as_mut() {
+          fds.write(addr, data);
+        }
+      }
+      0x4800..=0x4FFF => {
+        if let Some(n163) = self.n163.as_mut() {
+          n163.write(addr, data);
+        }
+      }
+      0x5000..=0x5015 => {
+        if let Some(mmc5) = self.mmc5.as_mut() {
+          mmc5.write(addr, data);
+        }
+      }
+      0x5205 => self.multiplier.0 = data,
+      0x5206 => self.multiplier.1 = data,
+      0x5FF6..=0x5FF7 if self.is_fds => self.write_bank((addr - 0x5FF6) as usize, data),
+      0x5FF8..=0x5FFF => self.write_bank((addr - 0x5FF6) as usize, data),
+      _ => (),
+    }
+  }
+
+  fn step_cpu(&mut self, cycle: Word, _cpu_register: &mut Register) {
+    for _ in 0..cycle {
+      if let Some(fds) = self.fds.as_mut() {
+        fds.step();
+      }
+      if let Some(vrc6) = self.vrc6.as_mut() {
+        vrc6.step();
+      }
+      if let Some(mmc5) = self.mmc5.as_mut() {
+        mmc5.step();
+      }
+      if let Some(n163) = self.n163.as_mut() {
+        n163.step();
+      }
+      if let Some(sunsoft5b) = self.sunsoft5b.as_mut() {
+        sunsoft5b.step();
+      }
+    }
+  }
+
+  fn expansion_audio(&self) -> f32 {
+    let mut level = 0.0;
+    if let Some(fds) = self.fds.as_ref() {
+      level += fds.output() as f32 * FDS_LEVEL;
+    }
+    if let Some(vrc6) = self.vrc6.as_ref() {
+      level += vrc6.output() as f32 * VRC6_LEVEL;
+    }
+    if let Some(mmc5) = self.mmc5.as_ref() {
+      level += mmc5.pulse_output() as f32 * MMC5_PULSE_LEVEL + mmc5.pcm_output() as f32 * MMC5_PCM_LEVEL;
+    }
+    if let Some(n163) = self.n163.as_ref() {
+      level += n163.output() as f32 * N163_LEVEL;
+    }
+    if let Some(sunsoft5b) = self.sunsoft5b.as_ref() {
+      level += sunsoft5b.output() * SUNSOFT5B_LEVEL;
+    }
+    level
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use super::super::super::cassette_paser::nsf::{self, test::build_nsf};
+
+  #[test]
+  fn test_bankswitch() {
+    let mut program = vec![0x11; 0x1000];
+    program.extend(vec![0x22; 0x1000]);
+    let cassette = nsf::parse(&build_nsf(0x8000, &[0, 0, 0, 0, 0, 0, 0, 1], &program)).unwrap();
+    let mut mapper = MapperNsf::new(&cassette.program_rom, cassette.nsf.as_ref().unwrap());
+    let rom = Rom::new(vec![]);
+    let mut sram = Ram::new(vec![]);
+    let mut cfg = PpuConfig { is_horizontal_mirror: false };
+    assert_eq!(mapper.read(0x8000, &rom, &sram), 0x11);
+    assert_eq!(mapper.read(0xF000, &rom, &sram), 0x22);
+    mapper.write_expansion(0x5FF8, 1, &mut cfg);
+    assert_eq!(mapper.read(0x8000, &rom, &sram), 0x22);
+    // out of the image
+    mapper.write_expansion(0x5FF8, 5, &mut cfg);
+    assert_eq!(mapper.read(0x8000, &rom, &sram), 0x00);
+    mapper.write(0x6000, 0x33, &mut sram, &mut cfg);
+    assert_eq!(mapper.read(0x6000, &rom, &sram), 0x33);
+  }
+
+  #[test]
+  fn test_fds_bank_copies_to_ram() {
+    let mut buf = build_nsf(0x8000, &[0, 0, 0, 0, 0, 0, 0, 1], &[0x44; 0x2000]);
+    buf[0x7B] = CHIP_FDS;
+    let cassette = nsf::parse(&buf).unwrap();
+    let mut mapper = MapperNsf::new(&cassette.program_rom, cassette.nsf.as_ref().unwrap());
+    let rom = Rom::new(vec![]);
+    let mut sram = Ram::new(vec![]);
+    let mut cfg = PpuConfig { is_horizontal_mirror: false };
+    mapper.write_expansion(0x5FF6, 1, &mut cfg);
+    assert_eq!(mapper.read(0x6000, &rom, &sram), 0x44);
+    mapper.write(0xE000, 0x55, &mut sram, &mut cfg);
+    assert_eq!(mapper.read(0xE000, &rom, &sram), 0x55);
+  }
+}
@@ -0,0 +1,128 @@
+use super::super::{Addr, Data};
+
+// ref. https://wiki.nesdev.com/w/index.php/Namco_163_audio
+// One channel is updated every 15 cpu cycles, the channels are time multiplexed.
+const CHANNEL_UPDATE_CYCLES: u8 = 15;
+
+#[derive(Debug)]
+pub struct N163Audio {
+  ram: Vec<Data>,
+  address: Data,
+  is_auto_increment: bool,
+  is_disabled: bool,
+  cycle: u8,
+  channel: usize,
+  outputs: Vec<i16>,
+}
+
+impl N163Audio {
+  pub fn new() -> Self {
+    N163Audio {
+      ram: vec![0; 0x80],
+      address: 0,
+      is_auto_increment: false,
+      is_disabled: false,
+      cycle: 0,
+      channel: 7,
+      outputs: vec![0; 8],
+    }
+  }
+
+  fn channel_count(&self) -> usize {
+    ((self.ram[0x7F] >> 4) & 0x07) as usize + 1
+  }
+
+  fn increment_address(&mut self) {
+    if self.is_auto_increment {
+      self.address = (self.address + 1) & 0x7F;
+    }
+  }
+
+  // $4800 data port
+  pub fn read(&mut self) -> Data {
+    let data = self.ram[self.address as usize];
+    self.increment_address();
+    data
+  }
+
+  pub fn write(&mut self, addr: Addr, data: Data) {
+    match addr {
+      0x4800..=0x4FFF => {
+        self.ram[self.address as usize] = data;
+        self.increment_address();
+      }
+      0xE000..=0xE7FF => self.is_disabled = data & 0x40 == 0x40,
+      0xF800..=0xFFFF => {
+        self.address = data & 0x7F;
+        self.is_auto_increment = data & 0x80 == 0x80;
+      }
+      _ => (),
+    }
+  }
+
+  fn update_channel(&mut self, channel: usize) {
+    let base = 0x40 + channel * 8;
+    let reg = &self.ram[base..base + 8];
+    let frequency = reg[0] as u32 | (reg[2] as u32) << 8 | (reg[4] as u32 & 0x03) << 16;
+    let length = 256 - (reg[4] as u32 & 0xFC);
+    let mut phase = reg[1] as u32 | (reg[3] as u32) << 8 | (reg[5] as u32) << 16;
+    let offset = reg[6] as u32;
+    let volume = (reg[7] & 0x0F) as i16;
+
+    phase = (phase + frequency) % (length << 16);
+    let position = ((phase >> 16) + offset) & 0xFF;
+    let sample = (self.ram[(position >> 1) as usize] >> ((position & 1) * 4)) & 0x0F;
+    self.outputs[channel] = (sample as i16 - 8) * volume;
+
+    self.ram[base + 1] = phase as Data;
+    self.ram[base + 3] = (phase >> 8) as Data;
+    self.ram[base + 5] = (phase >> 16) as Data;
+  }
+
+  pub fn step(&mut self) {
+    if self.is_disabled {
+      return;
+    }
+    self.cycle += 1;
+    if self.cycle < CHANNEL_UPDATE_CYCLES {
+      return;
+    }
+    self.cycle = 0;
+    // channels 7 down to 8 - count are active
+    self.update_channel(self.channel);
+    let first = 8 - self.channel_count();
+    self.channel = if self.channel <= first { 7 } else { self.channel - 1 };
+  }
+
+  // average of the active channels, -120 - 105
+  pub fn output(&self) -> i16 {
+    let count = self.channel_count();
+    self.outputs[8 - count..].iter().sum::<i16>() / count as i16
+  }
+}
+
+#[test]
+fn test_n163_ram_port() {
+  let mut audio = N163Audio::new();
+  audio.write(0xF800, 0x80 | 0x10);
+  audio.write(0x4800, 0xAB);
+  audio.write(0x4800, 0xCD);
+  audio.write(0xF800, 0x80 | 0x10);
+  assert_eq!(audio.read(), 0xAB);
+  assert_eq!(audio.read(), 0xCD);
+}
+
+#[test]
+fn test_n163_channel() {
+  let mut audio = N163Audio::new();
+  // wave: 16 samples of 0xF at ram 0x00
+  for i in 0..8 {
+    audio.ram[i] = 0xFF;
+  }
+  audio.ram[0x7C] = 0xF0; // length 16
+  audio.ram[0x7F] = 0x0F; // 1 channel, volume 15
+  for _ in 0..CHANNEL_UPDATE_CYCLES {
+    audio.step();
+  }
+  assert_eq!(audio.output(), 7 * 15);
+}
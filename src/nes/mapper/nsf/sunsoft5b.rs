@@ -0,0 +1,194 @@
+use lazy_static::lazy_static;
+use super::super::{Addr, Data, Word};
+
+// ref. https://wiki.nesdev.com/w/index.php/Sunsoft_5B_audio
+// YM2149F clocked at cpu / 2, tones and noise tick every 16 cpu cycles.
+const DIVIDER: u8 = 16;
+
+lazy_static! {
+  // 3dB per step of the 4bit volume, the envelope has 32 steps of 1.5dB
+  static ref VOLUME_TABLE: Vec<f32> = (0..32).map(|i| {
+    if i == 0 { 0.0 } else { 10f32.powf((i as f32 - 31.0) * 1.5 / 20.0) }
+  }).collect();
+}
+
+#[derive(Debug)]
+pub struct Sunsoft5bAudio {
+  address: Data,
+  registers: Vec<Data>,
+  divider: u8,
+  tone_counters: [Word; 3],
+  tone_outputs: [bool; 3],
+  noise_counter: Word,
+  noise_shift: u32,
+  envelope_counter: u32,
+  envelope_step: Data,
+  is_envelope_attack: bool,
+  is_envelope_holding: bool,
+}
+
+impl Sunsoft5bAudio {
+  pub fn new() -> Self {
+    Sunsoft5bAudio {
+      address: 0,
+      registers: vec![0; 0x10],
+      divider: 0,
+      tone_counters: [0; 3],
+      tone_outputs: [false; 3],
+      noise_counter: 0,
+      noise_shift: 1,
+      envelope_counter: 0,
+      envelope_step: 0,
+      is_envelope_attack: false,
+      is_envelope_holding: false,
+    }
+  }
+
+  pub fn write(&mut self, addr: Addr, data: Data) {
+    match addr {
+      0xC000..=0xDFFF => self.address = data & 0x0F,
+      0xE000..=0xFFFF => {
+        self.registers[self.address as usize] = data;
+        if self.address == 0x0D {
+          self.envelope_step = 0;
+          self.envelope_counter = 0;
+          self.is_envelope_attack = data & 0x04 == 0x04;
+          self.is_envelope_holding = false;
+        }
+      }
+      _ => (),
+    }
+  }
+
+  fn tone_period(&self, channel: usize) -> Word {
+    let period = self.registers[channel * 2] as Word | (self.registers[channel * 2 + 1] as Word & 0x0F) << 8;
+    period.max(1)
+  }
+
+  fn envelope_period(&self) -> u32 {
+    (self.registers[0x0B] as u32 | (self.registers[0x0C] as u32) << 8).max(1)
+  }
+
+  fn envelope_level(&self) -> Data {
+    if self.is_envelope_attack { self.envelope_step } else { 31 - self.envelope_step }
+  }
+
+  fn step_envelope(&mut self) {
+    if self.is_envelope_holding {
+      return;
+    }
+    self.envelope_counter += 1;
+    if self.envelope_counter < self.envelope_period() {
+      return;
+    }
+    self.envelope_counter = 0;
+    if self.envelope_step < 31 {
+      self.envelope_step += 1;
+      return;
+    }
+    let shape = self.registers[0x0D];
+    let is_continue = shape & 0x08 == 0x08;
+    let is_alternate = shape & 0x02 == 0x02;
+    let is_hold = shape & 0x01 == 0x01;
+    if !is_continue {
+      // hold at 0
+      self.is_envelope_attack = false;
+      self.is_envelope_holding = true;
+    } else if is_hold {
+      // hold at the last level, or the opposite one when alternating
+      if is_alternate {
+        self.is_envelope_attack = !self.is_envelope_attack;
+        self.envelope_step = 0;
+      }
+      self.is_envelope_holding = true;
+    } else {
+      if is_alternate {
+        self.is_envelope_attack = !self.is_envelope_attack;
+      }
+      self.envelope_step = 0;
+    }
+  }
+
+  pub fn step(&mut self) {
+    self.divider += 1;
+    if self.divider < DIVIDER {
+      return;
+    }
+    self.divider = 0;
+    for channel in 0..3 {
+      self.tone_counters[channel] += 1;
+      if self.tone_counters[channel] >= self.tone_period(channel) {
+        self.tone_counters[channel] = 0;
+        self.tone_outputs[channel] = !self.tone_outputs[channel];
+      }
+    }
+    // noise is clocked at half of the tone rate
+    self.noise_counter += 1;
+    if self.noise_counter >= (self.registers[0x06] as Word & 0x1F).max(1) * 2 {
+      self.noise_counter = 0;
+      let bit = (self.noise_shift ^ (self.noise_shift >> 3)) & 1;
+      self.noise_shift = (self.noise_shift >> 1) | (bit << 16);
+    }
+    self.step_envelope();
+  }
+
+  // 0.0 - 3.0
+  pub fn output(&self) -> f32 {
+    let mixer = self.registers[0x07];
+    let noise = self.noise_shift & 1 == 1;
+    (0..3).map(|channel| {
+      let is_tone = mixer & (1 << channel) != 0 || self.tone_outputs[channel];
+      let is_noise = mixer & (8 << channel) != 0 || noise;
+      if !is_tone || !is_noise {
+        return 0.0;
+      }
+      let volume = self.registers[0x08 + channel];
+      let level = if volume & 0x10 == 0x10 {
+        self.envelope_level()
+      } else if volume & 0x0F == 0 {
+        0
+      } else {
+        (volume & 0x0F) * 2 + 1
+      };
+      VOLUME_TABLE[level as usize]
+    }).sum()
+  }
+}
+
+#[test]
+fn test_sunsoft5b_tone() {
+  let mut audio = Sunsoft5bAudio::new();
+  audio.write(0xC000, 0x07);
+  audio.write(0xE000, 0x3E); // tone A only
+  audio.write(0xC000, 0x08);
+  audio.write(0xE000, 0x0F);
+  audio.write(0xC000, 0x00);
+  audio.write(0xE000, 0x02);
+  audio.write(0xC000, 0x01);
+  audio.write(0xE000, 0x00);
+  let mut toggles = 0;
+  let mut last = audio.output();
+  for _ in 0..(16 * 2 * 4) {
+    audio.step();
+    if audio.output() != last {
+      toggles += 1;
+      last = audio.output();
+    }
+  }
+  assert_eq!(toggles, 4);
+  assert!(last == 0.0 || (last - 1.0).abs() < 0.001);
+}
+
+#[test]
+fn test_sunsoft5b_envelope_hold() {
+  let mut audio = Sunsoft5bAudio::new();
+  audio.write(0xC000, 0x0B);
+  audio.write(0xE000, 0x01);
+  audio.write(0xC000, 0x0D);
+  audio.write(0xE000, 0x0D); // attack, continue, hold: ramp up and stay high
+  for _ in 0..(16 * 40) {
+    audio.step();
+  }
+  assert!(audio.is_envelope_holding);
+  assert_eq!(audio.envelope_level(), 31);
+}
@@ -0,0 +1,181 @@
+use super::super::{Addr, Data, Word};
+
+// ref. https://wiki.nesdev.com/w/index.php/VRC6_audio
+#[derive(Debug)]
+struct Pulse {
+  volume: Data,
+  duty: Data,
+  is_ignore_duty: bool,
+  period: Word,
+  is_enabled: bool,
+  timer: Word,
+  step: Data,
+}
+
+impl Pulse {
+  fn new() -> Self {
+    Pulse { volume: 0, duty: 0, is_ignore_duty: false, period: 0, is_enabled: false, timer: 0, step: 0 }
+  }
+
+  fn write(&mut self, addr: Addr, data: Data) {
+    match addr & 0x03 {
+      0x00 => {
+        self.volume = data & 0x0F;
+        self.duty = (data >> 4) & 0x07;
+        self.is_ignore_duty = data & 0x80 == 0x80;
+      }
+      0x01 => self.period = (self.period & 0x0F00) | data as Word,
+      0x02 => {
+        self.period = (self.period & 0x00FF) | ((data as Word & 0x0F) << 8);
+        self.is_enabled = data & 0x80 == 0x80;
+        if !self.is_enabled {
+          self.step = 0;
+        }
+      }
+      _ => (),
+    }
+  }
+
+  fn step(&mut self, shift: Data) {
+    if !self.is_enabled {
+      return;
+    }
+    if self.timer == 0 {
+      self.timer = self.period >> shift;
+      self.step = (self.step + 1) & 0x0F;
+    } else {
+      self.timer -= 1;
+    }
+  }
+
+  fn output(&self) -> Data {
+    if self.is_enabled && (self.is_ignore_duty || self.step <= self.duty) {
+      self.volume
+    } else {
+      0
+    }
+  }
+}
+
+#[derive(Debug)]
+struct Saw {
+  rate: Data,
+  period: Word,
+  is_enabled: bool,
+  timer: Word,
+  step: Data,
+  accumulator: Data,
+}
+
+impl Saw {
+  fn new() -> Self {
+    Saw { rate: 0, period: 0, is_enabled: false, timer: 0, step: 0, accumulator: 0 }
+  }
+
+  fn write(&mut self, addr: Addr, data: Data) {
+    match addr & 0x03 {
+      0x00 => self.rate = data & 0x3F,
+      0x01 => self.period = (self.period & 0x0F00) | data as Word,
+      0x02 => {
+        self.period = (self.period & 0x00FF) | ((data as Word & 0x0F) << 8);
+        self.is_enabled = data & 0x80 == 0x80;
+        if !self.is_enabled {
+          self.step = 0;
+          self.accumulator = 0;
+        }
+      }
+      _ => (),
+    }
+  }
+
+  // the accumulator is added on every other clock and reset on the 14th clock
+  fn step(&mut self, shift: Data) {
+    if !self.is_enabled {
+      return;
+    }
+    if self.timer > 0 {
+      self.timer -= 1;
+      return;
+    }
+    self.timer = self.period >> shift;
+    self.step += 1;
+    if self.step == 14 {
+      self.step = 0;
+      self.accumulator = 0;
+    } else if self.step & 1 == 0 {
+      self.accumulator = self.accumulator.wrapping_add(self.rate);
+    }
+  }
+
+  fn output(&self) -> Data {
+    self.accumulator >> 3
+  }
+}
+
+#[derive(Debug)]
+pub struct Vrc6Audio {
+  pulses: (Pulse, Pulse),
+  saw: Saw,
+  is_halted: bool,
+  shift: Data, // $9003 frequency scaling
+}
+
+impl Vrc6Audio {
+  pub fn new() -> Self {
+    Vrc6Audio { pulses: (Pulse::new(), Pulse::new()), saw: Saw::new(), is_halted: false, shift: 0 }
+  }
+
+  pub fn write(&mut self, addr: Addr, data: Data) {
+    match addr {
+      0x9003 => {
+        self.is_halted = data & 0x01 == 0x01;
+        self.shift = if data & 0x04 == 0x04 { 8 } else if data & 0x02 == 0x02 { 4 } else { 0 };
+      }
+      0x9000..=0x9002 => self.pulses.0.write(addr, data),
+      0xA000..=0xA002 => self.pulses.1.write(addr, data),
+      0xB000..=0xB002 => self.saw.write(addr, data),
+      _ => (),
+    }
+  }
+
+  pub fn step(&mut self) {
+    if self.is_halted {
+      return;
+    }
+    self.pulses.0.step(self.shift);
+    self.pulses.1.step(self.shift);
+    self.saw.step(self.shift);
+  }
+
+  // 0 - 61
+  pub fn output(&self) -> Data {
+    self.pulses.0.output() + self.pulses.1.output() + self.saw.output()
+  }
+}
+
+#[test]
+fn test_vrc6_pulse() {
+  let mut audio = Vrc6Audio::new();
+  audio.write(0x9000, 0x7F); // duty 8/16, volume 15
+  audio.write(0x9001, 0x00);
+  audio.write(0x9002, 0x80);
+  let mut levels = vec![];
+  for _ in 0..16 {
+    audio.step();
+    levels.push(audio.output());
+  }
+  assert_eq!(levels.iter().filter(|&&l| l == 15).count(), 8);
+}
+
+#[test]
+fn test_vrc6_saw() {
+  let mut audio = Vrc6Audio::new();
+  audio.write(0xB000, 0x08);
+  audio.write(0xB002, 0x80);
+  let mut levels = vec![];
+  for _ in 0..14 {
+    audio.step();
+    levels.push(audio.output());
+  }
+  assert_eq!(levels, vec![0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 0]);
+}
@@ -5,24 +5,32 @@ mod cpu;
 mod cpu_register;
 mod dma;
 mod types;
+mod hash;
 mod helper;
 mod keypad;
+mod patch;
 mod ram;
 mod rom;
 mod ppu;
 mod mapper;
+mod nsf_player;
 
 pub use self::apu::*;
 pub use self::keypad::*;
+pub use self::cassette_paser::{RomError, DatabaseMatch, NsfInfo, Region};
+pub use self::ppu::{NtscPalette, NtscSetup, Overscan, PaletteError, Scaler};
 use self::mapper::*;
 use self::bus::cpu_bus;
 use self::ram::Ram;
 use self::rom::Rom;
 use self::ppu::*;
 use self::dma::*;
+use self::nsf_player::NsfPlayer;
 pub use self::types::{Data, Addr, Word};
 
-const DMA_CYCLES: u16 = 514;
+extern "C" {
+  fn save_wav(ptr: *const Data, len: usize);
+}
 
 #[derive(Debug)]
 pub struct Context {
@@ -31,11 +39,17 @@ pub struct Context {
   ppu: Ppu,
   program_rom: Rom,
   sram: Ram,
+  has_battery: bool, // of the header or the database, only then sram is saved
   cpu_register: cpu_register::Register,
   dma: Dma,
   nmi: bool,
   keypad: Keypad,
   mapper: Box<dyn Mapper>,
+  database_match: Option<DatabaseMatch>,
+  header_region: Region, // of the header or the database
+  region: Region,
+  debug_input: Data,
+  nsf_player: Option<NsfPlayer>,
 }
 
 pub fn reset(ctx: &mut Context) {
@@ -50,6 +64,36 @@ pub fn reset(ctx: &mut Context) {
     &mut *ctx.mapper,
   );
   cpu::reset(&mut ctx.cpu_register, &mut cpu_bus);
+  if let Some(player) = ctx.nsf_player.as_ref() {
+    let track = player.info().starting_song - 1;
+    start_nsf_track(ctx, track);
+  }
+}
+
+// NSF: reset the memory, apu and banks then call INIT for the track (0 origin).
+pub fn start_nsf_track(ctx: &mut Context, track: Data) {
+  let banks = match ctx.nsf_player.as_ref() {
+    Some(player) => player.info().banks,
+    None => return,
+  };
+  for addr in 0..0x0800 {
+    ctx.work_ram.write(addr, 0);
+  }
+  for addr in 0x6000..=0x7FFF {
+    ctx.mapper.write(addr, 0, &mut ctx.sram, &mut ctx.ppu.config);
+  }
+  for (i, bank) in banks.iter().enumerate() {
+    ctx.mapper.write_expansion(0x5FF6 + i as Addr, *bank, &mut ctx.ppu.config);
+  }
+  for addr in 0x00..=0x13 {
+    ctx.apu.write(addr, 0);
+  }
+  ctx.apu.write(0x15, 0x00);
+  ctx.apu.write(0x15, 0x0F);
+  ctx.apu.write(0x17, 0x40);
+  if let Some(player) = ctx.nsf_player.as_mut() {
+    player.start(track, &mut ctx.cpu_register, &mut ctx.work_ram);
+  }
 }
 
 pub fn run(ctx: &mut Context, key_state: Data, debug_input: Data){
@@ -57,18 +101,44 @@ pub fn run(ctx: &mut Context, key_state: Data, debug_input: Data){
 
   // debug
   if debug_input & 0x01 == 0x01 {
-    ctx.sram.save();
+    if ctx.has_battery() {
+      ctx.sram.save();
+    }
+    ctx.mapper.save();
+  }
+  // switch to the next disk side on key down
+  if debug_input & !ctx.debug_input & 0x02 == 0x02 {
+    switch_disk_side(ctx);
+  }
+  // start or stop recording the audio, the mix is saved as WAV when it stops
+  if debug_input & !ctx.debug_input & 0x04 == 0x04 {
+    toggle_recording(ctx);
+  }
+  // remove or restore the 8 sprites a line limit
+  if debug_input & !ctx.debug_input & 0x08 == 0x08 {
+    let is_unlimited = !ctx.ppu.is_sprite_unlimited();
+    println!("sprite limit {}", if is_unlimited { "off" } else { "on" });
+    ctx.ppu.set_sprite_unlimited(is_unlimited);
+  }
+  ctx.debug_input = debug_input;
+  if let Some(track) = ctx.nsf_player.as_mut().and_then(|p| p.update_buttons(key_state)) {
+    ctx.select_nsf_track(track);
+  }
+  if let Some(player) = ctx.nsf_player.as_ref() {
+    ctx.apu.set_volume(player.volume());
   }
-
 
   let mut stall: u8 = 0;
   loop {
     let cycle: Word = if ctx.dma.is_should_run() {
-      ctx.dma.run(&ctx.work_ram, &mut ctx.ppu);
-      DMA_CYCLES
+      let cycle = ctx.dma.run(&ctx.work_ram, &mut ctx.ppu, ctx.apu.is_odd_cycle());
+      ctx.apu.start_oam_dma(cycle);
+      cycle
     } else if stall > 0 {
       stall -= 1;
       1
+    } else if is_nsf_idle(ctx) {
+      1 // waiting for the next PLAY call
     } else {
       let mut cpu_bus = cpu_bus::Bus::new(
         &mut ctx.apu,
@@ -83,24 +153,72 @@ pub fn run(ctx: &mut Context, key_state: Data, debug_input: Data){
       cpu::run(&mut ctx.cpu_register, &mut cpu_bus, &mut ctx.nmi) as Word
     };
     // want to pass the cpu_bus
+    ctx.mapper.step_cpu(cycle, &mut ctx.cpu_register);
     ctx.apu.run(cycle, &mut ctx.cpu_register, &mut *ctx.mapper, &ctx.sram, &ctx.program_rom, &mut stall);
+    let is_track_end = match ctx.nsf_player.as_mut() {
+      Some(player) => player.step(cycle, &mut ctx.cpu_register, &mut ctx.work_ram),
+      None => false,
+    };
+    if is_track_end {
+      if let Some(track) = ctx.nsf_player.as_mut().and_then(|p| p.next_track()) {
+        start_nsf_track(ctx, track);
+      }
+    }
     let mut is_ready = false;
-    for _ in 0..cycle*3 { // refactor: step for mapper
+    for _ in 0..ctx.ppu.dots(cycle as usize) { // refactor: step for mapper
       is_ready |= ctx.ppu.run(1 as usize, &mut ctx.nmi, &*ctx.mapper);
       ctx.mapper.step(&ctx.ppu,&mut ctx.cpu_register);
     }
 
     if is_ready {
+      ctx.apu.flush();
       break;
     }
   }
 }
 
+fn is_nsf_idle(ctx: &mut Context) -> bool {
+  match ctx.nsf_player.as_mut() {
+    Some(player) => player.is_idle(&ctx.cpu_register),
+    None => false,
+  }
+}
+
+// start or stop recording, the mix is saved as WAV when it stops
+fn toggle_recording(ctx: &mut Context) {
+  match ctx.stop_recording() {
+    Some(recording) => unsafe { save_wav(recording.mix.as_ptr(), recording.mix.len()) },
+    None => ctx.start_recording(false),
+  }
+}
+
+// Famicom Disk System: eject and insert the next side, back to side A after the last one.
+pub fn switch_disk_side(ctx: &mut Context) {
+  let count = ctx.mapper.disk_side_count();
+  if count == 0 {
+    return;
+  }
+  let next = ctx.mapper.disk_side().map_or(0, |side| (side + 1) % count);
+  println!("switch disk side to {}", next);
+  ctx.mapper.insert_disk(Some(next));
+}
+
 impl Context {
-  pub fn new(buf: &mut [Data], sram: &mut [Data]) -> Self {
-    let cassette = cassette_paser::parse(buf);
+  pub fn new(buf: &[Data], sram: &[Data]) -> Result<Self, RomError> {
+    let cassette = cassette_paser::load(buf)?;
+    Ok(Context::from_cassette(cassette, sram))
+  }
+
+  // *.fds disk image (or a saved disk) with the disksys.rom BIOS
+  pub fn new_fds(disk: &[Data], bios: &[Data], sram: &[Data]) -> Result<Self, RomError> {
+    let cassette = cassette_paser::fds::parse(disk, bios)?;
+    Ok(Context::from_cassette(cassette, sram))
+  }
+
+  fn from_cassette(cassette: cassette_paser::Cassette, sram: &[Data]) -> Self {
     let mapper = Mapper::new(&cassette);
-    Context {
+    let nsf_player = cassette.nsf.map(|info| NsfPlayer::new(info, CPU_CLOCK));
+    let mut ctx = Context {
       apu: Apu::new(),
       cpu_register: cpu_register::Register::new(),
       program_rom: Rom::new(cassette.program_rom),
@@ -112,10 +230,268 @@ impl Context {
       ),
       work_ram: Ram::new(vec![0;0x2000]),
       sram: Ram::new(sram.to_vec()),
+      has_battery: cassette.has_battery,
       dma: Dma::new(),
       nmi: false,
       keypad: Keypad::new(),
       mapper: mapper,
+      database_match: cassette.database_match,
+      header_region: cassette.region,
+      region: Region::Ntsc,
+      debug_input: 0,
+      nsf_player,
+    };
+    ctx.set_region(None);
+    ctx
+  }
+
+  // Apply an IPS / UPS / BPS patch to the raw rom file before loading it.
+  pub fn new_with_patch(buf: &[Data], patch: &[Data], sram: &[Data]) -> Result<Self, RomError> {
+    let patched = patch::apply(buf, patch)?;
+    Context::new(&patched, sram)
+  }
+
+  // NSF metadata (title, artist, copyright, track lengths) when playing a *.nsf
+  pub fn nsf_info(&self) -> Option<&NsfInfo> {
+    self.nsf_player.as_ref().map(|p| p.info())
+  }
+
+  // NSF: current track, 0 origin
+  pub fn nsf_track(&self) -> Option<Data> {
+    self.nsf_player.as_ref().map(|p| p.track())
+  }
+
+  pub fn select_nsf_track(&mut self, track: Data) {
+    start_nsf_track(self, track);
+  }
+
+  pub fn has_battery(&self) -> bool {
+    self.has_battery
+  }
+
+  // Some when the rom was found in the built-in database, with the header fields it corrected.
+  pub fn database_match(&self) -> Option<&DatabaseMatch> {
+    self.database_match.as_ref()
+  }
+
+  // NTSC, PAL or Dendy timing, None for the region of the rom, multi-region roms run as NTSC
+  pub fn set_region(&mut self, region: Option<Region>) {
+    self.region = match region.unwrap_or(self.header_region) {
+      Region::Multi => Region::Ntsc,
+      region => region,
+    };
+    self.apu.set_region(self.region);
+    self.ppu.set_region(self.region);
+    let cpu_clock = self.apu.cpu_clock();
+    if let Some(player) = self.nsf_player.as_mut() {
+      player.set_cpu_clock(cpu_clock);
     }
   }
+
+  pub fn region(&self) -> Region {
+    self.region
+  }
+
+  // crop the picture from the next frame, 256x240 without overscan
+  pub fn set_overscan(&mut self, overscan: Overscan) {
+    self.ppu.set_overscan(overscan);
+  }
+
+  // upscale the frame, None for the size of the ppu output
+  pub fn set_scaler(&mut self, scaler: Option<Scaler>) {
+    self.ppu.set_scaler(scaler);
+  }
+
+  // RGBA of the last frame, (width, height) from the overscan, the ntsc filter and the scaler
+  pub fn frame(&self) -> (&[Data], usize, usize) {
+    let (width, height) = self.ppu.frame_size();
+    (self.ppu.frame(), width, height)
+  }
+
+  // decode the composite signal through the tv controls, None for the plain colors
+  pub fn set_ntsc_filter(&mut self, setup: Option<NtscSetup>, controls: NtscPalette) {
+    self.ppu.set_ntsc_filter(setup.map(|setup| NtscFilter::new(setup, controls)));
+  }
+
+  // *.pal of 192 or 1536 bytes
+  pub fn load_palette(&mut self, pal: &[Data]) -> Result<(), PaletteError> {
+    let colors = load_pal(pal)?;
+    self.ppu.set_colors(colors);
+    Ok(())
+  }
+
+  pub fn set_ntsc_palette(&mut self, palette: NtscPalette) {
+    self.ppu.set_colors(palette.generate());
+  }
+
+  // the colors in use as a 1536 bytes *.pal
+  pub fn palette_file(&self) -> Vec<Data> {
+    encode_pal(self.ppu.colors())
+  }
+
+  // channel states and scopes for visualizers
+  pub fn apu(&self) -> &Apu {
+    &self.apu
+  }
+
+  // sound controls, e.g. channel mute / solo / gain, master volume and output filters
+  pub fn apu_mut(&mut self) -> &mut Apu {
+    &mut self.apu
+  }
+
+  // capture the audio output to WAV, see Apu::start_recording
+  pub fn start_recording(&mut self, per_channel: bool) {
+    self.apu.start_recording(per_channel);
+  }
+
+  pub fn stop_recording(&mut self) -> Option<Recording> {
+    self.apu.stop_recording()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::fs;
+  use super::super::externs::native;
+
+  // run a rom headless, returns the audio played
+  pub fn run_rom(path: &str, frames: usize) -> Vec<i16> {
+    let buf = fs::read(path).unwrap();
+    let sram = vec![0; 0x2000];
+    let mut ctx = Context::new(&buf, &sram).unwrap();
+    reset(&mut ctx);
+    native::take_samples();
+    for _ in 0..frames {
+      run(&mut ctx, 0, 0);
+    }
+    native::take_samples()
+  }
+
+  // whether each frame has a tone (not only a dc step)
+  pub fn frames_with_sound(samples: &[i16]) -> Vec<bool> {
+    samples
+      .chunks(735)
+      .map(|frame| {
+        let max = *frame.iter().max().unwrap() as i32;
+        let min = *frame.iter().min().unwrap() as i32;
+        max - min > 2000
+      })
+      .collect()
+  }
+
+  // NES 2.0 header with the timing byte
+  fn nes2_rom(timing: Data) -> Vec<Data> {
+    let mut buf = vec![0; 0x10 + 0x4000 + 0x2000];
+    buf[0..4].copy_from_slice(b"NES\x1A");
+    buf[4] = 1;
+    buf[5] = 1;
+    buf[7] = 0x08;
+    buf[12] = timing;
+    buf
+  }
+
+  #[test]
+  fn test_region() {
+    let sram = vec![0; 0x2000];
+    let mut ctx = Context::new(&nes2_rom(0x01), &sram).unwrap();
+    assert_eq!(ctx.region(), Region::Pal);
+    assert_eq!(ctx.apu().cpu_clock(), 1662607);
+    ctx.set_region(Some(Region::Dendy));
+    assert_eq!(ctx.region(), Region::Dendy);
+    ctx.set_region(None);
+    assert_eq!(ctx.region(), Region::Pal);
+    // multi-region runs as NTSC
+    let ctx = Context::new(&nes2_rom(0x02), &sram).unwrap();
+    assert_eq!(ctx.region(), Region::Ntsc);
+    assert_eq!(ctx.apu().cpu_clock(), CPU_CLOCK);
+  }
+
+  #[test]
+  fn test_battery() {
+    let sram = vec![0; 0x2000];
+    assert!(!Context::new(&nes2_rom(0x00), &sram).unwrap().has_battery());
+    let mut buf = nes2_rom(0x00);
+    buf[6] |= 0x02;
+    assert!(Context::new(&buf, &sram).unwrap().has_battery());
+  }
+
+  // the first part only holds the linear counter with $4017 writes, it must be silent,
+  // a noise beep starts the second part where each tone lasts until the next manual clock
+  #[test]
+  fn test_lin_ctr_rom() {
+    let sound = frames_with_sound(&run_rom("roms/apu/lin_ctr.nes", 340));
+    assert!(sound[20..250].iter().all(|s| !s));
+    assert!(sound[252..260].iter().all(|s| *s));
+    assert!(sound[268..340].iter().all(|s| *s));
+  }
+
+  // the tones with targets over $7FF must be muted though the sweeps are disabled
+  #[test]
+  fn test_sweep_cutoff_rom() {
+    let sound = frames_with_sound(&run_rom("roms/apu/sweep_cutoff.nes", 260));
+    assert!(sound[20..130].iter().all(|s| !s));
+    assert!(sound[140..250].iter().all(|s| *s));
+  }
+
+  // every tone is swept down to about $1FF then its high bits are rewritten,
+  // pulse 1 negates by the one's complement so no tone jumps an octave up
+  #[test]
+  fn test_sweep_sub_rom() {
+    let samples = run_rom("roms/apu/sweep_sub.nes", 180);
+    let crossings: Vec<usize> = samples[16 * 735..]
+      .chunks(735)
+      .map(|frame| frame.windows(2).filter(|w| (w[0] < 0) != (w[1] < 0)).count())
+      .collect();
+    // 218 Hz is about 14.5 zero crossings a frame
+    assert!(crossings.iter().all(|c| *c > 4 && *c < 25));
+  }
+
+  // the peak to peak level of each frame
+  fn frame_levels(samples: &[i16]) -> Vec<i32> {
+    samples
+      .chunks(735)
+      .map(|frame| *frame.iter().max().unwrap() as i32 - *frame.iter().min().unwrap() as i32)
+      .collect()
+  }
+
+  // Hz of a square tone by its rising edges
+  fn frequency(samples: &[i16]) -> f64 {
+    let mean = samples.iter().map(|s| *s as i32).sum::<i32>() / samples.len() as i32;
+    let mut is_high = samples[0] as i32 > mean;
+    let mut edges = Vec::new();
+    for (i, sample) in samples.iter().enumerate() {
+      let sample = *sample as i32;
+      if !is_high && sample > mean + 1000 {
+        is_high = true;
+        edges.push(i);
+      } else if is_high && sample < mean - 1000 {
+        is_high = false;
+      }
+    }
+    44100.0 * (edges.len() - 1) as f64 / (edges[edges.len() - 1] - edges[0]) as f64
+  }
+
+  // the tone gets louder in 15 steps, one every 75 quarter frames of the sequencer
+  // (18.75 frames at 240 Hz), then it is held until the end
+  #[test]
+  fn test_apu_env_rom() {
+    let levels = frame_levels(&run_rom("roms/apu/test_apu_env.nes", 470));
+    let rises: Vec<usize> = (1..levels.len()).filter(|i| levels[*i] - levels[*i - 1] > 150).collect();
+    assert_eq!(rises.len(), 15);
+    assert!(rises.windows(2).all(|w| w[1] - w[0] == 18 || w[1] - w[0] == 19));
+    assert_eq!(rises[14] - rises[0], 263);
+    assert!(levels[rises[14] + 100] > levels[rises[0]] * 10);
+    assert!(levels[465] < 100);
+  }
+
+  // the square timer is clocked every other cpu cycle,
+  // $4002 = $DF is 1789772 / 16 / 224 = 499 Hz, then $FF is 437 Hz
+  #[test]
+  fn test_square_timer_div2_rom() {
+    let samples = run_rom("roms/apu/square_timer_div2.nes", 70);
+    let tone = |from: usize, to: usize| frequency(&samples[from * 735..to * 735]);
+    assert!((tone(38, 50) - 499.4).abs() < 2.0);
+    assert!((tone(53, 65) - 436.9).abs() < 2.0);
+  }
 }
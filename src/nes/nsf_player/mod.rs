@@ -0,0 +1,227 @@
+use super::types::{Data, Addr, Word};
+use super::cpu_register::CpuRegister;
+use super::ram::Ram;
+use super::cassette_paser::{NsfInfo, Region};
+
+// INIT and PLAY return to this address, the player stops the cpu before it is fetched.
+const RETURN_ADDR: Addr = 0x5FF5;
+// msec, used when the NSF2 time / fade chunks don't specify the track
+pub const DEFAULT_TRACK_LENGTH: u32 = 180_000;
+pub const DEFAULT_FADE_LENGTH: u32 = 8_000;
+
+const BUTTON_LEFT: Data = 0x40;
+const BUTTON_RIGHT: Data = 0x80;
+
+#[derive(Debug)]
+pub struct NsfPlayer {
+  info: NsfInfo,
+  cpu_clock: usize,
+  track: Data, // 0 origin
+  play_period: u32,
+  play_timer: u32,
+  is_running: bool,     // the cpu is executing INIT or PLAY
+  is_initialized: bool, // INIT returned
+  is_stopped: bool,
+  elapsed: u64,         // cpu cycles since INIT
+  length: u64,
+  fade: u64,
+  buttons: Data,
+}
+
+impl NsfPlayer {
+  pub fn new(info: NsfInfo, cpu_clock: usize) -> Self {
+    let play_period = info.play_period(cpu_clock).max(1);
+    NsfPlayer {
+      info,
+      cpu_clock,
+      track: 0,
+      play_period,
+      play_timer: 0,
+      is_running: false,
+      is_initialized: false,
+      is_stopped: true,
+      elapsed: 0,
+      length: 0,
+      fade: 0,
+      buttons: 0,
+    }
+  }
+
+  // the PLAY period follows now, the track length from the next start
+  pub fn set_cpu_clock(&mut self, cpu_clock: usize) {
+    self.cpu_clock = cpu_clock;
+    self.play_period = self.info.play_period(cpu_clock).max(1);
+  }
+
+  pub fn info(&self) -> &NsfInfo {
+    &self.info
+  }
+
+  pub fn track(&self) -> Data {
+    self.track
+  }
+
+  fn to_cycles(&self, msec: u32) -> u64 {
+    msec as u64 * self.cpu_clock as u64 / 1000
+  }
+
+  // JSR from the player: push the return address and jump
+  fn call<T: CpuRegister>(&mut self, addr: Addr, register: &mut T, work_ram: &mut Ram) {
+    let ret = RETURN_ADDR - 1;
+    work_ram.write(0x0100 | register.get_S() as Addr, (ret >> 8) as Data);
+    register.dec_S();
+    work_ram.write(0x0100 | register.get_S() as Addr, ret as Data);
+    register.dec_S();
+    register.set_PC(addr);
+    self.is_running = true;
+  }
+
+  // Call INIT for the track. The caller resets the memory, apu and banks beforehand.
+  pub fn start<T: CpuRegister>(&mut self, track: Data, register: &mut T, work_ram: &mut Ram) {
+    self.track = track;
+    let index = track as usize;
+    let length = self.info.track_lengths.get(index).cloned().flatten().unwrap_or(DEFAULT_TRACK_LENGTH);
+    let fade = self.info.track_fades.get(index).cloned().flatten().unwrap_or(DEFAULT_FADE_LENGTH);
+    self.length = self.to_cycles(length);
+    self.fade = self.to_cycles(fade);
+    self.elapsed = 0;
+    self.play_timer = self.play_period;
+    self.is_initialized = false;
+    self.is_stopped = false;
+    match self.info.track_labels.get(index) {
+      Some(label) => println!("nsf track {}/{}: {} ({} ms)", index + 1, self.info.total_songs, label, length),
+      None => println!("nsf track {}/{} ({} ms)", index + 1, self.info.total_songs, length),
+    }
+
+    register.set_A(track);
+    register.set_X(if self.info.region == Region::Pal { 1 } else { 0 });
+    register.set_S(0xFD);
+    register.set_status_interrupt(true);
+    self.call(self.info.init_addr, register, work_ram);
+  }
+
+  // true while the cpu waits for the next PLAY call
+  pub fn is_idle<T: CpuRegister>(&mut self, register: &T) -> bool {
+    if self.is_running && register.get_PC() == RETURN_ADDR {
+      self.is_running = false;
+      self.is_initialized = true;
+    }
+    !self.is_running
+  }
+
+  // 1.0 until the track length, then fades out to 0.0
+  pub fn volume(&self) -> f32 {
+    if self.is_stopped {
+      0.0
+    } else if self.elapsed <= self.length {
+      1.0
+    } else if self.fade == 0 || self.elapsed >= self.length + self.fade {
+      0.0
+    } else {
+      1.0 - (self.elapsed - self.length) as f32 / self.fade as f32
+    }
+  }
+
+  // Call PLAY at the tune's rate. Returns true when the track has faded out.
+  pub fn step<T: CpuRegister>(&mut self, cycle: Word, register: &mut T, work_ram: &mut Ram) -> bool {
+    if self.is_stopped {
+      return false;
+    }
+    self.elapsed += cycle as u64;
+    let cycle = cycle as u32;
+    if self.play_timer > cycle {
+      self.play_timer -= cycle;
+    } else {
+      self.play_timer = self.play_period.saturating_sub(cycle - self.play_timer).max(1);
+      // a PLAY still running (or a non returning INIT) skips the call
+      if !self.is_running && self.is_initialized {
+        self.call(self.info.play_addr, register, work_ram);
+      }
+    }
+    self.volume() <= 0.0
+  }
+
+  // The next track after the current one ended, None after the last track.
+  pub fn next_track(&mut self) -> Option<Data> {
+    if self.track + 1 < self.info.total_songs {
+      Some(self.track + 1)
+    } else {
+      println!("nsf finished");
+      self.is_stopped = true;
+      None
+    }
+  }
+
+  // left / right on the pad selects the previous / next track
+  pub fn update_buttons(&mut self, key_state: Data) -> Option<Data> {
+    let pressed = key_state & !self.buttons;
+    self.buttons = key_state;
+    let count = self.info.total_songs.max(1);
+    if pressed & BUTTON_RIGHT == BUTTON_RIGHT {
+      Some((self.track + 1) % count)
+    } else if pressed & BUTTON_LEFT == BUTTON_LEFT {
+      Some((self.track + count - 1) % count)
+    } else {
+      None
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use super::super::cpu_register::Register;
+  use super::super::cassette_paser::nsf::{self, test::build_nsf};
+
+  fn player() -> NsfPlayer {
+    let cassette = nsf::parse(&build_nsf(0x8000, &[0; 8], &[0x60; 0x10])).unwrap();
+    NsfPlayer::new(cassette.nsf.unwrap(), 1789772)
+  }
+
+  #[test]
+  fn test_call_init_and_play() {
+    let mut player = player();
+    let mut register = Register::new();
+    let mut work_ram = Ram::new(vec![0; 0x800]);
+    player.start(1, &mut register, &mut work_ram);
+    assert_eq!(register.get_PC(), 0x8000);
+    assert_eq!(register.get_A(), 1);
+    assert_eq!(register.get_S(), 0xFB);
+    assert_eq!(work_ram.read(0x01FD), 0x5F);
+    assert_eq!(work_ram.read(0x01FC), 0xF4);
+    assert!(!player.is_idle(&register));
+
+    // RTS from INIT
+    register.set_PC(RETURN_ADDR).set_S(0xFD);
+    assert!(player.is_idle(&register));
+    assert!(!player.step(29779, &mut register, &mut work_ram));
+    assert!(player.is_idle(&register));
+    player.step(1, &mut register, &mut work_ram);
+    assert_eq!(register.get_PC(), 0x8003);
+    assert!(!player.is_idle(&register));
+  }
+
+  #[test]
+  fn test_fade_out() {
+    let mut player = player();
+    let mut register = Register::new();
+    let mut work_ram = Ram::new(vec![0; 0x800]);
+    player.start(0, &mut register, &mut work_ram);
+    player.length = 100;
+    player.fade = 100;
+    assert!(!player.step(100, &mut register, &mut work_ram));
+    assert_eq!(player.volume(), 1.0);
+    assert!(!player.step(50, &mut register, &mut work_ram));
+    assert_eq!(player.volume(), 0.5);
+    assert!(player.step(50, &mut register, &mut work_ram));
+    assert_eq!(player.next_track(), Some(1));
+  }
+
+  #[test]
+  fn test_update_buttons() {
+    let mut player = player();
+    assert_eq!(player.update_buttons(BUTTON_LEFT), Some(2));
+    assert_eq!(player.update_buttons(BUTTON_LEFT), None);
+    assert_eq!(player.update_buttons(BUTTON_RIGHT), Some(1));
+  }
+}
@@ -0,0 +1,136 @@
+use super::super::types::Data;
+use super::{check_target_size, read_footer, verify_source, verify_target, PatchError, Reader};
+
+// ref. https://www.romhacking.net/documents/746/
+pub const BPS_MAGIC: &[Data] = b"BPS1";
+
+const SOURCE_READ: usize = 0;
+const TARGET_READ: usize = 1;
+const SOURCE_COPY: usize = 2;
+const TARGET_COPY: usize = 3;
+
+pub fn apply(rom: &[Data], patch: &[Data]) -> Result<Vec<Data>, PatchError> {
+  let footer = read_footer(patch)?;
+  verify_source(rom, &footer)?;
+  let body_end = patch.len() - 12;
+  let mut reader = Reader::new(&patch[..body_end], BPS_MAGIC.len());
+  let source_size = reader.read_number()?;
+  let target_size = check_target_size(reader.read_number()?)?;
+  if source_size != rom.len() {
+    return Err(PatchError::SourceSizeMismatch { expected: source_size, actual: rom.len() });
+  }
+  let metadata_size = reader.read_number()?;
+  reader.read_slice(metadata_size)?;
+
+  let mut target: Vec<Data> = Vec::with_capacity(target_size);
+  let mut source_offset: usize = 0;
+  let mut target_offset: usize = 0;
+  while reader.pos < body_end {
+    let data = reader.read_number()?;
+    let len = (data >> 2) + 1;
+    // no command writes past the target size
+    let offset = target.len();
+    match offset.checked_add(len) {
+      Some(end) if end <= target_size => (),
+      _ => return Err(PatchError::OutOfBounds { offset, len }),
+    }
+    match data & 0x03 {
+      SOURCE_READ => {
+        let src = rom.get(offset..offset + len).ok_or(PatchError::OutOfBounds { offset, len })?;
+        target.extend_from_slice(src);
+      }
+      TARGET_READ => target.extend_from_slice(reader.read_slice(len)?),
+      SOURCE_COPY => {
+        source_offset = relative(source_offset, reader.read_number()?)?;
+        let end = source_offset.checked_add(len).ok_or(PatchError::OutOfBounds { offset: source_offset, len })?;
+        let src = rom.get(source_offset..end).ok_or(PatchError::OutOfBounds { offset: source_offset, len })?;
+        target.extend_from_slice(src);
+        source_offset = end;
+      }
+      TARGET_COPY => {
+        target_offset = relative(target_offset, reader.read_number()?)?;
+        if target_offset >= target.len() {
+          return Err(PatchError::OutOfBounds { offset: target_offset, len });
+        }
+        // The copy may overlap with the bytes being written, so it has to be byte by byte.
+        for _ in 0..len {
+          let v = target[target_offset];
+          target.push(v);
+          target_offset += 1;
+        }
+      }
+      _ => unreachable!(),
+    }
+  }
+  if target.len() != target_size {
+    return Err(PatchError::Truncated);
+  }
+  verify_target(&target, &footer)?;
+  Ok(target)
+}
+
+// bit 0 is the sign of the offset
+fn relative(offset: usize, data: usize) -> Result<usize, PatchError> {
+  let delta = data >> 1;
+  let result = if data & 0x01 == 0x01 {
+    offset.checked_sub(delta)
+  } else {
+    offset.checked_add(delta)
+  };
+  result.ok_or(PatchError::OutOfBounds { offset, len: delta })
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use super::super::with_footer;
+
+  #[test]
+  fn test_apply() {
+    let rom = vec![1, 2, 3, 4];
+    let target = vec![1, 2, 9, 9, 9, 9, 3, 4];
+    let body = [
+      b'B', b'P', b'S', b'1', 0x84, 0x88, 0x80,
+      0x84 | SOURCE_READ as u8,         // source read 2 bytes
+      0x80 | TARGET_READ as u8, 9,      // target read 1 byte
+      0x88 | TARGET_COPY as u8, 0x84,   // target copy 3 bytes from target offset 2
+      0x84 | SOURCE_COPY as u8, 0x84,   // source copy 2 bytes from source offset 2
+    ];
+    let patch = with_footer(&rom, &target, &body);
+    assert_eq!(super::super::apply(&rom, &patch).unwrap(), target);
+  }
+
+  #[test]
+  fn test_apply_out_of_bounds() {
+    let rom = vec![1, 2, 3, 4];
+    // target size 2, target read 1 byte then target copy 0x4000_0000 bytes
+    let body = [
+      b'B', b'P', b'S', b'1', 0x84, 0x82, 0x80,
+      0x80 | TARGET_READ as u8, 9,
+      0x7F, 0x7F, 0x7F, 0x7F | TARGET_COPY as u8, 0x80, 0x80,
+    ];
+    let patch = with_footer(&rom, &[9, 9], &body);
+    match apply(&rom, &patch) {
+      Err(PatchError::OutOfBounds { offset: 1, .. }) => (),
+      result => panic!("{:?}", result),
+    }
+    // a target size over the limit
+    let body = [b'B', b'P', b'S', b'1', 0x84, 0x7F, 0x7F, 0x7F, 0x7F, 0x80, 0x80];
+    let patch = with_footer(&rom, &[9, 9], &body);
+    match apply(&rom, &patch) {
+      Err(PatchError::OutOfBounds { offset: 0, .. }) => (),
+      result => panic!("{:?}", result),
+    }
+  }
+
+  #[test]
+  fn test_apply_source_mismatch() {
+    let body = [b'B', b'P', b'S', b'1', 0x84, 0x84, 0x80, 0x8C];
+    let patch = with_footer(&[1, 2, 3, 4], &[1, 2, 3, 4], &body);
+    assert_eq!(apply(&[1, 2, 3], &patch), Err(PatchError::SourceChecksumMismatch {
+      expected: super::super::super::hash::crc32(&[1, 2, 3, 4]),
+      actual: super::super::super::hash::crc32(&[1, 2, 3]),
+    }));
+    assert_eq!(apply(&[1, 2, 3, 4], &patch).unwrap(), vec![1, 2, 3, 4]);
+  }
+}
@@ -0,0 +1,73 @@
+use super::super::types::Data;
+use super::{PatchError, Reader};
+
+// ref. http://fileformats.archiveteam.org/wiki/IPS_(binary_patch_format)
+pub const IPS_MAGIC: &[Data] = b"PATCH";
+const IPS_EOF: usize = 0x454F46; // "EOF"
+
+pub fn apply(rom: &[Data], patch: &[Data]) -> Result<Vec<Data>, PatchError> {
+  let mut target = rom.to_vec();
+  let mut reader = Reader::new(patch, IPS_MAGIC.len());
+  loop {
+    let offset = reader.read_be(3)?;
+    if offset == IPS_EOF {
+      break;
+    }
+    let size = reader.read_be(2)?;
+    if size == 0 {
+      // RLE record
+      let count = reader.read_be(2)?;
+      let value = reader.read()?;
+      write(&mut target, offset, &vec![value; count]);
+    } else {
+      let data = reader.read_slice(size)?;
+      write(&mut target, offset, data);
+    }
+  }
+  // Truncation extension: 3 bytes of new file size after "EOF"
+  if let Ok(size) = reader.read_be(3) {
+    target.truncate(size);
+  }
+  Ok(target)
+}
+
+fn write(target: &mut Vec<Data>, offset: usize, data: &[Data]) {
+  if target.len() < offset + data.len() {
+    target.resize(offset + data.len(), 0);
+  }
+  target[offset..offset + data.len()].copy_from_slice(data);
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_apply() {
+    let rom = vec![0; 8];
+    let mut patch = b"PATCH".to_vec();
+    patch.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x02, 0xAA, 0xBB]); // write 2 bytes at 1
+    patch.extend_from_slice(&[0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x03, 0xCC]); // rle 3 bytes at 4
+    patch.extend_from_slice(&[0x00, 0x00, 0x09, 0x00, 0x01, 0xDD]); // extend the rom
+    patch.extend_from_slice(b"EOF");
+    assert_eq!(
+      super::super::apply(&rom, &patch).unwrap(),
+      vec![0x00, 0xAA, 0xBB, 0x00, 0xCC, 0xCC, 0xCC, 0x00, 0x00, 0xDD]
+    );
+  }
+
+  #[test]
+  fn test_apply_truncate() {
+    let mut patch = b"PATCH".to_vec();
+    patch.extend_from_slice(b"EOF");
+    patch.extend_from_slice(&[0x00, 0x00, 0x04]);
+    assert_eq!(apply(&[1; 8], &patch).unwrap(), vec![1; 4]);
+  }
+
+  #[test]
+  fn test_apply_truncated_patch() {
+    let mut patch = b"PATCH".to_vec();
+    patch.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x02, 0xAA]);
+    assert_eq!(apply(&[0; 8], &patch), Err(PatchError::Truncated));
+  }
+}
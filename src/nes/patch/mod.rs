@@ -0,0 +1,172 @@
+mod ips;
+mod ups;
+mod bps;
+
+use std::fmt;
+use super::types::Data;
+
+#[derive(Debug, PartialEq)]
+pub enum PatchError {
+  UnknownFormat,
+  Truncated,
+  OutOfBounds { offset: usize, len: usize },
+  SourceSizeMismatch { expected: usize, actual: usize },
+  SourceChecksumMismatch { expected: u32, actual: u32 },
+  TargetChecksumMismatch { expected: u32, actual: u32 },
+  PatchChecksumMismatch { expected: u32, actual: u32 },
+}
+
+impl fmt::Display for PatchError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      PatchError::UnknownFormat => write!(f, "Unknown patch format, expected IPS, UPS or BPS."),
+      PatchError::Truncated => write!(f, "Patch file is truncated."),
+      PatchError::OutOfBounds { offset, len } => {
+        write!(f, "Patch reads outside of the rom (offset 0x{:x}, size 0x{:x}).", offset, len)
+      }
+      PatchError::SourceSizeMismatch { expected, actual } => {
+        write!(f, "Patch expects a {} bytes rom but got {} bytes.", expected, actual)
+      }
+      PatchError::SourceChecksumMismatch { expected, actual } => {
+        write!(f, "Patch is for another rom: crc32 expected {:08X}, got {:08X}.", expected, actual)
+      }
+      PatchError::TargetChecksumMismatch { expected, actual } => {
+        write!(f, "Patched rom is broken: crc32 expected {:08X}, got {:08X}.", expected, actual)
+      }
+      PatchError::PatchChecksumMismatch { expected, actual } => {
+        write!(f, "Patch file is corrupted: crc32 expected {:08X}, got {:08X}.", expected, actual)
+      }
+    }
+  }
+}
+
+impl std::error::Error for PatchError {}
+
+// Detect the patch format by its magic bytes and return the patched rom.
+pub fn apply(rom: &[Data], patch: &[Data]) -> Result<Vec<Data>, PatchError> {
+  if patch.starts_with(ips::IPS_MAGIC) {
+    ips::apply(rom, patch)
+  } else if patch.starts_with(ups::UPS_MAGIC) {
+    ups::apply(rom, patch)
+  } else if patch.starts_with(bps::BPS_MAGIC) {
+    bps::apply(rom, patch)
+  } else {
+    Err(PatchError::UnknownFormat)
+  }
+}
+
+// far larger than any rom, a bigger target size is a broken patch
+const MAX_TARGET_SIZE: usize = 0x0400_0000;
+
+// UPS / BPS target size from the header
+fn check_target_size(target_size: usize) -> Result<usize, PatchError> {
+  if target_size > MAX_TARGET_SIZE {
+    return Err(PatchError::OutOfBounds { offset: 0, len: target_size });
+  }
+  Ok(target_size)
+}
+
+// Cursor over the patch body shared by all formats.
+struct Reader<'a> {
+  buf: &'a [Data],
+  pos: usize,
+}
+
+impl<'a> Reader<'a> {
+  fn new(buf: &'a [Data], pos: usize) -> Self {
+    Reader { buf, pos }
+  }
+
+  fn read(&mut self) -> Result<Data, PatchError> {
+    let data = *self.buf.get(self.pos).ok_or(PatchError::Truncated)?;
+    self.pos += 1;
+    Ok(data)
+  }
+
+  fn read_slice(&mut self, len: usize) -> Result<&'a [Data], PatchError> {
+    if self.buf.len() - self.pos < len {
+      return Err(PatchError::Truncated);
+    }
+    let slice = &self.buf[self.pos..self.pos + len];
+    self.pos += len;
+    Ok(slice)
+  }
+
+  fn read_be(&mut self, len: usize) -> Result<usize, PatchError> {
+    Ok(self.read_slice(len)?.iter().fold(0, |v, &b| v << 8 | b as usize))
+  }
+
+  // UPS / BPS variable length integer
+  fn read_number(&mut self) -> Result<usize, PatchError> {
+    let mut value: usize = 0;
+    let mut shift: usize = 1;
+    loop {
+      let x = self.read()?;
+      value = value.checked_add((x & 0x7F) as usize * shift).ok_or(PatchError::Truncated)?;
+      if x & 0x80 == 0x80 {
+        return Ok(value);
+      }
+      shift = shift.checked_shl(7).ok_or(PatchError::Truncated)?;
+      value = value.checked_add(shift).ok_or(PatchError::Truncated)?;
+    }
+  }
+}
+
+// UPS and BPS end with crc32 of source, target and the patch itself.
+struct Footer {
+  source_crc: u32,
+  target_crc: u32,
+}
+
+fn read_footer(patch: &[Data]) -> Result<Footer, PatchError> {
+  if patch.len() < 16 {
+    return Err(PatchError::Truncated);
+  }
+  let crc = |i: usize| u32::from_le_bytes([patch[i], patch[i + 1], patch[i + 2], patch[i + 3]]);
+  let footer = patch.len() - 12;
+  let expected = crc(footer + 8);
+  let actual = super::hash::crc32(&patch[..footer + 8]);
+  if expected != actual {
+    return Err(PatchError::PatchChecksumMismatch { expected, actual });
+  }
+  Ok(Footer { source_crc: crc(footer), target_crc: crc(footer + 4) })
+}
+
+fn verify_source(rom: &[Data], footer: &Footer) -> Result<(), PatchError> {
+  let actual = super::hash::crc32(rom);
+  if actual != footer.source_crc {
+    return Err(PatchError::SourceChecksumMismatch { expected: footer.source_crc, actual });
+  }
+  Ok(())
+}
+
+fn verify_target(target: &[Data], footer: &Footer) -> Result<(), PatchError> {
+  let actual = super::hash::crc32(target);
+  if actual != footer.target_crc {
+    return Err(PatchError::TargetChecksumMismatch { expected: footer.target_crc, actual });
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+fn with_footer(rom: &[Data], target: &[Data], body: &[Data]) -> Vec<Data> {
+  let mut patch = body.to_vec();
+  patch.extend_from_slice(&super::hash::crc32(rom).to_le_bytes());
+  patch.extend_from_slice(&super::hash::crc32(target).to_le_bytes());
+  let crc = super::hash::crc32(&patch);
+  patch.extend_from_slice(&crc.to_le_bytes());
+  patch
+}
+
+#[test]
+fn test_apply_unknown_format() {
+  assert_eq!(apply(&[0; 4], b"NOPE"), Err(PatchError::UnknownFormat));
+}
+
+#[test]
+fn test_read_number() {
+  let mut r = Reader::new(&[0x80, 0x7F, 0x80, 0x00, 0x80], 0);
+  assert_eq!(r.read_number(), Ok(0));
+  assert_eq!(r.read_number(), Ok(0xFF));
+  assert_eq!(r.read_number(), Ok(0x80));
+}
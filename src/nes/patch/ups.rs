@@ -0,0 +1,75 @@
+use super::super::types::Data;
+use super::{check_target_size, read_footer, verify_source, verify_target, PatchError, Reader};
+
+// ref. https://www.romhacking.net/documents/392/
+pub const UPS_MAGIC: &[Data] = b"UPS1";
+
+pub fn apply(rom: &[Data], patch: &[Data]) -> Result<Vec<Data>, PatchError> {
+  let footer = read_footer(patch)?;
+  verify_source(rom, &footer)?;
+  let body_end = patch.len() - 12;
+  let mut reader = Reader::new(&patch[..body_end], UPS_MAGIC.len());
+  let source_size = reader.read_number()?;
+  let target_size = check_target_size(reader.read_number()?)?;
+  if source_size != rom.len() {
+    return Err(PatchError::SourceSizeMismatch { expected: source_size, actual: rom.len() });
+  }
+
+  let mut target = rom.to_vec();
+  target.resize(target_size, 0);
+  let mut pos = 0;
+  while reader.pos < body_end {
+    pos += reader.read_number()?;
+    loop {
+      let x = reader.read()?;
+      if x == 0 {
+        pos += 1;
+        break;
+      }
+      if pos < target_size {
+        target[pos] ^= x;
+      }
+      pos += 1;
+    }
+  }
+  verify_target(&target, &footer)?;
+  Ok(target)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use super::super::with_footer;
+
+  #[test]
+  fn test_apply() {
+    let rom = vec![0x10, 0x20, 0x30, 0x40];
+    let target = vec![0x10, 0x21, 0x30, 0x40, 0x05];
+    // source size 4, target size 5, skip 1 then xor 0x01, skip 1 then xor 0x05
+    let body = [b'U', b'P', b'S', b'1', 0x84, 0x85, 0x81, 0x01, 0x00, 0x81, 0x05, 0x00];
+    let patch = with_footer(&rom, &target, &body);
+    assert_eq!(super::super::apply(&rom, &patch).unwrap(), target);
+  }
+
+  #[test]
+  fn test_apply_checksum_mismatch() {
+    let rom = vec![0x10, 0x20, 0x30, 0x40];
+    let body = [b'U', b'P', b'S', b'1', 0x84, 0x84];
+    let patch = with_footer(&[0; 4], &rom, &body);
+    match apply(&rom, &patch) {
+      Err(PatchError::SourceChecksumMismatch { .. }) => (),
+      r => panic!("unexpected {:?}", r),
+    }
+    let patch = with_footer(&rom, &[0; 4], &body);
+    match apply(&rom, &patch) {
+      Err(PatchError::TargetChecksumMismatch { .. }) => (),
+      r => panic!("unexpected {:?}", r),
+    }
+    let mut patch = with_footer(&rom, &rom, &body);
+    patch[4] = 0x85;
+    match apply(&rom, &patch) {
+      Err(PatchError::PatchChecksumMismatch { .. }) => (),
+      r => panic!("unexpected {:?}", r),
+    }
+  }
+}
@@ -1,62 +1,75 @@
 use super::super::Ram;
 use super::super::types::{Data, Addr};
-use super::palette::*;
 use super::sprite_utils::*;
-use super::tile::Tile;
 use super::Mapper;
 
+// ref. http://wiki.nesdev.com/w/index.php/PPU_rendering
+// fetches a tile every 8 dots into the shift registers, 2 tiles ahead of the output
 #[derive(Debug)]
-pub struct BackgroundCtx {
-  pub tile: Tile,
-  pub scroll_x: Data,
-  pub scroll_y: Data,
-  pub is_enabled: bool,
+pub struct Background {
+  name_table_byte: Data,
+  palette_id: Data,
+  low_tile_byte: Data,
+  high_tile_byte: Data,
+  // 4 bits a pixel (palette id << 2 | pattern), the upper 32 bits are on screen
+  tile_data: u64,
 }
 
-pub type BackgroundField = Vec<BackgroundCtx>;
-
-#[derive(Debug)]
-pub struct Background(pub BackgroundField);
-
-const TILE_PER_LINE: Data = 32;
-
 impl Background {
   pub fn new() -> Self {
-    Background(Vec::new())
+    Background {
+      name_table_byte: 0,
+      palette_id: 0,
+      low_tile_byte: 0,
+      high_tile_byte: 0,
+      tile_data: 0,
+    }
   }
 
-  pub fn clear(&mut self) {
-    self.0 = Vec::new()
+  // v: the current vram address, yyy NN YYYYY XXXXX
+  fn position(v: Addr) -> SpritePosition {
+    ((v & 0x1F) as Data, ((v >> 5) & 0x1F) as Data)
   }
 
-  pub fn build_line<P: PaletteRam>(
-    &mut self,
-    vram: &Ram,
-    cram: &Ram,
-    palette: &P,
-    tile: (Data, Data),
-    scroll: (Data, Data),
-    config: &mut SpriteConfig,
-    mapper: &dyn Mapper
-  ) {
-    // INFO: Horizontal offsets range from 0 to 255. "Normal" vertical offsets range from 0 to 239,
-    // while values of 240 to 255 are treated as -16 through -1 in a way, but tile data is incorrectly
-    // fetched from the attribute table.
-    // tile 8*8 display 32(tiles)*30(tiles)
-    let clamped_tile_y = tile.1 % 30;
-    let table_id_offset = if (tile.1 / 30) % 2 == 0 { 0 } else { 2 };
-    for x in 0..(TILE_PER_LINE + 1) {
-      let tile_x = x + tile.0;
-      let clamped_tile_x = tile_x % TILE_PER_LINE;
-      let name_table_id = ((tile_x / TILE_PER_LINE) % 2) + table_id_offset;
-      config.offset_addr_by_name_table = Some((name_table_id as Addr) * 0x400);
-      let position: SpritePosition = (clamped_tile_x as u8, clamped_tile_y as u8);
-      self.0.push(BackgroundCtx {
-        tile: Tile::new(vram, cram, palette, &position, &config, mapper),
-        scroll_x: scroll.0,
-        scroll_y: scroll.1,
-        is_enabled: config.is_background_enable,
-      });
+  // called every dot while fetching, after the output of the dot
+  pub fn step(&mut self, dot: usize, v: Addr, config: &SpriteConfig, vram: &Ram, cram: &Ram, mapper: &dyn Mapper) {
+    self.tile_data <<= 4;
+    let position = Self::position(v);
+    let config = SpriteConfig {
+      offset_addr_by_name_table: Some(v & 0x0C00),
+      ..*config
+    };
+    match dot % 8 {
+      1 => self.name_table_byte = get_tile_id(vram, &position, &config),
+      3 => {
+        let attr = get_attribute(vram, &position, &config);
+        self.palette_id = (attr >> (get_block_id(&position) * 2)) & 0x03;
+      }
+      5 => self.low_tile_byte = self.fetch_pattern(v, 0, &config, cram, mapper),
+      7 => self.high_tile_byte = self.fetch_pattern(v, 8, &config, cram, mapper),
+      0 => self.store_tile(),
+      _ => (),
     }
   }
-}
\ No newline at end of file
+
+  fn fetch_pattern(&self, v: Addr, plane: Addr, config: &SpriteConfig, cram: &Ram, mapper: &dyn Mapper) -> Data {
+    let fine_y = (v >> 12) & 0x07;
+    let addr = config.offset_addr_by_background_table + self.name_table_byte as Addr * 16 + plane + fine_y;
+    cram.read(mapper.get_cram_index(addr))
+  }
+
+  fn store_tile(&mut self) {
+    let mut data: u32 = 0;
+    for i in 0..8 {
+      let low = (self.low_tile_byte >> (7 - i)) & 0x01;
+      let high = (self.high_tile_byte >> (7 - i)) & 0x01;
+      data = (data << 4) | (self.palette_id << 2 | high << 1 | low) as u32;
+    }
+    self.tile_data |= data as u64;
+  }
+
+  // palette id << 2 | pattern of the dot, fine_x: 0 - 7
+  pub fn pixel(&self, fine_x: Data) -> Data {
+    ((self.tile_data >> 32) as u32 >> ((7 - fine_x) * 4) & 0x0F) as Data
+  }
+}
@@ -1,12 +1,12 @@
 pub mod background;
-pub mod tile;
 mod register;
 mod palette;
 mod sprite;
 mod sprite_utils;
 mod renderer;
 
-use super::types::{Addr, Data};
+use super::types::{Addr, Data, Word};
+use super::cassette_paser::Region;
 use super::mapper::Mapper;
 use self::super::ram::Ram;
 use self::register::*;
@@ -15,8 +15,41 @@ pub use self::sprite::*;
 pub use self::sprite_utils::*;
 pub use self::background::*;
 use self::renderer::Renderer;
+pub use self::renderer::{encode_pal, load_pal, NtscFilter, NtscPalette, NtscSetup, Overscan, PaletteError, Scaler};
 
 const CYCLES_PER_LINE: usize = 341;
+const VISIBLE_LINES: usize = 240;
+
+// ref. http://wiki.nesdev.com/w/index.php/Cycle_reference_chart
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct PpuTiming {
+  vblank_line: usize,
+  pre_render_line: usize, // the last line of a frame
+  has_short_frame: bool,  // odd frames skip a dot while rendering
+  dots: (usize, usize),   // ppu dots a cpu cycles
+}
+
+const NTSC_TIMING: PpuTiming = PpuTiming {
+  vblank_line: 241,
+  pre_render_line: 261,
+  has_short_frame: true,
+  dots: (3, 1),
+};
+
+const PAL_TIMING: PpuTiming = PpuTiming {
+  vblank_line: 241,
+  pre_render_line: 311,
+  has_short_frame: false,
+  dots: (16, 5),
+};
+
+// 51 idle lines before the vblank to keep the NTSC vblank length at 50Hz
+const DENDY_TIMING: PpuTiming = PpuTiming {
+  vblank_line: 291,
+  pre_render_line: 311,
+  has_short_frame: false,
+  dots: (3, 1),
+};
 
 #[derive(Debug)]
 pub struct PpuCtx<P: PaletteRam> {
@@ -33,13 +66,17 @@ pub struct PpuConfig {
 
 #[derive(Debug)]
 pub struct Ppu {
-  pub cycle: usize,
-  pub line: usize,
+  pub cycle: usize, // dot of the line, 0 - 340
+  pub line: usize,  // 0 - 239 visible, 241 vblank, 261 pre-render on NTSC
   pub register: Register,
   pub ctx: PpuCtx<Palette>,
-  pub sprites: SpritesWithCtx,
+  pub sprites: Vec<LineSprite>, // on the current line
   pub background: Background,
   pub config: PpuConfig,
+  is_sprite_unlimited: bool,
+  is_odd_frame: bool,
+  timing: PpuTiming,
+  dot_remainder: usize, // of the cpu cycles converted to dots
   renderer: Renderer,
 }
 
@@ -58,6 +95,10 @@ impl Ppu {
       sprites: Vec::new(),
       background: Background::new(),
       config,
+      is_sprite_unlimited: false,
+      is_odd_frame: false,
+      timing: NTSC_TIMING,
+      dot_remainder: 0,
       renderer: Renderer::new(),
     }
   }
@@ -70,79 +111,196 @@ impl Ppu {
     self.register.write(addr, data, &mut self.ctx, mapper)
   }
 
-  pub fn run(&mut self, cycle: usize, nmi: &mut bool, mapper: &dyn Mapper) -> bool {
-    let cycle = self.cycle + cycle;
-    if cycle < CYCLES_PER_LINE {
-      self.cycle = cycle;
-      return false;
-    }
+  // the lines and the clock of the console, Multi runs as NTSC
+  pub fn set_region(&mut self, region: Region) {
+    self.timing = match region {
+      Region::Pal => PAL_TIMING,
+      Region::Dendy => DENDY_TIMING,
+      Region::Ntsc | Region::Multi => NTSC_TIMING,
+    };
+    self.dot_remainder = 0;
+  }
 
-    if self.line == 0 {
-      self.background.clear();
-    }
+  // the dots run in cpu cycles, 3.2 a cycle on PAL
+  pub fn dots(&mut self, cpu_cycles: usize) -> usize {
+    let (dots, cycles) = self.timing.dots;
+    let total = cpu_cycles * dots + self.dot_remainder;
+    self.dot_remainder = total % cycles;
+    total / cycles
+  }
+
+  // the visible and the pre-render lines
+  pub fn is_render_line(&self) -> bool {
+    self.line < VISIBLE_LINES || self.line == self.timing.pre_render_line
+  }
 
-    if self.has_sprite_hit(cycle) {
-      self.register.set_sprite_hit();
+  // returns true at the end of a frame
+  pub fn run(&mut self, cycle: usize, nmi: &mut bool, mapper: &dyn Mapper) -> bool {
+    let mut is_frame_end = false;
+    for _ in 0..cycle {
+      is_frame_end |= self.step(nmi, mapper);
     }
+    is_frame_end
+  }
 
-    self.cycle = cycle - CYCLES_PER_LINE;
-    self.line = self.line + 1;
+  // ref. http://wiki.nesdev.com/w/images/4/4f/Ppu.svg
+  fn step(&mut self, nmi: &mut bool, mapper: &dyn Mapper) -> bool {
+    let is_rendering = self.is_background_enabled() || self.is_sprite_enabled();
+    let is_visible_line = self.line < VISIBLE_LINES;
+    let is_render_line = self.is_render_line();
+    let is_visible_dot = 1 <= self.cycle && self.cycle <= 256;
+    let is_fetch_dot = is_visible_dot || (321 <= self.cycle && self.cycle <= 336);
 
-    let scroll_x = self.register.get_scroll_x();
-    let scroll_y = self.register.get_scroll_y();
-    if self.line <= 240 && self.line % 8 == 0 && scroll_y <= 240 {
-      let mut config = SpriteConfig{
-        offset_addr_by_name_table: None,
-        offset_addr_by_background_table: self.register.get_background_table_offset(),
-        offset_addr_by_sprite_table: self.register.get_sprite_table_offset(),
-        is_horizontal_mirror: self.config.is_horizontal_mirror,
-        is_background_enable: self.register.is_background_enable(),
+    if is_visible_line && is_visible_dot {
+      self.render_pixel();
+    }
+    if is_rendering && is_render_line {
+      if is_fetch_dot {
+        let config = SpriteConfig {
+          offset_addr_by_name_table: None,
+          offset_addr_by_background_table: self.register.get_background_table_offset(),
+          is_horizontal_mirror: self.config.is_horizontal_mirror,
+        };
+        self.background.step(self.cycle, self.register.loopy.get_v(), &config, &self.ctx.vram, &self.ctx.cram, mapper);
+        if self.cycle.is_multiple_of(8) {
+          self.register.loopy.increment_x();
+        }
+      }
+      match self.cycle {
+        256 => self.register.loopy.increment_y(),
+        257 => self.register.loopy.copy_x(),
+        280..=304 if self.line == self.timing.pre_render_line => self.register.loopy.copy_y(),
+        _ => (),
+      }
+    }
+    // sprites of the next line
+    if self.cycle == 257 {
+      self.sprites = if is_rendering && is_visible_line {
+        let result = evaluate_sprites(
+          &self.ctx.cram,
+          &self.ctx.oam_ram,
+          self.line,
+          self.register.get_sprite_table_offset(),
+          self.register.is_sprite_8x8(),
+          self.is_sprite_unlimited,
+          mapper,
+        );
+        if result.is_overflow {
+          self.register.set_sprite_overflow();
+        }
+        result.sprites
+      } else {
+        Vec::new()
       };
-      // target line edge on display area
-      let tile_x = ((scroll_x as usize + (self.register.get_name_table_id() % 2) as usize * 256) / 8) as Data;
-      let tile_y = self.get_scroll_tile_y();
-      self.background.build_line(
-        &self.ctx.vram,
-        &self.ctx.cram,
-        &self.ctx.palette,
-        (tile_x, tile_y),
-        (scroll_x, scroll_y),
-        &mut config,
-        mapper
-      );
     }
 
     // VBLANK
-    if self.line == 241 {
+    if self.line == self.timing.vblank_line && self.cycle == 1 {
       self.register.set_vblank();
-      self.register.clear_sprite_hit();
       if self.register.is_irq_enable() {
         *nmi = true;
       }
+      self.renderer.render(self.is_odd_frame);
     }
-
-    // page end
-    if self.line >= 262 {
+    if self.line == self.timing.pre_render_line && self.cycle == 1 {
       self.register.clear_vblank();
       self.register.clear_sprite_hit();
+      self.register.clear_sprite_overflow();
       *nmi = false;
+    }
+
+    self.tick(is_rendering)
+  }
+
+  // next dot, the NTSC pre-render line is one dot shorter on odd frames while rendering
+  fn tick(&mut self, is_rendering: bool) -> bool {
+    let is_skipped = is_rendering
+      && self.timing.has_short_frame
+      && self.is_odd_frame
+      && self.line == self.timing.pre_render_line
+      && self.cycle == 339;
+    self.cycle += if is_skipped { 2 } else { 1 };
+    if self.cycle < CYCLES_PER_LINE {
+      return false;
+    }
+    self.cycle = 0;
+    self.line += 1;
+    if self.line > self.timing.pre_render_line {
       self.line = 0;
-      self.sprites = build_sprites(
-        &self.ctx.cram,
-        &self.ctx.oam_ram,
-        &self.ctx.palette,
-        self.register.get_sprite_table_offset(),
-        self.register.is_sprite_8x8(),
-        mapper,
-      );
-      self.sprites.reverse(); // low index is be front
-      if self.background.0.len() != 0 {
-        self.renderer.render(&self.background.0, &self.sprites, self.register.is_background_clip(), self.register.is_sprites_clip());
+      self.is_odd_frame = !self.is_odd_frame;
+      return true;
+    }
+    false
+  }
+
+  fn render_pixel(&mut self) {
+    let x = self.cycle - 1;
+    let background = if self.is_background_enabled() && !(x < 8 && self.register.is_background_clip()) {
+      self.background.pixel(self.register.loopy.get_fine_x())
+    } else {
+      0
+    };
+    let sprite = if self.is_sprite_enabled() && !(x < 8 && self.register.is_sprites_clip()) {
+      self.sprites.iter().map(|s| (s, s.pixel(x))).find(|(_, pixel)| pixel & 0x03 != 0)
+    } else {
+      None
+    };
+    // sprite 0 is the first on the line when it is in range, hit even when behind
+    if let Some((s, _)) = sprite {
+      if s.index == 0 && background & 0x03 != 0 && x != 255 {
+        self.register.set_sprite_hit();
       }
-      return true
     }
+    let addr = match sprite {
+      Some((s, pixel)) if background & 0x03 == 0 || !s.is_behind_background() => 0x10 | pixel,
+      _ if background & 0x03 != 0 => background,
+      _ => 0,
+    };
+    let mut color_id = self.ctx.palette.read(addr as Addr) & 0x3F;
+    // grayscale keeps the column 0 of the brightness
+    if self.register.is_grayscale() {
+      color_id &= 0x30;
+    }
+    let pixel = (self.register.get_emphasis() as Word) << 6 | color_id as Word;
+    self.renderer.put_pixel(x, self.line, pixel);
+  }
 
-    false
+  // draw more than 8 sprites a line, the overflow flag is kept
+  pub fn set_sprite_unlimited(&mut self, is_unlimited: bool) {
+    self.is_sprite_unlimited = is_unlimited;
+  }
+
+  pub fn is_sprite_unlimited(&self) -> bool {
+    self.is_sprite_unlimited
+  }
+
+  pub fn set_overscan(&mut self, overscan: Overscan) {
+    self.renderer.set_overscan(overscan);
+  }
+
+  pub fn set_colors(&mut self, colors: Vec<(u8, u8, u8)>) {
+    self.renderer.set_colors(colors);
+  }
+
+  pub fn colors(&self) -> &[(u8, u8, u8)] {
+    self.renderer.colors()
+  }
+
+  pub fn set_ntsc_filter(&mut self, ntsc_filter: Option<NtscFilter>) {
+    self.renderer.set_ntsc_filter(ntsc_filter);
+  }
+
+  pub fn set_scaler(&mut self, scaler: Option<Scaler>) {
+    self.renderer.set_scaler(scaler);
+  }
+
+  pub fn frame_size(&self) -> (usize, usize) {
+    self.renderer.frame_size()
+  }
+
+  // RGBA of the last frame with the overscan cut
+  pub fn frame(&self) -> &[Data] {
+    self.renderer.frame()
   }
 
   pub fn transfer_sprite(&mut self, addr: Addr, data: Data) {
@@ -150,13 +308,6 @@ impl Ppu {
     self.ctx.oam_ram.write(addr % 0x100, data);
   }
 
-  fn get_scroll_tile_y(&self) -> Data {
-    ((self.register.get_scroll_y() as usize
-        + self.line
-        + ((self.register.get_name_table_id() / 2) as usize * 240))
-        / 8) as Data
-  }
-
   pub fn is_background_enabled(&self) -> bool {
     self.register.is_background_enable()
   }
@@ -164,11 +315,75 @@ impl Ppu {
   pub fn is_sprite_enabled(&self) -> bool {
     self.register.is_sprite_enable()
   }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use super::super::mapper::Mapper0;
 
-  // sprite 0 hit
-  fn has_sprite_hit(&self, cycle: usize) -> bool {
-    let y = self.ctx.oam_ram.read(0) as usize;
-    let x = self.ctx.oam_ram.read(3) as usize;
-    self.register.is_sprite_enable() && (y == self.line) && x <= cycle
+  // an opaque tile 0 for both the background and sprite 0 at (x, 30)
+  fn sprite_hit_line(x: Data, mask: Data) -> Option<usize> {
+    let mut cram = vec![0; 0x2000];
+    for data in cram.iter_mut().take(8) {
+      *data = 0xFF;
+    }
+    let mut ppu = Ppu::new(cram, PpuConfig { is_horizontal_mirror: false });
+    let mut mapper = Mapper0::new();
+    for (i, data) in [30, 0, 0, x].iter().enumerate() {
+      ppu.ctx.oam_ram.write(i as Addr, *data);
+    }
+    for y in 1..4 {
+      ppu.ctx.oam_ram.write(y * 4, 0xFF);
+    }
+    ppu.write(0x0001, mask, &mut mapper);
+    let mut nmi = false;
+    while !ppu.run(1, &mut nmi, &mapper) {
+      if ppu.register.ppu_status & 0x40 == 0x40 {
+        return Some(ppu.line);
+      }
+    }
+    None
   }
-}
\ No newline at end of file
+
+  // (dots, the line of the vblank) of a frame
+  fn frame_timing(region: Region) -> (usize, usize) {
+    let mut ppu = Ppu::new(vec![0; 0x2000], PpuConfig { is_horizontal_mirror: false });
+    ppu.set_region(region);
+    let mapper = Mapper0::new();
+    let mut nmi = false;
+    let mut dots = 1;
+    let mut vblank_line = 0;
+    while !ppu.run(1, &mut nmi, &mapper) {
+      if vblank_line == 0 && ppu.register.ppu_status & 0x80 == 0x80 {
+        vblank_line = ppu.line;
+      }
+      dots += 1;
+    }
+    (dots, vblank_line)
+  }
+
+  #[test]
+  fn test_region_timing() {
+    assert_eq!(frame_timing(Region::Ntsc), (262 * 341, 241));
+    assert_eq!(frame_timing(Region::Pal), (312 * 341, 241));
+    assert_eq!(frame_timing(Region::Dendy), (312 * 341, 291));
+    let mut ppu = Ppu::new(vec![0; 0x2000], PpuConfig { is_horizontal_mirror: false });
+    assert_eq!(ppu.dots(3), 9);
+    ppu.set_region(Region::Pal);
+    assert_eq!((0..5).map(|_| ppu.dots(1)).collect::<Vec<_>>(), vec![3, 3, 3, 3, 4]);
+    assert_eq!(ppu.dots(7), 22);
+  }
+
+  #[test]
+  fn test_sprite_zero_hit() {
+    // drawn from the next line of y
+    assert_eq!(sprite_hit_line(100, 0x18), Some(31));
+    assert_eq!(sprite_hit_line(100, 0x10), None);
+    assert_eq!(sprite_hit_line(255, 0x1E), None);
+    // the left 8 pixels are clipped unless both are shown
+    assert_eq!(sprite_hit_line(0, 0x1E), Some(31));
+    assert_eq!(sprite_hit_line(0, 0x1C), None);
+    assert_eq!(sprite_hit_line(0, 0x1A), None);
+  }
+}
@@ -1,18 +1,9 @@
 use super::super::types::{Data, Addr};
 
 #[derive(Debug)]
-pub enum PaletteType {
-  Sprite,
-  Background,
-}
-
-pub type PaletteList = Vec<Data>;
-
-#[derive(Debug)]
-pub struct Palette(PaletteList);
+pub struct Palette(Vec<Data>);
 
 pub trait PaletteRam {
-  fn get(&self, palette_id: Data, palette_type: PaletteType) -> PaletteList;
   fn read(&self, addr: Addr) -> Data;
   fn write(&mut self, addr: Addr, data: Data);
 }
@@ -42,16 +33,6 @@ impl Palette {
 }
 
 impl PaletteRam for Palette {
-  fn get(&self, id: Data, p_type: PaletteType) -> PaletteList {
-    let offset = match p_type{
-      PaletteType::Sprite => 0x10,
-      _ => 0x00,
-    };
-    let start = (id*4 + offset) as usize;
-    let end = start + 4;
-    (start..end).map(|p| self.read(p as Addr)).collect()
-  }
-
   fn read(&self, addr: Addr) -> Data {
     if self.is_sprite_mirror(addr) {
       return self.0[(addr - 0x10) as usize]
@@ -77,12 +58,10 @@ fn test_get_background_palette() {
   for x in 0..4 {
     p.write(x,x as Data);
   }
-  let palette = p.get(0x00, PaletteType::Background);
-  assert_eq!(palette.len(), 0x4);
-  assert_eq!(palette[0],0x0);
-  assert_eq!(palette[1],0x1);
-  assert_eq!(palette[2],0x2);
-  assert_eq!(palette[3],0x3);
+  assert_eq!(p.read(0x0),0x0);
+  assert_eq!(p.read(0x1),0x1);
+  assert_eq!(p.read(0x2),0x2);
+  assert_eq!(p.read(0x3),0x3);
 }
 
 #[test]
@@ -91,10 +70,10 @@ fn test_get_sprite_palette() {
     for x in 0x10..0x14 {
         p.write(x, x as Data);
     }
-    let palette = p.get(0x00, PaletteType::Sprite);
-    assert_eq!(palette.len(), 0x4);
-    assert_eq!(palette[0], 0x10);
-    assert_eq!(palette[1], 0x11);
-    assert_eq!(palette[2], 0x12);
-    assert_eq!(palette[3], 0x13);
-}
\ No newline at end of file
+    // 0x3F10 is a mirror of 0x3F00
+    assert_eq!(p.read(0x00), 0x10);
+    assert_eq!(p.read(0x10), 0x10);
+    assert_eq!(p.read(0x11), 0x11);
+    assert_eq!(p.read(0x12), 0x12);
+    assert_eq!(p.read(0x13), 0x13);
+}
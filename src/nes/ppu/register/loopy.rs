@@ -0,0 +1,174 @@
+use super::super::super::types::{Data, Addr};
+
+// ref. http://wiki.nesdev.com/w/index.php/PPU_scrolling
+// the internal registers shared by $2000, $2005 and $2006
+// v and t are yyy NN YYYYY XXXXX (fine y, name table, coarse y, coarse x)
+#[derive(Debug)]
+pub struct Loopy {
+  v: Addr,  // current vram address
+  t: Addr,  // temporary vram address, the top left of the screen
+  x: Data,  // fine x scroll, 3 bits
+  w: bool,  // write toggle, true after the first write
+}
+
+impl Loopy {
+  pub fn new() -> Self {
+    Loopy {
+      v: 0,
+      t: 0,
+      x: 0,
+      w: false,
+    }
+  }
+
+  pub fn get_v(&self) -> Addr {
+    self.v
+  }
+
+  pub fn get_fine_x(&self) -> Data {
+    self.x
+  }
+
+  // $2002 read
+  pub fn reset_latch(&mut self) {
+    self.w = false
+  }
+
+  // $2000 write
+  pub fn write_ctrl(&mut self, data: Data) {
+    self.t = (self.t & 0xF3FF) | ((data as Addr & 0x03) << 10);
+  }
+
+  // $2005 write
+  pub fn write_scroll(&mut self, data: Data) {
+    if self.w {
+      self.t = (self.t & 0x8C1F) | ((data as Addr & 0x07) << 12) | ((data as Addr & 0xF8) << 2);
+    } else {
+      self.t = (self.t & 0xFFE0) | (data as Addr >> 3);
+      self.x = data & 0x07;
+    }
+    self.w = !self.w;
+  }
+
+  // $2006 write, v is updated with the second write
+  pub fn write_addr(&mut self, data: Data) {
+    if self.w {
+      self.t = (self.t & 0xFF00) | data as Addr;
+      self.v = self.t;
+    } else {
+      self.t = (self.t & 0x00FF) | ((data as Addr & 0x3F) << 8);
+    }
+    self.w = !self.w;
+  }
+
+  // after $2007 access
+  pub fn increment(&mut self, offset: Addr) {
+    self.v = self.v.wrapping_add(offset) & 0x7FFF;
+  }
+
+  pub fn get_scroll_x(&self) -> Data {
+    ((self.t & 0x1F) << 3) as Data | self.x
+  }
+
+  pub fn get_scroll_y(&self) -> Data {
+    (((self.t >> 2) & 0xF8) | ((self.t >> 12) & 0x07)) as Data
+  }
+
+  pub fn get_name_table_id(&self) -> Data {
+    ((self.t >> 10) & 0x03) as Data
+  }
+
+  // ref. http://wiki.nesdev.com/w/index.php/PPU_scrolling#Wrapping_around
+  pub fn increment_x(&mut self) {
+    if self.v & 0x001F == 31 {
+      self.v &= !0x001F;
+      self.v ^= 0x0400; // next horizontal name table
+    } else {
+      self.v += 1;
+    }
+  }
+
+  pub fn increment_y(&mut self) {
+    if self.v & 0x7000 != 0x7000 {
+      self.v += 0x1000;
+      return;
+    }
+    self.v &= !0x7000;
+    let y = match (self.v & 0x03E0) >> 5 {
+      29 => {
+        self.v ^= 0x0800; // next vertical name table
+        0
+      }
+      31 => 0,
+      y => y + 1,
+    };
+    self.v = (self.v & !0x03E0) | y << 5;
+  }
+
+  // dot 257 of the render lines
+  pub fn copy_x(&mut self) {
+    self.v = (self.v & 0xFBE0) | (self.t & 0x041F);
+  }
+
+  // dot 280 - 304 of the pre-render line
+  pub fn copy_y(&mut self) {
+    self.v = (self.v & 0x841F) | (self.t & 0x7BE0);
+  }
+}
+
+#[test]
+fn set_addr() {
+  let mut reg = Loopy::new();
+  reg.write_addr(0xFF);
+  reg.write_addr(0x55);
+  // the upper 2 bits are cleared
+  assert_eq!(reg.get_v(), 0x3F55);
+}
+
+#[test]
+fn update_addr() {
+  let mut reg = Loopy::new();
+  reg.write_addr(0x3F);
+  reg.write_addr(0x55);
+  reg.increment(32);
+  assert_eq!(reg.get_v(), 0x3F75);
+}
+
+#[test]
+fn scroll_and_addr_share_toggle() {
+  let mut reg = Loopy::new();
+  reg.write_ctrl(0x02);
+  reg.write_scroll(0x7D); // coarse x 15, fine x 5
+  reg.write_scroll(0x5E); // coarse y 11, fine y 6
+  assert_eq!(reg.get_scroll_x(), 0x7D);
+  assert_eq!(reg.get_scroll_y(), 0x5E);
+  assert_eq!(reg.get_name_table_id(), 2);
+  assert_eq!(reg.get_fine_x(), 5);
+  // the mid-frame $2006 / $2005 / $2005 / $2006 sequence
+  reg.write_addr(0x04);
+  reg.write_scroll(0x3E);
+  reg.write_scroll(0x7D);
+  reg.write_addr(0xEF);
+  assert_eq!(reg.get_v(), 0x64EF);
+  assert_eq!(reg.get_fine_x(), 5);
+}
+
+#[test]
+fn increment_and_copy() {
+  let mut reg = Loopy::new();
+  reg.write_addr(0x23);
+  reg.write_addr(0xBF); // fine y 2, coarse y 29, coarse x 31
+  reg.increment_x();
+  assert_eq!(reg.get_v(), 0x27A0);
+  for _ in 0..6 {
+    reg.increment_y();
+  }
+  // coarse y 29 wraps to the next vertical name table
+  assert_eq!(reg.get_v(), 0x0C00);
+  reg.write_scroll(0x08);
+  reg.write_scroll(0x10);
+  reg.copy_x();
+  assert_eq!(reg.get_v(), 0x0801);
+  reg.copy_y();
+  assert_eq!(reg.get_v(), 0x0041);
+}
@@ -1,12 +1,10 @@
+mod loopy;
 mod oam;
-mod ppu_addr;
 mod ppu_data;
-mod ppu_scroll;
 
+use self::loopy::Loopy;
 use self::oam::Oam;
-use self::ppu_addr::PpuAddr;
 use self::ppu_data::PpuData;
-use self::ppu_scroll::PpuScroll;
 use super::super::types::{Data, Addr};
 use super::super::Ram;
 use super::palette::*;
@@ -19,9 +17,8 @@ pub struct Register {
   pub ppu_ctrl2: Data,
   pub ppu_status: Data,
   pub oam: Oam,
-  pub ppu_addr: PpuAddr,
   pub ppu_data: PpuData,
-  pub ppu_scroll: PpuScroll,
+  pub loopy: Loopy,
 }
 
 //from https://github.com/bokuweb/rustynes/blob/master/src/nes/ppu/registers/mod.rs
@@ -53,18 +50,19 @@ pub struct Register {
     fn clear_sprite_hit(&mut self);
     fn set_vblank(&mut self);
     fn set_sprite_hit(&mut self);
+    fn set_sprite_overflow(&mut self);
+    fn clear_sprite_overflow(&mut self);
     fn is_sprites_clip(&self) -> bool;
     fn is_background_clip(&self) -> bool;
     fn is_sprite_enable(&self) -> bool;
     fn is_background_enable(&self) -> bool;
     fn is_irq_enable(&self) -> bool;
     fn is_sprite_8x8(&self) -> bool;
+    fn is_grayscale(&self) -> bool;
+    fn get_emphasis(&self) -> Data;
     fn get_ppu_addr_increment_value(&self) -> usize;
     fn get_background_table_offset(&self) -> Addr;
-    fn get_name_table_id(&self) -> Data;
     fn get_sprite_table_offset(&self) -> Addr;
-    fn get_scroll_x(&self) -> Data;
-    fn get_scroll_y(&self) -> Data;
   }
 
 impl Register {
@@ -74,18 +72,15 @@ impl Register {
       ppu_ctrl2: 0,
       ppu_status: 0,
       oam: Oam::new(),
-      ppu_addr: PpuAddr::new(),
       ppu_data: PpuData::new(),
-      ppu_scroll: PpuScroll::new(),
+      loopy: Loopy::new(),
     }
   }
 
   fn read_status(&mut self) -> Data {
     let data = self.ppu_status;
-    self.ppu_scroll.enable_x();
     self.clear_vblank();
-    self.clear_sprite_hit();
-    self.ppu_addr.reset_latch();
+    self.loopy.reset_latch();
     data
   }
 
@@ -97,23 +92,28 @@ impl Register {
     self.oam.write_data(oam_ram, data)
   }
 
+  fn write_ppu_ctrl1(&mut self, data: Data) {
+    self.ppu_ctrl1 = data;
+    self.loopy.write_ctrl(data);
+  }
+
   fn write_ppu_addr(&mut self, data: Data) {
-    self.ppu_addr.write(data)
+    self.loopy.write_addr(data)
   }
 
   fn read_ppu_data<P: PaletteRam>(&mut self, vram: &Ram, cram: &Ram, palette: &P, mapper: &dyn Mapper) -> Data {
-    let addr = self.ppu_addr.get();
+    let addr = self.loopy.get_v() & 0x3FFF;
     let data = self.ppu_data.read(vram, cram,addr, palette, mapper);
     let v = self.get_ppu_addr_increment_value() as u16;
-    self.ppu_addr.update(v);
+    self.loopy.increment(v);
     data
   }
 
   fn write_ppu_data<P: PaletteRam>(&mut self, data: Data, vram: &mut Ram, cram: &mut Ram, palette: &mut P, mapper: &mut Mapper){
-    let addr = self.ppu_addr.get();
+    let addr = self.loopy.get_v() & 0x3FFF;
     self.ppu_data.write(vram, cram, addr ,data, palette, mapper);
     let v = self.get_ppu_addr_increment_value() as u16;
-    self.ppu_addr.update(v);
+    self.loopy.increment(v);
   }
 }
 
@@ -130,11 +130,11 @@ impl PpuRegister for Register {
 
   fn write<P: PaletteRam>(&mut self, addr: Addr, data: Data, ctx: &mut PpuCtx<P>, mapper: &mut dyn Mapper) {
     match addr {
-      0x0000 => self.ppu_ctrl1 = data,
+      0x0000 => self.write_ppu_ctrl1(data),
       0x0001 => self.ppu_ctrl2 = data,
       0x0003 => self.write_oam_addr(data),
       0x0004 => self.write_oam_data(data, &mut ctx.oam_ram),
-      0x0005 => self.ppu_scroll.write(data),
+      0x0005 => self.loopy.write_scroll(data),
       0x0006 => self.write_ppu_addr(data),
       0x0007 => self.write_ppu_data(data, &mut ctx.vram, &mut ctx.cram, &mut ctx.palette, mapper),
       _ => (),
@@ -157,12 +157,21 @@ impl PpuRegister for Register {
     self.ppu_status |= 0x40
   }
 
+  fn set_sprite_overflow(&mut self) {
+    self.ppu_status |= 0x20
+  }
+
+  fn clear_sprite_overflow(&mut self) {
+    self.ppu_status &= 0xDF
+  }
+
+  // the left 8 pixels are hidden unless the bit is set
   fn is_sprites_clip(&self) -> bool {
-    self.ppu_ctrl2 & 0x02 != 0x02
+    self.ppu_ctrl2 & 0x04 != 0x04
   }
 
   fn is_background_clip(&self) -> bool {
-    self.ppu_ctrl2 & 0x01 != 0x01
+    self.ppu_ctrl2 & 0x02 != 0x02
   }
 
   fn is_background_enable(&self) -> bool {
@@ -181,6 +190,15 @@ impl PpuRegister for Register {
     self.ppu_ctrl1 & 0x20 != 0x20
   }
 
+  fn is_grayscale(&self) -> bool {
+    self.ppu_ctrl2 & 0x01 == 0x01
+  }
+
+  // bit 0: red, 1: green, 2: blue
+  fn get_emphasis(&self) -> Data {
+    self.ppu_ctrl2 >> 5
+  }
+
   fn get_ppu_addr_increment_value(&self) -> usize {
     if self.ppu_ctrl1 & 0x04 == 0x04 {
       32
@@ -197,10 +215,6 @@ impl PpuRegister for Register {
     }
   }
 
-  fn get_name_table_id(&self) -> Data {
-    self.ppu_ctrl1 & 0x03
-  }
-
   fn get_sprite_table_offset(&self) -> Addr {
     if self.ppu_ctrl1 & 0x08 == 0x08 {
       0x1000
@@ -208,12 +222,4 @@ impl PpuRegister for Register {
       0x0000
     }
   }
-
-  fn get_scroll_x(&self) -> Data {
-    self.ppu_scroll.get_x()
-  }
-
-  fn get_scroll_y(&self) -> Data {
-    self.ppu_scroll.get_y()
-  }
 }
\ No newline at end of file
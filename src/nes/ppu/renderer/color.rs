@@ -1,3 +1,5 @@
+use std::fmt;
+
 pub static COLORS: &'static [(u8, u8, u8)] = &[
   (0x80, 0x80, 0x80), (0x00, 0x3D, 0xA6), (0x00, 0x12, 0xB0), (0x44, 0x00, 0x96),
   (0xA1, 0x00, 0x5E), (0xC7, 0x00, 0x28), (0xBA, 0x06, 0x00), (0x8C, 0x17, 0x00),
@@ -15,4 +17,90 @@ pub static COLORS: &'static [(u8, u8, u8)] = &[
   (0xFF, 0xA8, 0xF9), (0xFF, 0xAB, 0xB3), (0xFF, 0xD2, 0xB0), (0xFF, 0xEF, 0xA6),
   (0xFF, 0xF7, 0x9C), (0xD7, 0xE8, 0x95), (0xA6, 0xED, 0xAF), (0xA2, 0xF2, 0xDA),
   (0x99, 0xFF, 0xFC), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11),
-];
\ No newline at end of file
+];
+// $2001 bit 5 - 7 dim the other 2 of red, green and blue
+const EMPHASIS_ATTENUATION: f32 = 0.816328;
+
+// 512 colors indexed by emphasis << 6 | color id from the 64 base colors
+pub fn build_colors(base: &[(u8, u8, u8)]) -> Vec<(u8, u8, u8)> {
+  let mut colors = Vec::with_capacity(base.len() * 8);
+  for emphasis in 0..8 {
+    for &(r, g, b) in base.iter() {
+      let mut rgb = [r as f32, g as f32, b as f32];
+      for (i, value) in rgb.iter_mut().enumerate() {
+        // the other emphasis bits than the channel's own
+        let dims = (emphasis & !(1 << i) as usize).count_ones();
+        *value *= EMPHASIS_ATTENUATION.powi(dims as i32);
+      }
+      colors.push((rgb[0].round() as u8, rgb[1].round() as u8, rgb[2].round() as u8));
+    }
+  }
+  colors
+}
+
+// *.pal: RGB of the 64 colors, or of all the 512 with emphasis
+const PAL_SIZE: usize = 64 * 3;
+const PAL_WITH_EMPHASIS_SIZE: usize = 512 * 3;
+
+#[derive(Debug, PartialEq)]
+pub enum PaletteError {
+  InvalidSize(usize),
+}
+
+impl fmt::Display for PaletteError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      PaletteError::InvalidSize(actual) => write!(
+        f,
+        "Invalid *.pal file: needs {} or {} bytes but file has {}.",
+        PAL_SIZE, PAL_WITH_EMPHASIS_SIZE, actual
+      ),
+    }
+  }
+}
+
+// the 512 colors of a *.pal, the emphasis is applied to the 64 colors ones
+pub fn load_pal(bytes: &[u8]) -> Result<Vec<(u8, u8, u8)>, PaletteError> {
+  let colors: Vec<(u8, u8, u8)> = match bytes.len() {
+    PAL_SIZE | PAL_WITH_EMPHASIS_SIZE => bytes.chunks(3).map(|c| (c[0], c[1], c[2])).collect(),
+    actual => return Err(PaletteError::InvalidSize(actual)),
+  };
+  if colors.len() == 64 {
+    return Ok(build_colors(&colors));
+  }
+  Ok(colors)
+}
+
+pub fn encode_pal(colors: &[(u8, u8, u8)]) -> Vec<u8> {
+  colors.iter().flat_map(|&(r, g, b)| vec![r, g, b]).collect()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_build_colors() {
+    let colors = build_colors(COLORS);
+    assert_eq!(colors.len(), 512);
+    assert_eq!(&colors[..64], COLORS);
+    // red emphasis dims green and blue of white
+    assert_eq!(colors[0x040 | 0x30], (0xFF, 0xD0, 0xD0));
+    // all 3 dim everything twice
+    assert_eq!(colors[0x1C0 | 0x30], (0xAA, 0xAA, 0xAA));
+  }
+
+  #[test]
+  fn test_load_pal() {
+    let pal = encode_pal(COLORS);
+    assert_eq!(pal.len(), 192);
+    assert_eq!(load_pal(&pal), Ok(build_colors(COLORS)));
+    let mut full = encode_pal(&build_colors(COLORS));
+    full[0x1FF * 3] = 0x12;
+    // used as is
+    let colors = load_pal(&full).unwrap();
+    assert_eq!(colors[0x1FF].0, 0x12);
+    assert_eq!(colors[..0x1FF], build_colors(COLORS)[..0x1FF]);
+    assert_eq!(load_pal(&pal[..191]), Err(PaletteError::InvalidSize(191)));
+  }
+}
@@ -1,109 +1,226 @@
 mod color;
+mod ntsc_filter;
+mod ntsc_palette;
+mod scaler;
 
-use super::super::types::{Data};
-use super::{BackgroundField, BackgroundCtx};
-use super::{Sprite, SpritesWithCtx, SpritePosition};
-use super::{PaletteList};
-use self::color::COLORS;
+use super::super::types::{Data, Word};
+use self::color::{build_colors, COLORS};
+use self::ntsc_filter::NTSC_SCALE;
+pub use self::color::{encode_pal, load_pal, PaletteError};
+pub use self::ntsc_filter::{NtscFilter, NtscSetup};
+pub use self::ntsc_palette::NtscPalette;
+pub use self::scaler::Scaler;
 extern "C" {
-  fn canvas_render(ptr: *const Data, len: usize);
+  fn canvas_render(ptr: *const Data, len: usize, width: usize, height: usize);
+}
+
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 240;
+
+// pixels cut from each side of the 256x240 picture when it is output
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Overscan {
+  pub top: usize,
+  pub bottom: usize,
+  pub left: usize,
+  pub right: usize,
+}
+
+impl Overscan {
+  pub fn width(&self) -> usize {
+    WIDTH - self.left - self.right
+  }
+
+  pub fn height(&self) -> usize {
+    HEIGHT - self.top - self.bottom
+  }
+
+  // at least a pixel is left on both axes
+  fn clamp(self) -> Self {
+    let top = self.top.min(HEIGHT - 1);
+    let left = self.left.min(WIDTH - 1);
+    Overscan {
+      top,
+      bottom: self.bottom.min(HEIGHT - 1 - top),
+      left,
+      right: self.right.min(WIDTH - 1 - left),
+    }
+  }
 }
 
 #[derive(Debug)]
 pub struct Renderer {
-  buf: Vec<Data>,
+  pixels: Vec<Word>, // emphasis << 6 | color id of each dot
+  buf: Vec<Data>,    // RGBA of the cropped picture
+  overscan: Overscan,
+  colors: Vec<(u8, u8, u8)>, // 512 colors of the pixels
+  ntsc_filter: Option<NtscFilter>,
+  scaler: Option<Scaler>,
+  scaled: Vec<Data>, // RGBA of the buf scaled up
 }
 
 impl Renderer {
   pub fn new() -> Self {
-    Renderer { buf: vec![0xFF;256*224*4]}
+    Renderer {
+      pixels: vec![0; WIDTH * HEIGHT],
+      buf: vec![0xFF; WIDTH * HEIGHT * 4],
+      overscan: Overscan::default(),
+      colors: build_colors(COLORS),
+      ntsc_filter: None,
+      scaler: None,
+      scaled: Vec::new(),
+    }
   }
 
-  pub fn render(&mut self, background: &BackgroundField, sprites: &SpritesWithCtx, bg_clip: bool, sprite_clip: bool) {
-    self.render_background(background, bg_clip);
-    self.render_sprites(sprites,background, sprite_clip);
-    unsafe {
-      canvas_render(self.buf.as_ptr(), self.buf.len());
-    }
+  // called by the ppu at each visible dot
+  pub fn put_pixel(&mut self, x: usize, y: usize, pixel: Word) {
+    self.pixels[y * WIDTH + x] = pixel;
   }
 
-  fn render_background(&mut self, background: &BackgroundField, clip: bool) {
-    for (i, bg) in background.into_iter().enumerate() {
-      if bg.is_enabled {
-        let x = (i % 33) * 8;
-        let y = (i / 33) * 8;
-        self.render_tile(bg, x, y, clip);
-      }
-    }
+  pub fn set_overscan(&mut self, overscan: Overscan) {
+    self.overscan = overscan.clamp();
+    self.resize();
   }
 
-  fn render_tile(&mut self, bg: &BackgroundCtx, x: usize, y: usize, clip: bool) {
-    let offset_x = (bg.scroll_x % 8) as i32;
-    let offset_y = (bg.scroll_y % 8) as i32;
-    for i in 0..8 {
-        for j in 0..8 {
-            let x = (x + j) as i32 - offset_x;
-            let y = (y + i) as i32 - offset_y;
-            if x >= 0 as i32 && 0xFF >= x && y >= 0 as i32 && y < 224 {
-                let color_id = bg.tile.palette[bg.tile.sprite[i][j] as usize];
-                let color = COLORS[color_id as usize];
-                let index = ((x + (y * 0x100)) * 4) as usize;
-                self.buf[index] = color.0;
-                self.buf[index + 1] = color.1;
-                self.buf[index + 2] = color.2;
-                if clip && x < 8 {
-                    self.buf[index + 3] = 0;
-                }
-            }
-        }
-    }
-}
+  // the composite signal instead of the colors, the picture gets NTSC_SCALE times wider
+  pub fn set_ntsc_filter(&mut self, ntsc_filter: Option<NtscFilter>) {
+    self.ntsc_filter = ntsc_filter;
+    self.resize();
+  }
+
+  // upscale the output, after the ntsc filter
+  pub fn set_scaler(&mut self, scaler: Option<Scaler>) {
+    self.scaler = scaler;
+    self.rescale();
+  }
 
-  fn render_sprites(&mut self, sprites: &SpritesWithCtx, background: &BackgroundField, clip: bool) {
-    for sprite in sprites {
-      self.render_sprite(&sprite.sprite, &sprite.position,&sprite.palette ,sprite.attr, &background, clip);
+  fn resize(&mut self) {
+    let (width, height) = self.output_size();
+    self.buf = vec![0xFF; width * height * 4];
+    self.rescale();
+  }
+
+  // keeps the scaled frame in the size of frame_size()
+  fn rescale(&mut self) {
+    let (width, height) = self.output_size();
+    self.scaled = match self.scaler {
+      Some(scaler) => scaler.scale(&self.buf, width, height).0,
+      None => Vec::new(),
+    };
+  }
+
+  // before the scaler
+  fn output_size(&self) -> (usize, usize) {
+    let scale = if self.ntsc_filter.is_some() { NTSC_SCALE } else { 1 };
+    (self.overscan.width() * scale, self.overscan.height())
+  }
+
+  // of the frame
+  pub fn frame_size(&self) -> (usize, usize) {
+    let (width, height) = self.output_size();
+    let factor = self.scaler.map_or(1, |scaler| scaler.factor());
+    (width * factor, height * factor)
+  }
+
+  // 512 colors indexed by emphasis << 6 | color id, from the next frame
+  pub fn set_colors(&mut self, colors: Vec<(u8, u8, u8)>) {
+    assert_eq!(colors.len(), 512);
+    self.colors = colors;
+  }
+
+  pub fn colors(&self) -> &[(u8, u8, u8)] {
+    &self.colors
+  }
+
+  // RGBA of the last frame, frame_size()
+  pub fn frame(&self) -> &[Data] {
+    if self.scaler.is_some() {
+      &self.scaled
+    } else {
+      &self.buf
     }
   }
 
-  fn render_sprite(&mut self, sprite: &Sprite, position: &SpritePosition, palette: &PaletteList, attr: Data, background: &BackgroundField, clip: bool) {
-    let is_vertical_reverse = (attr & 0x80) == 0x80;
-    let is_horizontal_reverse = (attr & 0x40) == 0x40;
-    let is_low_priority = (attr & 0x20) == 0x20;
-    let h = sprite.len();
-    for i in 0..h {
-      let y = position.1 as usize + if is_vertical_reverse { h - 1 - i} else { i };
-      if y >= 224 {
-        continue
+  pub fn render(&mut self, is_odd_frame: bool) {
+    let Overscan { top, left, .. } = self.overscan;
+    let (width, height) = (self.overscan.width(), self.overscan.height());
+    if let Some(ntsc_filter) = self.ntsc_filter.as_ref() {
+      let line_size = width * NTSC_SCALE * 4;
+      for y in 0..height {
+        let line = &self.pixels[(top + y) * WIDTH..(top + y + 1) * WIDTH];
+        let phase = NtscFilter::line_phase(top + y, is_odd_frame);
+        ntsc_filter.filter_line(line, phase, left, width, &mut self.buf[y * line_size..(y + 1) * line_size]);
       }
-      for j in 0..8 {
-        let x = position.0 as usize + if is_horizontal_reverse { 7 - j } else { j };
-        if x >= 256 {
-          continue
-        }
-        if is_low_priority && self.should_pixel_hide(x, y, background) {
-          continue
-        }
-        if sprite[i][j] != 0 {
-          let color_id = palette[sprite[i][j] as usize];
-          let color = COLORS[color_id as usize];
-          let index = (x + (y * 0x100)) * 4;
-          self.buf[index] = color.0;
-          self.buf[index + 1] = color.1;
-          self.buf[index + 2] = color.2;
-          if clip && x < 8 {
-            self.buf[index + 3] = 0;
-          }
-        }
+    } else {
+      self.render_colors();
+    }
+    self.rescale();
+    let (width, height) = self.frame_size();
+    let frame = self.frame();
+    unsafe {
+      canvas_render(frame.as_ptr(), frame.len(), width, height);
+    }
+  }
+
+  fn render_colors(&mut self) {
+    let Overscan { top, left, .. } = self.overscan;
+    let (width, height) = (self.overscan.width(), self.overscan.height());
+    for y in 0..height {
+      let line = &self.pixels[(top + y) * WIDTH + left..(top + y) * WIDTH + left + width];
+      for (x, pixel) in line.iter().enumerate() {
+        let color = self.colors[(pixel & 0x1FF) as usize];
+        let i = (y * width + x) * 4;
+        self.buf[i] = color.0;
+        self.buf[i + 1] = color.1;
+        self.buf[i + 2] = color.2;
       }
     }
   }
+}
 
-  fn should_pixel_hide(&self, x: usize, y: usize, background: &BackgroundField) -> bool {
-    let tile_x = x / 8;
-    let tile_y = y / 8;
-    let background_index = tile_y * 33 + tile_x;
-    let sprite = &background[background_index];
-    // NOTE: If background pixel is not transparent, we need to hide sprite.
-    sprite.tile.sprite[y % 8][x % 8] % 4 != 0
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_overscan() {
+    let mut renderer = Renderer::new();
+    renderer.put_pixel(8, 8, 0x30);
+    renderer.put_pixel(255, 239, 0x16);
+    renderer.put_pixel(0, 239, 0x1C0 | 0x30);
+    renderer.render(false);
+    assert_eq!(renderer.frame().len(), WIDTH * HEIGHT * 4);
+    let last = (WIDTH * HEIGHT - 1) * 4;
+    assert_eq!(&renderer.frame()[last..last + 3], &[COLORS[0x16].0, COLORS[0x16].1, COLORS[0x16].2]);
+    // all the emphasis bits
+    let first = WIDTH * 239 * 4;
+    assert_eq!(&renderer.frame()[first..first + 3], &[0xAA, 0xAA, 0xAA]);
+    renderer.set_overscan(Overscan { top: 8, bottom: 8, left: 8, right: 0 });
+    renderer.render(false);
+    let (width, height) = renderer.frame_size();
+    assert_eq!((width, height), (248, 224));
+    assert_eq!(renderer.frame().len(), 248 * 224 * 4);
+    assert_eq!(&renderer.frame()[..3], &[COLORS[0x30].0, COLORS[0x30].1, COLORS[0x30].2]);
+    // the bottom right is cut
+    let last = (width * height - 1) * 4;
+    assert_eq!(&renderer.frame()[last..last + 3], &[COLORS[0].0, COLORS[0].1, COLORS[0].2]);
+    renderer.set_ntsc_filter(Some(NtscFilter::new(NtscSetup::rgb(), NtscPalette::default())));
+    renderer.render(false);
+    assert_eq!(renderer.frame_size(), (496, 224));
+    assert_eq!(renderer.frame().len(), 496 * 224 * 4);
+    renderer.set_scaler(Some(Scaler::Scale2x));
+    // the frame follows the scaler before the next render
+    assert_eq!(renderer.frame().len(), 992 * 448 * 4);
+    renderer.set_overscan(Overscan { top: 8, bottom: 8, left: 8, right: 8 });
+    let (width, height) = renderer.frame_size();
+    assert_eq!(renderer.frame().len(), width * height * 4);
+    renderer.set_overscan(Overscan { top: 8, bottom: 8, left: 8, right: 0 });
+    renderer.render(false);
+    assert_eq!(renderer.frame_size(), (992, 448));
+    assert_eq!(renderer.frame().len(), 992 * 448 * 4);
+    renderer.set_scaler(None);
+    renderer.set_ntsc_filter(None);
+    renderer.set_overscan(Overscan { top: 300, bottom: 300, left: 0, right: 300 });
+    assert_eq!(renderer.frame_size(), (1, 1));
   }
-}
\ No newline at end of file
+}
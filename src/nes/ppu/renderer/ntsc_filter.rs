@@ -0,0 +1,204 @@
+use super::super::super::types::{Data, Word};
+use super::ntsc_palette::{signal, NtscPalette};
+
+// ref. https://www.nesdev.org/wiki/NTSC_video
+// a pixel lasts 8 of the 12 phases of a color cycle
+const SAMPLES_PER_PIXEL: usize = 8;
+const PHASES: usize = 12;
+// a line is 341 * 8 samples, the next one starts 4 phases later
+const LINE_PHASE_STEP: usize = 4;
+// output pixels a pixel
+pub const NTSC_SCALE: usize = 2;
+const SAMPLES_PER_OUTPUT: usize = SAMPLES_PER_PIXEL / NTSC_SCALE;
+
+// how the TV separates the luma and the chroma, each 0 - 1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NtscSetup {
+  pub sharpness: f32, // 0: the luma of a color cycle, 1: of half a pixel
+  pub fringing: f32,  // the chroma left in the sharp luma
+  pub artifacts: f32, // the luma edges decoded as colors
+  pub bleed: f32,     // widens the chroma from 1 up to 3 color cycles
+}
+
+impl NtscSetup {
+  pub fn composite() -> Self {
+    NtscSetup {
+      sharpness: 0.0,
+      fringing: 1.0,
+      artifacts: 1.0,
+      bleed: 0.5,
+    }
+  }
+
+  pub fn svideo() -> Self {
+    NtscSetup {
+      sharpness: 0.5,
+      fringing: 0.0,
+      artifacts: 0.0,
+      bleed: 0.5,
+    }
+  }
+
+  pub fn rgb() -> Self {
+    NtscSetup {
+      sharpness: 1.0,
+      fringing: 0.0,
+      artifacts: 0.0,
+      bleed: 0.0,
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "composite" => Some(NtscSetup::composite()),
+      "svideo" => Some(NtscSetup::svideo()),
+      "rgb" => Some(NtscSetup::rgb()),
+      _ => None,
+    }
+  }
+}
+
+// encodes the pixels of a line into the composite signal and decodes it back
+#[derive(Debug)]
+pub struct NtscFilter {
+  setup: NtscSetup,
+  palette: NtscPalette,
+  signals: Vec<[f32; PHASES]>, // of the 512 pixels
+  lumas: Vec<f32>,             // the average of the signals
+}
+
+// sums of the samples before each index
+fn prefix_sums(samples: &[f32]) -> Vec<f32> {
+  let mut sums = Vec::with_capacity(samples.len() + 1);
+  let mut sum = 0.0;
+  sums.push(sum);
+  for sample in samples {
+    sum += sample;
+    sums.push(sum);
+  }
+  sums
+}
+
+// the average of width samples around center
+fn average(sums: &[f32], center: usize, width: usize) -> f32 {
+  let len = sums.len() - 1;
+  let start = (center + width / 2).saturating_sub(width).min(len);
+  let end = (start + width).min(len);
+  (sums[end] - sums[start]) / (end - start) as f32
+}
+
+impl NtscFilter {
+  pub fn new(setup: NtscSetup, palette: NtscPalette) -> Self {
+    let mut signals = vec![[0.0; PHASES]; 512];
+    for (pixel, wave) in signals.iter_mut().enumerate() {
+      for (phase, level) in wave.iter_mut().enumerate() {
+        *level = signal(pixel, phase);
+      }
+    }
+    let lumas = signals.iter().map(|wave| wave.iter().sum::<f32>() / PHASES as f32).collect();
+    NtscFilter {
+      setup,
+      palette,
+      signals,
+      lumas,
+    }
+  }
+
+  // the phase of the first sample of a line, it crawls each frame
+  pub fn line_phase(line: usize, is_odd_frame: bool) -> usize {
+    let frame_phase = if is_odd_frame { LINE_PHASE_STEP } else { 0 };
+    (frame_phase + line * LINE_PHASE_STEP) % PHASES
+  }
+
+  // pixels: a whole line, writes NTSC_SCALE * width RGBA pixels from the left pixel
+  pub fn filter_line(&self, pixels: &[Word], phase: usize, left: usize, width: usize, out: &mut [Data]) {
+    let len = pixels.len() * SAMPLES_PER_PIXEL;
+    let mut lumas = Vec::with_capacity(len);
+    let mut chromas_i = Vec::with_capacity(len);
+    let mut chromas_q = Vec::with_capacity(len);
+    for k in 0..len {
+      let pixel = (pixels[k / SAMPLES_PER_PIXEL] & 0x1FF) as usize;
+      let sample_phase = (phase + k) % PHASES;
+      let level = self.signals[pixel][sample_phase];
+      let luma = self.lumas[pixel];
+      lumas.push(luma + self.setup.fringing * (level - luma));
+      let chroma = level - (1.0 - self.setup.artifacts) * luma;
+      let (cos, sin) = self.palette.carrier(sample_phase);
+      chromas_i.push(chroma * cos);
+      chromas_q.push(chroma * sin);
+    }
+    let lumas = prefix_sums(&lumas);
+    let chromas_i = prefix_sums(&chromas_i);
+    let chromas_q = prefix_sums(&chromas_q);
+    let chroma_width = PHASES * (1 + (self.setup.bleed * 2.0).round() as usize);
+    for x in 0..width * NTSC_SCALE {
+      let center = left * SAMPLES_PER_PIXEL + x * SAMPLES_PER_OUTPUT + SAMPLES_PER_OUTPUT / 2;
+      let soft = average(&lumas, center, PHASES);
+      let sharp = average(&lumas, center, SAMPLES_PER_OUTPUT);
+      let y = soft + self.setup.sharpness * (sharp - soft);
+      let i = average(&chromas_i, center, chroma_width);
+      let q = average(&chromas_q, center, chroma_width);
+      let (r, g, b) = self.palette.rgb(y, i, q);
+      out[x * 4] = r;
+      out[x * 4 + 1] = g;
+      out[x * 4 + 2] = b;
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn filter(setup: NtscSetup, pixels: &[Word], phase: usize) -> Vec<(u8, u8, u8)> {
+    let filter = NtscFilter::new(setup, NtscPalette::default());
+    let mut out = vec![0; pixels.len() * NTSC_SCALE * 4];
+    filter.filter_line(pixels, phase, 0, pixels.len(), &mut out);
+    out.chunks(4).map(|c| (c[0], c[1], c[2])).collect()
+  }
+
+  fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> i32 {
+    (a.0 as i32 - b.0 as i32).abs() + (a.1 as i32 - b.1 as i32).abs() + (a.2 as i32 - b.2 as i32).abs()
+  }
+
+  #[test]
+  fn test_flat_color() {
+    // a flat field decodes to the generated palette
+    let colors = NtscPalette::default().generate();
+    for setup in [NtscSetup::composite(), NtscSetup::svideo(), NtscSetup::rgb()].iter() {
+      let out = filter(*setup, &[0x16; 256], 0);
+      assert_eq!(out.len(), 512);
+      assert!(out[16..496].iter().all(|c| distance(*c, colors[0x16]) <= 3));
+    }
+  }
+
+  #[test]
+  fn test_dither_and_artifacts() {
+    // white and black columns
+    let pixels: Vec<Word> = (0..256).map(|x| if x % 2 == 0 { 0x30 } else { 0x0F }).collect();
+    let spread = |out: &[(u8, u8, u8)]| {
+      let lumas: Vec<i32> = out[64..448].iter().map(|c| c.0 as i32 + c.1 as i32 + c.2 as i32).collect();
+      lumas.iter().max().unwrap() - lumas.iter().min().unwrap()
+    };
+    let composite = filter(NtscSetup::composite(), &pixels, 0);
+    let rgb = filter(NtscSetup::rgb(), &pixels, 0);
+    // blended into a flat tone on composite
+    assert!(spread(&composite) < spread(&rgb));
+    // the luma edges become colors on composite only
+    let is_colored = |c: &(u8, u8, u8)| distance(*c, (c.0, c.0, c.0)) > 24;
+    assert!(composite[64..448].iter().any(is_colored));
+    assert!(!filter(NtscSetup::svideo(), &pixels, 0)[64..448].iter().any(is_colored));
+    // dot crawl: another phase, other artifacts
+    assert_ne!(composite, filter(NtscSetup::composite(), &pixels, 4));
+  }
+
+  #[test]
+  fn test_line_phase() {
+    assert_eq!(NtscFilter::line_phase(0, false), 0);
+    assert_eq!(NtscFilter::line_phase(1, false), 4);
+    assert_eq!(NtscFilter::line_phase(3, false), 0);
+    assert_eq!(NtscFilter::line_phase(0, true), 4);
+    assert_eq!(NtscSetup::from_name("svideo"), Some(NtscSetup::svideo()));
+    assert_eq!(NtscSetup::from_name("vga"), None);
+  }
+}
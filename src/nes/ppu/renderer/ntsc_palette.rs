@@ -0,0 +1,142 @@
+use std::f32::consts::PI;
+
+// ref. https://www.nesdev.org/wiki/NTSC_video
+// the composite levels of the 2C02 in volts, the low then the high of each row
+const LEVELS: [f32; 8] = [0.350, 0.518, 0.962, 1.550, 1.094, 1.506, 1.962, 1.962];
+const BLACK: f32 = 0.518;
+const WHITE: f32 = 1.962;
+// the emphasis bits pull the signal down during their phases
+const ATTENUATION: f32 = 0.746;
+// phases from the color burst to the decoder's reference
+const BURST_PHASE: f32 = 3.9;
+
+// the controls of a TV, the default is the plain decoded signal
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NtscPalette {
+  pub hue: f32,        // degrees
+  pub saturation: f32,
+  pub contrast: f32,
+  pub brightness: f32, // added to the luma
+  pub gamma: f32,      // of the emulated CRT against 2.2 of the display
+}
+
+impl Default for NtscPalette {
+  fn default() -> Self {
+    NtscPalette {
+      hue: 0.0,
+      saturation: 1.0,
+      contrast: 1.0,
+      brightness: 0.0,
+      gamma: 2.2,
+    }
+  }
+}
+
+// the square wave of a color is high for 6 of the 12 phases
+fn is_in_color_phase(color: usize, phase: usize) -> bool {
+  (color + phase) % 12 < 6
+}
+
+// the composite signal of a pixel (emphasis << 6 | color id) at a phase (0 - 11),
+// 0 at black and 1 at white
+pub fn signal(pixel: usize, phase: usize) -> f32 {
+  let color = pixel & 0x0F;
+  let emphasis = pixel >> 6;
+  // $xE and $xF are black
+  let level = if color > 0x0D { 1 } else { (pixel >> 4) & 0x03 };
+  let low = LEVELS[level + if color == 0x00 { 4 } else { 0 }];
+  let high = LEVELS[level + if color < 0x0D { 4 } else { 0 }];
+  let mut signal = if is_in_color_phase(color, phase) { high } else { low };
+  let is_attenuated = (emphasis & 0x01 != 0 && is_in_color_phase(0, phase))
+    || (emphasis & 0x02 != 0 && is_in_color_phase(4, phase))
+    || (emphasis & 0x04 != 0 && is_in_color_phase(8, phase));
+  if is_attenuated {
+    signal *= ATTENUATION;
+  }
+  (signal - BLACK) / (WHITE - BLACK)
+}
+
+impl NtscPalette {
+  // 512 colors indexed by emphasis << 6 | color id
+  pub fn generate(&self) -> Vec<(u8, u8, u8)> {
+    (0..512).map(|pixel| self.color(pixel)).collect()
+  }
+
+  fn color(&self, pixel: usize) -> (u8, u8, u8) {
+    let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+    for phase in 0..12 {
+      let v = signal(pixel, phase) / 12.0;
+      let (cos, sin) = self.carrier(phase);
+      y += v;
+      i += v * cos;
+      q += v * sin;
+    }
+    self.rgb(y, i, q)
+  }
+
+  // the subcarrier the decoder multiplies at a phase, (cos, sin)
+  pub fn carrier(&self, phase: usize) -> (f32, f32) {
+    let angle = PI * (phase as f32 + BURST_PHASE) / 6.0 + self.hue.to_radians();
+    (angle.cos(), angle.sin())
+  }
+
+  // the decoded YIQ through the controls
+  pub fn rgb(&self, y: f32, i: f32, q: f32) -> (u8, u8, u8) {
+    let y = y * self.contrast + self.brightness;
+    let i = i * self.contrast * self.saturation;
+    let q = q * self.contrast * self.saturation;
+    // FCC YIQ to RGB
+    let r = y + 0.946882 * i + 0.623557 * q;
+    let g = y - 0.274788 * i - 0.635691 * q;
+    let b = y - 1.108545 * i + 1.709007 * q;
+    (self.gamma_byte(r), self.gamma_byte(g), self.gamma_byte(b))
+  }
+
+  fn gamma_byte(&self, value: f32) -> u8 {
+    let value = value.max(0.0).powf(2.2 / self.gamma);
+    (value * 255.0).round().min(255.0) as u8
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_generate() {
+    let colors = NtscPalette::default().generate();
+    assert_eq!(colors.len(), 512);
+    assert_eq!(colors[0x30], (0xFF, 0xFF, 0xFF));
+    assert_eq!(colors[0x0F], (0x00, 0x00, 0x00));
+    assert_eq!(colors[0x1D], (0x00, 0x00, 0x00));
+    // the hues: $x1 blue, $x6 red, $xA green
+    let (r, g, b) = colors[0x11];
+    assert!(b > r && b > g);
+    let (r, g, b) = colors[0x16];
+    assert!(r > g && r > b);
+    let (r, g, b) = colors[0x1A];
+    assert!(g > r && g > b);
+    // gray has no chroma
+    let (r, g, b) = colors[0x10];
+    assert!(r == g && g == b);
+    // red emphasis darkens white but keeps red the strongest
+    let (r, g, b) = colors[0x040 | 0x30];
+    assert!(r < 0xFF && r > g && r > b);
+  }
+
+  #[test]
+  fn test_controls() {
+    let base = NtscPalette::default().generate();
+    let gray = NtscPalette { saturation: 0.0, ..NtscPalette::default() }.generate();
+    let (r, g, b) = gray[0x16];
+    assert!(r == g && g == b);
+    let bright = NtscPalette { brightness: 0.1, ..NtscPalette::default() }.generate();
+    assert!(bright[0x10].0 > base[0x10].0);
+    // 180 degrees turns red into cyan
+    let rotated = NtscPalette { hue: 180.0, ..NtscPalette::default() }.generate();
+    let (r, g, b) = rotated[0x16];
+    assert!(r < g && r < b);
+    let dark = NtscPalette { gamma: 1.8, ..NtscPalette::default() }.generate();
+    assert!(dark[0x10].0 < base[0x10].0);
+  }
+}
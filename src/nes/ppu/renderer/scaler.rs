@@ -0,0 +1,362 @@
+use super::super::super::types::Data;
+
+type Rgba = [Data; 4];
+
+// pixel art upscalers on RGBA frames
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scaler {
+  Nearest(usize),
+  Scale2x,
+  Scale3x,
+  Hq2x,
+  Xbr(usize), // 2 - 4
+}
+
+impl Scaler {
+  // "nearest3x", "scale2x", "hq2x", "xbr2x"
+  pub fn from_name(name: &str) -> Option<Self> {
+    let scaler = match name {
+      "scale2x" => Scaler::Scale2x,
+      "scale3x" => Scaler::Scale3x,
+      "hq2x" => Scaler::Hq2x,
+      _ => {
+        let factor = name.strip_suffix('x')?.chars().last()?.to_digit(10)? as usize;
+        if !(2..=4).contains(&factor) {
+          return None;
+        }
+        match &name[..name.len() - 2] {
+          "nearest" => Scaler::Nearest(factor),
+          "xbr" => Scaler::Xbr(factor),
+          _ => return None,
+        }
+      }
+    };
+    Some(scaler)
+  }
+
+  pub fn factor(self) -> usize {
+    match self {
+      Scaler::Scale2x | Scaler::Hq2x => 2,
+      Scaler::Scale3x => 3,
+      Scaler::Nearest(factor) | Scaler::Xbr(factor) => factor,
+    }
+  }
+
+  // returns the scaled frame with its width and height
+  pub fn scale(self, rgba: &[Data], width: usize, height: usize) -> (Vec<Data>, usize, usize) {
+    let src = Frame { rgba, width, height };
+    let n = self.factor();
+    let mut out = vec![0; width * n * height * n * 4];
+    let mut block = vec![[0; 4]; n * n];
+    for y in 0..height {
+      for x in 0..width {
+        match self {
+          Scaler::Nearest(_) => block.iter_mut().for_each(|p| *p = src.get(x, y, 0, 0)),
+          Scaler::Scale2x => scale2x(&src, x, y, &mut block),
+          Scaler::Scale3x => scale3x(&src, x, y, &mut block),
+          Scaler::Hq2x => hq2x(&src, x, y, &mut block),
+          Scaler::Xbr(_) => xbr(&src, x, y, n, &mut block),
+        }
+        for (i, pixel) in block.iter().enumerate() {
+          let (sx, sy) = (i % n, i / n);
+          let offset = ((y * n + sy) * width * n + x * n + sx) * 4;
+          out[offset..offset + 4].copy_from_slice(pixel);
+        }
+      }
+    }
+    (out, width * n, height * n)
+  }
+}
+
+struct Frame<'a> {
+  rgba: &'a [Data],
+  width: usize,
+  height: usize,
+}
+
+impl<'a> Frame<'a> {
+  // the neighbor at (dx, dy) from (x, y), the edges repeat
+  fn get(&self, x: usize, y: usize, dx: isize, dy: isize) -> Rgba {
+    let x = (x as isize + dx).clamp(0, self.width as isize - 1) as usize;
+    let y = (y as isize + dy).clamp(0, self.height as isize - 1) as usize;
+    let i = (y * self.width + x) * 4;
+    [self.rgba[i], self.rgba[i + 1], self.rgba[i + 2], self.rgba[i + 3]]
+  }
+}
+
+// ref. https://www.scale2x.it/algorithm
+fn scale2x(src: &Frame, x: usize, y: usize, block: &mut [Rgba]) {
+  let (b, d, e, f, h) = (src.get(x, y, 0, -1), src.get(x, y, -1, 0), src.get(x, y, 0, 0), src.get(x, y, 1, 0), src.get(x, y, 0, 1));
+  if b == h || d == f {
+    block.iter_mut().for_each(|p| *p = e);
+    return;
+  }
+  block[0] = if d == b { d } else { e };
+  block[1] = if b == f { f } else { e };
+  block[2] = if d == h { d } else { e };
+  block[3] = if h == f { f } else { e };
+}
+
+fn scale3x(src: &Frame, x: usize, y: usize, block: &mut [Rgba]) {
+  let p = |dx, dy| src.get(x, y, dx, dy);
+  let (a, b, c) = (p(-1, -1), p(0, -1), p(1, -1));
+  let (d, e, f) = (p(-1, 0), p(0, 0), p(1, 0));
+  let (g, h, i) = (p(-1, 1), p(0, 1), p(1, 1));
+  if b == h || d == f {
+    block.iter_mut().for_each(|p| *p = e);
+    return;
+  }
+  block[0] = if d == b { d } else { e };
+  block[1] = if (d == b && e != c) || (b == f && e != a) { b } else { e };
+  block[2] = if b == f { f } else { e };
+  block[3] = if (d == b && e != g) || (d == h && e != a) { d } else { e };
+  block[4] = e;
+  block[5] = if (b == f && e != i) || (h == f && e != c) { f } else { e };
+  block[6] = if d == h { d } else { e };
+  block[7] = if (d == h && e != i) || (h == f && e != g) { h } else { e };
+  block[8] = if h == f { f } else { e };
+}
+
+// the integer YUV of hqx
+fn yuv(p: Rgba) -> (i32, i32, i32) {
+  let (r, g, b) = (p[0] as i32, p[1] as i32, p[2] as i32);
+  ((r + g + b) >> 2, ((r - b) >> 2) + 128, ((2 * g - r - b) >> 3) + 128)
+}
+
+// hqx thresholds of Y, U and V
+fn is_different(a: Rgba, b: Rgba) -> bool {
+  let (ya, ua, va) = yuv(a);
+  let (yb, ub, vb) = yuv(b);
+  (ya - yb).abs() > 0x30 || (ua - ub).abs() > 0x07 || (va - vb).abs() > 0x06
+}
+
+fn blend(a: Rgba, b: Rgba, weight: f32) -> Rgba {
+  let mut p = [0; 4];
+  for (i, value) in p.iter_mut().enumerate() {
+    *value = (a[i] as f32 + (b[i] as f32 - a[i] as f32) * weight).round() as Data;
+  }
+  p
+}
+
+// (c1 * w1 + c2 * w2 + ...) >> shift on each channel, the weights add up to 1 << shift
+fn interpolate(colors: &[(Rgba, u32)], shift: u32) -> Rgba {
+  let mut p = [0; 4];
+  for (i, value) in p.iter_mut().enumerate() {
+    *value = (colors.iter().map(|(c, w)| c[i] as u32 * w).sum::<u32>() >> shift) as Data;
+  }
+  p
+}
+
+// the sub-pixel position from the center of its pixel, -0.5 - 0.5
+fn sub_position(s: usize, n: usize) -> f32 {
+  (s as f32 + 0.5) / n as f32 - 0.5
+}
+
+// ref. Maxim Stepin's hq2x, the 256 patterns folded into the rules of the top left pixel
+//      (as FFmpeg's vf_hqx does), the other 3 pixels mirror the 3x3 neighbors
+fn hq2x(src: &Frame, x: usize, y: usize, block: &mut [Rgba]) {
+  let mut w = [[0; 4]; 9];
+  for (i, p) in w.iter_mut().enumerate() {
+    *p = src.get(x, y, (i % 3) as isize - 1, (i / 3) as isize - 1);
+  }
+  // a bit for each neighbor but the center that differs from it
+  let mut pattern = 0;
+  for (bit, i) in [0, 1, 2, 3, 5, 6, 7, 8].iter().enumerate() {
+    if is_different(w[4], w[*i]) {
+      pattern |= 1 << bit;
+    }
+  }
+  block[0] = hq2x_pixel(pattern, &w, [0, 1, 2, 3, 4, 5, 6, 7, 8]);
+  block[1] = hq2x_pixel(pattern, &w, [2, 1, 0, 5, 4, 3, 8, 7, 6]);
+  block[2] = hq2x_pixel(pattern, &w, [6, 7, 8, 3, 4, 5, 0, 1, 2]);
+  block[3] = hq2x_pixel(pattern, &w, [8, 7, 6, 5, 4, 3, 2, 1, 0]);
+}
+
+// the top left pixel with the neighbors w[p[0]] - w[p[8]], p mirrors the other pixels to it
+fn hq2x_pixel(pattern: Data, w: &[Rgba; 9], p: [usize; 9]) -> Rgba {
+  // the 8 neighbors are bits 0 - 7 without the center
+  let bit = |i: usize| if i > 4 { i - 1 } else { i };
+  let mut k = 0;
+  for n in [0, 1, 2, 3, 5, 6, 7, 8].iter() {
+    k |= ((pattern >> bit(*n)) & 1) << bit(p[*n]);
+  }
+  // the bits of mask in the mirrored pattern are result
+  let is = |patterns: &[(Data, Data)]| patterns.iter().any(|&(mask, result)| k & mask == result);
+  let (w0, w1, w3, w4, w5, w7) = (w[p[0]], w[p[1]], w[p[3]], w[p[4]], w[p[5]], w[p[7]]);
+
+  if is(&[(0xBF, 0x37), (0xDB, 0x13)]) && is_different(w1, w5) {
+    return interpolate(&[(w4, 3), (w3, 1)], 2);
+  }
+  if is(&[(0xDB, 0x49), (0xEF, 0x6D)]) && is_different(w7, w3) {
+    return interpolate(&[(w4, 3), (w1, 1)], 2);
+  }
+  if is(&[(0x0B, 0x0B), (0xFE, 0x4A), (0xFE, 0x1A)]) && is_different(w3, w1) {
+    return w4;
+  }
+  if is(&[
+    (0x6F, 0x2A), (0x5B, 0x0A), (0xBF, 0x3A), (0xDF, 0x5A), (0x9F, 0x8A), (0xCF, 0x8A), (0xEF, 0x4E),
+    (0x3F, 0x0E), (0xFB, 0x5A), (0xBB, 0x8A), (0x7F, 0x5A), (0xAF, 0x8A), (0xEB, 0x8A),
+  ]) && is_different(w3, w1) {
+    return interpolate(&[(w4, 3), (w0, 1)], 2);
+  }
+  if is(&[(0x0B, 0x08)]) {
+    return interpolate(&[(w4, 2), (w0, 1), (w1, 1)], 2);
+  }
+  if is(&[(0x0B, 0x02)]) {
+    return interpolate(&[(w4, 2), (w0, 1), (w3, 1)], 2);
+  }
+  if is(&[(0x2F, 0x2F)]) {
+    return interpolate(&[(w4, 14), (w3, 1), (w1, 1)], 4);
+  }
+  if is(&[(0xBF, 0x37), (0xDB, 0x13)]) {
+    return interpolate(&[(w4, 5), (w1, 2), (w3, 1)], 3);
+  }
+  if is(&[(0xDB, 0x49), (0xEF, 0x6D)]) {
+    return interpolate(&[(w4, 5), (w3, 2), (w1, 1)], 3);
+  }
+  if is(&[(0x1B, 0x03), (0x4F, 0x43), (0x8B, 0x83), (0x6B, 0x43)]) {
+    return interpolate(&[(w4, 3), (w3, 1)], 2);
+  }
+  if is(&[(0x4B, 0x09), (0x8B, 0x89), (0x1F, 0x19), (0x3B, 0x19)]) {
+    return interpolate(&[(w4, 3), (w1, 1)], 2);
+  }
+  if is(&[(0x7E, 0x2A), (0xEF, 0xAB), (0xBF, 0x8F), (0x7E, 0x0E)]) {
+    return interpolate(&[(w4, 2), (w3, 3), (w1, 3)], 3);
+  }
+  if is(&[(0xFB, 0x6A), (0x6F, 0x6E), (0x3F, 0x3E), (0xFB, 0xFA), (0xDF, 0xDE), (0xDF, 0x1E)]) {
+    return interpolate(&[(w4, 3), (w0, 1)], 2);
+  }
+  if is(&[(0x0A, 0x00), (0x4F, 0x4B), (0x9F, 0x1B), (0x2F, 0x0B), (0xBE, 0x0A), (0xEE, 0x0A), (0x7E, 0x0A), (0xEB, 0x4B), (0x3B, 0x1B)]) {
+    return interpolate(&[(w4, 2), (w3, 1), (w1, 1)], 2);
+  }
+  interpolate(&[(w4, 6), (w3, 1), (w1, 1)], 3)
+}
+
+// xBR color distance
+fn distance(a: Rgba, b: Rgba) -> i32 {
+  let (ya, ua, va) = yuv(a);
+  let (yb, ub, vb) = yuv(b);
+  (ya - yb).abs() * 48 + (ua - ub).abs() * 7 + (va - vb).abs() * 6
+}
+
+// ref. Hyllian's xBR, level 1 rules with 5x5 neighbors
+// the edge rule of each corner, the new color covers the corner beyond the edge line
+fn xbr(src: &Frame, x: usize, y: usize, n: usize, block: &mut [Rgba]) {
+  let e = src.get(x, y, 0, 0);
+  block.iter_mut().for_each(|p| *p = e);
+  for &(dx, dy) in [(1, 1), (-1, 1), (1, -1), (-1, -1)].iter() {
+    // the bottom right corner mirrored to (dx, dy)
+    let p = |u: isize, v: isize| src.get(x, y, u * dx, v * dy);
+    let (b, c, d, f, g, h, i) = (p(0, -1), p(1, -1), p(-1, 0), p(1, 0), p(-1, 1), p(0, 1), p(1, 1));
+    let (f4, h5, i4, i5) = (p(2, 0), p(0, 2), p(2, 1), p(1, 2));
+    if e == f || e == h {
+      continue;
+    }
+    let edge = distance(e, c) + distance(e, g) + distance(i, f4) + distance(i, h5) + 4 * distance(h, f);
+    let across = distance(h, d) + distance(h, i5) + distance(f, i4) + distance(f, b) + 4 * distance(e, i);
+    if edge >= across {
+      continue;
+    }
+    let color = if distance(e, f) <= distance(e, h) { f } else { h };
+    for (index, pixel) in block.iter_mut().enumerate() {
+      let u = sub_position(index % n, n) * dx as f32;
+      let v = sub_position(index / n, n) * dy as f32;
+      let coverage = ((u + v - 0.5) * n as f32 + 0.5).clamp(0.0, 1.0);
+      if coverage > 0.0 {
+        *pixel = blend(*pixel, color, coverage);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  const W: Rgba = [0xFF, 0xFF, 0xFF, 0xFF];
+  const K: Rgba = [0x00, 0x00, 0x00, 0xFF];
+
+  fn frame(pixels: &[Rgba]) -> Vec<Data> {
+    pixels.iter().flat_map(|p| p.iter().copied()).collect()
+  }
+
+  fn at(rgba: &[Data], width: usize, x: usize, y: usize) -> Rgba {
+    let i = (y * width + x) * 4;
+    [rgba[i], rgba[i + 1], rgba[i + 2], rgba[i + 3]]
+  }
+
+  // a white diagonal on black, 3x3
+  fn diagonal() -> Vec<Data> {
+    frame(&[W, K, K, K, W, K, K, K, W])
+  }
+
+  #[test]
+  fn test_from_name() {
+    assert_eq!(Scaler::from_name("nearest3x"), Some(Scaler::Nearest(3)));
+    assert_eq!(Scaler::from_name("scale2x"), Some(Scaler::Scale2x));
+    assert_eq!(Scaler::from_name("hq2x"), Some(Scaler::Hq2x));
+    assert_eq!(Scaler::from_name("xbr2x"), Some(Scaler::Xbr(2)));
+    assert_eq!(Scaler::from_name("xbr5x"), None);
+    assert_eq!(Scaler::from_name("hq3x"), None);
+    assert_eq!(Scaler::from_name("scale4x"), None);
+    assert_eq!(Scaler::from_name("bilinear2x"), None);
+  }
+
+  #[test]
+  fn test_nearest_and_scale2x() {
+    let (out, width, height) = Scaler::Nearest(2).scale(&diagonal(), 3, 3);
+    assert_eq!((width, height), (6, 6));
+    assert_eq!(at(&out, 6, 1, 1), W);
+    assert_eq!(at(&out, 6, 2, 1), K);
+    let (out, width, _) = Scaler::Scale2x.scale(&diagonal(), 3, 3);
+    // the staircase is smoothed: the corners next to the diagonal are filled
+    assert_eq!(at(&out, width, 3, 2), W);
+    assert_eq!(at(&out, width, 2, 3), W);
+    assert_eq!(at(&out, width, 4, 2), K);
+    // a flat frame stays flat
+    let (out, _, _) = Scaler::Scale3x.scale(&frame(&[K; 4]), 2, 2);
+    assert!(out.chunks(4).all(|p| p == K));
+  }
+
+  #[test]
+  fn test_scale3x() {
+    let (out, width, height) = Scaler::Scale3x.scale(&diagonal(), 3, 3);
+    assert_eq!((width, height), (9, 9));
+    assert_eq!(at(&out, width, 4, 4), W);
+    assert_eq!(at(&out, width, 5, 3), W);
+    assert_eq!(at(&out, width, 3, 5), W);
+    assert_eq!(at(&out, width, 6, 3), K);
+  }
+
+  #[test]
+  fn test_hq2x() {
+    let (out, width, height) = Scaler::Hq2x.scale(&diagonal(), 3, 3);
+    assert_eq!((width, height), (6, 6));
+    // the diagonal goes on through the top left of the center, its top right is (2 W + 2 K) / 4
+    assert_eq!(at(&out, width, 2, 2), W);
+    assert_eq!(at(&out, width, 3, 2), [0x7F, 0x7F, 0x7F, 0xFF]);
+    assert_eq!(at(&out, width, 3, 3), W);
+    // a lone pixel is (14 W + 2 K) / 16 everywhere
+    let (out, _, _) = Scaler::Hq2x.scale(&frame(&[K, K, K, K, W, K, K, K, K]), 3, 3);
+    assert!((2..4).all(|y| (2..4).all(|x| at(&out, 6, x, y) == [0xDF, 0xDF, 0xDF, 0xFF])));
+    let (out, _, _) = Scaler::Hq2x.scale(&frame(&[W; 4]), 2, 2);
+    assert!(out.chunks(4).all(|p| p == W));
+  }
+
+  #[test]
+  fn test_xbr() {
+    for scaler in [Scaler::Xbr(2), Scaler::Xbr(3), Scaler::Xbr(4)].iter() {
+      let n = scaler.factor();
+      let (out, width, height) = scaler.scale(&diagonal(), 3, 3);
+      assert_eq!((width, height), (3 * n, 3 * n));
+      // the black pixel right of the center is lit at its bottom left, by the diagonal
+      assert!(at(&out, width, 2 * n, 2 * n - 1)[0] > 0);
+      assert_eq!(at(&out, width, 2 * n + 1, n), K);
+      // and stays black at the far corner
+      assert_eq!(at(&out, width, 3 * n - 1, 0), K);
+      // a flat frame stays flat
+      let (out, _, _) = scaler.scale(&frame(&[W; 4]), 2, 2);
+      assert!(out.chunks(4).all(|p| p == W));
+    }
+  }
+}
@@ -1,5 +1,3 @@
-use self::super::palette::*;
-use self::super::sprite_utils::*;
 use self::super::Ram;
 use super::super::types::{Data, Addr};
 use super::Mapper;
@@ -7,48 +5,177 @@ use super::Mapper;
 // 256 bytes
 const OAM_RAM_CAPACITY: u16 = 0x100;
 
-pub type SpritesWithCtx = Vec<SpriteWithCtx>;
+// a sprite on the next line with its pattern row
+#[derive(Debug, Clone, Copy)]
+pub struct LineSprite {
+  pub index: Data, // in OAM, 0 for the sprite 0 hit
+  pub x: Data,
+  pub attr: Data,
+  low: Data,
+  high: Data,
+}
+
+impl LineSprite {
+  pub fn is_behind_background(&self) -> bool {
+    self.attr & 0x20 == 0x20
+  }
 
+  // palette id << 2 | pattern at the screen x, 0 when out of the sprite
+  pub fn pixel(&self, x: usize) -> Data {
+    let offset = x.wrapping_sub(self.x as usize);
+    if offset >= 8 {
+      return 0;
+    }
+    let shift = 7 - offset;
+    let pattern = ((self.high >> shift) & 0x01) << 1 | ((self.low >> shift) & 0x01);
+    if pattern == 0 {
+      return 0;
+    }
+    (self.attr & 0x03) << 2 | pattern
+  }
+}
+
+// up to 8 sprites a line
+const SPRITES_PER_LINE: usize = 8;
+
+// the sprites drawn on a line and the $2002 sprite overflow
 #[derive(Debug)]
-pub struct SpriteWithCtx {
-  pub sprite: Sprite,
-  pub position: SpritePosition,
-  pub attr: Data,
-  pub palette: PaletteList,
+pub struct LineSprites {
+  pub sprites: Vec<LineSprite>,
+  pub is_overflow: bool,
+}
+
+// ref. http://wiki.nesdev.com/w/index.php/PPU_sprite_evaluation
+// sprites in range of the line in OAM order, evaluated at dot 257 of the previous line.
+// is_unlimited draws the sprites after the 8th too, unless the 8 are there to mask them
+pub fn evaluate_sprites(cram: &Ram, oam_ram: &Ram, line: usize, offset: Addr, is_8x8: bool, is_unlimited: bool, mapper: &dyn Mapper) -> LineSprites {
+  let height = if is_8x8 { 8 } else { 16 };
+  // the sprite is drawn from the next line of y
+  let is_in_range = |y: Data| line.wrapping_sub(y as usize) < height;
+  let mut in_range = Vec::new();
+  let mut n = 0;
+  while n < OAM_RAM_CAPACITY / 4 && in_range.len() < SPRITES_PER_LINE {
+    if is_in_range(oam_ram.read(n * 4)) {
+      in_range.push(n);
+    }
+    n += 1;
+  }
+  // the hardware bug: m is incremented with n after 8 sprites, so other bytes are read as y
+  let mut is_overflow = false;
+  let mut m = 0;
+  while n < OAM_RAM_CAPACITY / 4 {
+    if is_in_range(oam_ram.read(n * 4 + m)) {
+      is_overflow = true;
+      break;
+    }
+    n += 1;
+    m = (m + 1) & 0x03;
+  }
+  if is_unlimited && !is_masking(oam_ram, &in_range) {
+    let last = in_range.last().map_or(0, |n| n + 1);
+    in_range.extend((last..OAM_RAM_CAPACITY / 4).filter(|n| is_in_range(oam_ram.read(n * 4))));
+  }
+  let sprites = in_range
+    .into_iter()
+    .map(|n| fetch_sprite(cram, oam_ram, n * 4, line, offset, height, mapper))
+    .collect();
+  LineSprites { sprites, is_overflow }
+}
+
+// 8 sprites of the same tile, e.g. blank ones hiding the sprites after them
+fn is_masking(oam_ram: &Ram, in_range: &[Addr]) -> bool {
+  in_range.len() == SPRITES_PER_LINE && in_range.iter().all(|n| oam_ram.read(n * 4 + 1) == oam_ram.read(in_range[0] * 4 + 1))
 }
 
-// make all registered from oam
-pub fn build_sprites<P: PaletteRam>(cram: &Ram, oam_ram: &Ram, palette: &P, offset: Addr, is_8x8: bool, mapper: &dyn Mapper) ->SpritesWithCtx {
-  let mut buf: SpritesWithCtx = vec![];
-  for i in 0..(OAM_RAM_CAPACITY / 4){
-     // INFO: Offset sprite Y position, because First and last 8line is not rendered.
-    let base = i * 4;
-    let y = oam_ram.read(base);
-    if 8 <= y && y < 244 {
-      let sprite_id = oam_ram.read(base + 1);
-      let attr = oam_ram.read(base + 2);
-      let (offset, sprite_id) = if is_8x8 {
-        (offset, sprite_id)
-      } else {
-        // 76543210
-        // ||||||||
-        // |||||||+- Bank ($0000 or $1000) of tiles
-        // +++++++-- Tile number of top of sprite (0 to 254; bottom half gets the next tile)
-        let offset = 0x1000u16 * (sprite_id & 0x01) as u16;
-        let sprite_id = sprite_id & 0xFE;
-        (offset, sprite_id)
-      };
-      let x = oam_ram.read(base + 3);
-      let sprite = build(&cram, sprite_id as Data, offset, is_8x8, mapper);
-      let position: SpritePosition = (x, y - 8);
-      let palette_id = attr & 0x03;
-      buf.push(SpriteWithCtx {
-        sprite,
-        position,
-        attr,
-        palette: palette.get(palette_id, PaletteType::Sprite),
-      });
+fn fetch_sprite(cram: &Ram, oam_ram: &Ram, base: Addr, line: usize, offset: Addr, height: usize, mapper: &dyn Mapper) -> LineSprite {
+  let row = line.wrapping_sub(oam_ram.read(base) as usize);
+  let sprite_id = oam_ram.read(base + 1);
+  let attr = oam_ram.read(base + 2);
+  let row = if attr & 0x80 == 0x80 { height - 1 - row } else { row };
+  let (offset, sprite_id) = if height == 8 {
+    (offset, sprite_id)
+  } else {
+    // 76543210
+    // ||||||||
+    // |||||||+- Bank ($0000 or $1000) of tiles
+    // +++++++-- Tile number of top of sprite (0 to 254; bottom half gets the next tile)
+    (0x1000u16 * (sprite_id & 0x01) as u16, (sprite_id & 0xFE) + (row / 8) as Data)
+  };
+  let addr = offset + sprite_id as Addr * 16 + (row % 8) as Addr;
+  let mut low = cram.read(mapper.get_cram_index(addr));
+  let mut high = cram.read(mapper.get_cram_index(addr + 8));
+  if attr & 0x40 == 0x40 {
+    low = low.reverse_bits();
+    high = high.reverse_bits();
+  }
+  LineSprite {
+    index: (base / 4) as Data,
+    x: oam_ram.read(base + 3),
+    attr,
+    low,
+    high,
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use super::super::super::mapper::Mapper0;
+
+  #[test]
+  fn test_evaluate_sprites() {
+    let mut cram = Ram::new(vec![0; 0x2000]);
+    cram.write(0x0012, 0xF0); // tile 1, row 2, low plane
+    cram.write(0x001A, 0x0F); // high plane
+    let mut oam = Ram::new(vec![0xFF; 0x100]);
+    for (i, data) in [9, 1, 0x42, 100].iter().enumerate() {
+      oam.write(i as Addr + 4, *data);
     }
+    let mapper = Mapper0::new();
+    let sprites = evaluate_sprites(&cram, &oam, 11, 0, true, false, &mapper).sprites;
+    assert_eq!(sprites.len(), 1);
+    let sprite = sprites[0];
+    // flipped horizontally: high plane on the left
+    assert_eq!(sprite.pixel(100), 0x0A);
+    assert_eq!(sprite.pixel(104), 0x09);
+    assert_eq!(sprite.pixel(99), 0);
+    assert_eq!(sprite.pixel(108), 0);
+    assert!(evaluate_sprites(&cram, &oam, 18, 0, true, false, &mapper).sprites.is_empty());
+    assert_eq!(evaluate_sprites(&cram, &oam, 18, 0, false, false, &mapper).sprites.len(), 1);
   }
-  buf
-}
\ No newline at end of file
+
+  #[test]
+  fn test_sprite_limit_and_overflow() {
+    let cram = Ram::new(vec![0; 0x2000]);
+    let mut oam = Ram::new(vec![0xFF; 0x100]);
+    let mapper = Mapper0::new();
+    // 9 sprites on line 20, different tiles
+    for i in 0..9 {
+      oam.write(i * 4, 20);
+      oam.write(i * 4 + 1, i as Data);
+      oam.write(i * 4 + 3, i as Data * 8);
+    }
+    let result = evaluate_sprites(&cram, &oam, 20, 0, true, false, &mapper);
+    assert_eq!(result.sprites.len(), 8);
+    assert!(result.is_overflow);
+    let result = evaluate_sprites(&cram, &oam, 20, 0, true, true, &mapper);
+    assert_eq!(result.sprites.len(), 9);
+    assert_eq!(result.sprites[8].x, 64);
+    assert_eq!(result.sprites[8].index, 8);
+    // masking with 8 sprites of the same tile
+    for i in 0..8 {
+      oam.write(i * 4 + 1, 0x80);
+    }
+    assert_eq!(evaluate_sprites(&cram, &oam, 20, 0, true, true, &mapper).sprites.len(), 8);
+    // the 9th is out of range, the bug reads the tile of sprite 9 (m = 1) as y
+    oam.write(8 * 4, 0xFF);
+    oam.write(9 * 4 + 1, 20);
+    let result = evaluate_sprites(&cram, &oam, 20, 0, true, false, &mapper);
+    assert!(result.is_overflow);
+    oam.write(9 * 4 + 1, 0xFF);
+    oam.write(9 * 4, 20);
+    let result = evaluate_sprites(&cram, &oam, 20, 0, true, false, &mapper);
+    // missed because m = 1 when n = 9
+    assert!(!result.is_overflow);
+  }
+}
@@ -1,8 +1,5 @@
 use super::super::types::{Data, Addr, Word};
 use super::super::Ram;
-use super::Mapper;
-
-pub type Sprite = Vec<Vec<Data>>;
 
 pub type SpritePosition = (Data, Data);
 
@@ -10,9 +7,7 @@ pub type SpritePosition = (Data, Data);
 pub struct SpriteConfig {
   pub offset_addr_by_name_table: Option<Word>,
   pub offset_addr_by_background_table: Word,
-  pub offset_addr_by_sprite_table: Word,
   pub is_horizontal_mirror: bool,
-  pub is_background_enable: bool,
 }
 
 pub fn get_block_id(pos: &SpritePosition) -> Data { // for BG
@@ -46,23 +41,6 @@ pub fn mirror_down_sprite_addr(addr: Addr, is_horizontal_mirror: bool) -> Addr {
   }
 }
 
-pub fn build(cram: &Ram, tile_id: Data, offset: Addr, is_8x8: bool, mapper: &dyn Mapper) -> Sprite {
-  let h = if is_8x8 {1} else {2};
-  let mut sprite: Sprite = (0..8 * h).into_iter().map(|_| vec![0; 8 * h]).collect();
-  for k in 0..h {
-    for i in 0..16 {
-      for j in 0..8 {
-        let addr = ((tile_id + (k as Data)) as Addr) * 16 + i + offset; // pattern table
-        let data = cram.read(mapper.get_cram_index(addr));
-        if data & (0x80 >> j) as Data != 0 {
-          sprite[((k as u16) * 8 + i % 8) as usize][j] += (0x01 << (i / 8)) as u8;
-        }
-      }
-    }
-  }
-  sprite
-}
-
 #[test]
 fn test_get_block_id() {
     let position = (2, 3);
@@ -77,9 +55,7 @@ fn test_get_tile_id() {
   let c = SpriteConfig {
     offset_addr_by_name_table: Some(0x400),
     offset_addr_by_background_table: 0,
-    offset_addr_by_sprite_table: 0,
     is_horizontal_mirror: false,
-    is_background_enable: true,
   };
   let pos = (2, 3);
   let id = get_tile_id(&mut v, &pos, &c);
@@ -93,9 +69,7 @@ fn test_get_attribute() {
   let c = SpriteConfig {
     offset_addr_by_name_table: Some(0x400),
     offset_addr_by_background_table: 0,
-    offset_addr_by_sprite_table: 0,
     is_horizontal_mirror: false,
-    is_background_enable: true,
   };
   let pos = (4, 3);
   let attr = get_attribute(&mut v, &pos, &c);
@@ -8,6 +8,10 @@ export default class SRAM {
     console.log('sram saved')
   }
 
+  has() {
+    return window.localStorage.getItem(this.filename) != null
+  }
+
   load() {
     let buf = 0
     if (window.localStorage.getItem(this.filename) == null) {
@@ -0,0 +1,52 @@
+// plays the pcm samples generated by the apu
+const BUFFER_SIZE = 1024
+const MAX_QUEUE = 4096 * 4
+const TARGET_QUEUE = BUFFER_SIZE * 2
+// the emulator slightly speeds up or slows down the output to keep the queue around the target
+const MAX_RATE_ADJUST = 0.005
+
+export default class Speaker {
+  constructor (sampleRate = 44100) {
+    try {
+      const AudioContext = window.AudioContext || window.webkitAudioContext
+      this.context = new AudioContext({ sampleRate })
+    } catch (e) {
+      throw new Error('Web Audio isn\'t supported in this browser!')
+    }
+    this.queue = []
+    this.processor = this.context.createScriptProcessor(BUFFER_SIZE, 0, 1)
+    this.processor.onaudioprocess = (e) => this.onAudioProcess(e)
+    this.processor.connect(this.context.destination)
+  }
+
+  // the rate the apu should resample to for the next frame
+  sampleRate () {
+    const diff = (TARGET_QUEUE - this.queue.length) / TARGET_QUEUE
+    const adjust = Math.max(-1, Math.min(1, diff)) * MAX_RATE_ADJUST
+    return this.context.sampleRate * (1 + adjust)
+  }
+
+  // samples: Int16Array
+  push (samples) {
+    if (this.queue.length > MAX_QUEUE) {
+      // too much latency, drop the old samples
+      this.queue = []
+    }
+    for (let i = 0; i < samples.length; i++) {
+      this.queue.push(samples[i] / 32768)
+    }
+  }
+
+  onAudioProcess (e) {
+    const data = e.outputBuffer.getChannelData(0)
+    const samples = this.queue.splice(0, data.length)
+    data.set(samples)
+    // underrun, hold the last level to avoid a click
+    data.fill(samples.length ? samples[samples.length - 1] : 0, samples.length)
+  }
+
+  close () {
+    this.processor.disconnect()
+    this.context.close()
+  }
+}